@@ -1,21 +1,32 @@
-use egg_node::chain::block::Block;
+use egg_node::chain::block::{Block, merkle_root};
 use egg_node::chain::header::BlockHeader;
-use egg_node::chain::genesis_block;
+use egg_node::chain::tx::Transaction;
+use egg_node::chain::params::{ChainParams, Network};
 use egg_node::chain::state::ChainState;
 use egg_node::storage::sleddb::ChainDB;
 use egg_node::pow::verify::verify_pow;
 use egg_node::chain::hash::hash_header;
+use egg_node::pow::retarget::next_bits;
 
-/// Mine một block HỢP LỆ PoW (brute-force nonce)
-fn mine_block(prev: [u8; 32], bits: u32) -> Block {
+/// Mine một block HỢP LỆ PoW (brute-force nonce), bits theo luật retarget
+fn mine_block(chain: &ChainState, prev: [u8; 32], timestamp: u64) -> Block {
+    let parent = chain.index.get(&prev).expect("parent not in index");
+    let bits = next_bits(&chain.params.pow, &chain.index, parent);
     let mut nonce: u64 = 0;
 
+    let coinbase = Transaction::coinbase(
+        b"fork-test".to_vec(),
+        0,
+        &format!("{} {:08x}", hex::encode(prev), bits),
+    );
+    let txs = vec![coinbase];
+
     loop {
         let header = BlockHeader {
             version: 1,
             prev_hash: prev,
-            merkle_root: [1u8; 32],
-            timestamp: 1,
+            merkle_root: merkle_root(&txs),
+            timestamp,
             bits,
             nonce,
         };
@@ -23,7 +34,7 @@ fn mine_block(prev: [u8; 32], bits: u32) -> Block {
         if verify_pow(&header) {
             return Block {
                 header,
-                transactions: vec![],
+                transactions: txs,
             };
         }
 
@@ -32,49 +43,63 @@ fn mine_block(prev: [u8; 32], bits: u32) -> Block {
 }
 
 fn main() {
-    // DB test riêng, không ảnh hưởng chain thật
-    let db = ChainDB::open("./fork-test-db");
+    // DB trong bộ nhớ, không ảnh hưởng chain thật
+    let db = ChainDB::open_temporary();
 
-    let genesis = genesis_block();
-    let mut chain = ChainState::load_or_init(genesis, db);
+    // regtest: difficulty cố định, fork thắng nhờ dài hơn
+    let params = ChainParams::new(Network::Regtest);
+    let mut chain = ChainState::load_or_init(params, db);
 
     let genesis_hash = chain.tip;
+    let t0 = chain.tip_entry().header.timestamp;
 
     // ===============================
     // Fork A và Fork B từ genesis
     // ===============================
 
-    // Fork A (work thấp)
-    let block_a = mine_block(genesis_hash, 0x1f00ffff);
+    let block_a = mine_block(&chain, genesis_hash, t0 + 1);
     let hash_a = hash_header(&block_a.header);
     chain.add_block(block_a);
 
-    // Fork B (work cao hơn)
-    let block_b = mine_block(genesis_hash, 0x1f00aaaa);
+    let block_b = mine_block(&chain, genesis_hash, t0 + 2);
     let hash_b = hash_header(&block_b.header);
     chain.add_block(block_b);
 
-    println!("Tip after A/B: {:?}", chain.tip);
+    // cùng work: giữ block đến trước
+    println!("Tip after A/B: {}", hex::encode(chain.tip));
+    assert_eq!(chain.tip, hash_a);
 
     // ===============================
-    // Kéo dài fork A (yếu hơn ban đầu)
+    // Kéo dài fork B: B dài hơn => reorg
     // ===============================
 
-    let block_c = mine_block(hash_a, 0x1f00ffff);
-    let _hash_c = hash_header(&block_c.header);
+    let block_c = mine_block(&chain, hash_b, t0 + 3);
+    let hash_c = hash_header(&block_c.header);
     chain.add_block(block_c);
 
-    println!("Tip after extend A: {:?}", chain.tip);
+    println!("Tip after extend B: {}", hex::encode(chain.tip));
+    assert_eq!(chain.tip, hash_c);
 
     // ===============================
-    // Kéo dài fork B bằng block khó hơn
+    // Kéo dài fork A thêm 2 block => reorg ngược lại
     // ===============================
 
-    let block_d = mine_block(hash_b, 0x1e00ffff);
-
+    let block_d = mine_block(&chain, hash_a, t0 + 4);
     let hash_d = hash_header(&block_d.header);
     chain.add_block(block_d);
 
-    println!("Final tip (should be D): {:?}", chain.tip);
-    println!("Expected tip hash (D): {:?}", hash_d);
+    let block_e = mine_block(&chain, hash_d, t0 + 5);
+    let hash_e = hash_header(&block_e.header);
+    chain.add_block(block_e);
+
+    println!("Final tip (should be E): {}", hex::encode(chain.tip));
+    assert_eq!(chain.tip, hash_e);
+    assert_eq!(chain.tip_height(), 3);
+
+    // header sai difficulty bị từ chối
+    let mut bad = mine_block(&chain, hash_e, t0 + 6);
+    bad.header.bits = 0x1e00ffff;
+    assert!(!chain.add_block(bad));
+
+    println!("fork test OK");
 }
@@ -1,5 +1,6 @@
 use serde::{Serialize, Deserialize};
 
+use super::encode::Encodable;
 use super::header::BlockHeader;
 use super::tx::Transaction;
 
@@ -13,9 +14,11 @@ pub struct Block {
 
 pub fn merkle_root(txs: &[Transaction]) -> [u8; 32] {
     let mut hasher = Sha256::new();
+    let mut encoded = Vec::new();
     for tx in txs {
-        let encoded = bincode::serialize(tx).unwrap();
-        hasher.update(encoded);
+        encoded.clear();
+        tx.consensus_encode(&mut encoded);
+        hasher.update(&encoded);
     }
     hasher.finalize().into()
 }
\ No newline at end of file
@@ -0,0 +1,323 @@
+//! Consensus encoding.
+//!
+//! Hash của block header, txid và merkle root được định nghĩa trên encoding
+//! này, KHÔNG phụ thuộc vào bincode hay thứ tự field của struct Rust.
+//!
+//! - số nguyên cố định (u32, u64) ghi little-endian
+//! - hash 32 byte ghi nguyên văn
+//! - độ dài list / byte string ghi bằng varint (CompactSize), bắt buộc
+//!   ở dạng ngắn nhất (non-canonical bị reject)
+//!
+//! ```text
+//! varint      = n < 0xfd          -> 1 byte
+//!               n <= 0xffff       -> 0xfd | u16
+//!               n <= 0xffff_ffff  -> 0xfe | u32
+//!               còn lại           -> 0xff | u64
+//!
+//! BlockHeader = version u32 | prev_hash 32 | merkle_root 32
+//!               | timestamp u64 | bits u32 | nonce u64          (88 byte)
+//! TxInput     = prev_txid 32 | vout u32 | var_bytes(signature) | var_bytes(pubkey)
+//! TxOutput    = value u64 | var_bytes(to_address)
+//! Transaction = TX_VERSION u32 | varint(n) TxInput* | varint(n) TxOutput*
+//!               | var_bytes(data)
+//! Block       = BlockHeader | varint(n) Transaction*
+//! ```
+
+use std::fmt;
+
+use crate::chain::block::Block;
+use crate::chain::header::BlockHeader;
+use crate::chain::tx::{Transaction, TxInput, TxOutput};
+
+/// Version của transaction encoding, ghi ở đầu mỗi tx
+pub const TX_VERSION: u32 = 1;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    UnexpectedEof,
+    NonCanonicalVarInt,
+    OversizedLength(u64),
+    UnsupportedTxVersion(u32),
+    TrailingBytes(usize),
+    /// Giá trị cộng dồn vượt quá u64 (vd delta trong GCS filter)
+    ValueOverflow,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEof => write!(f, "unexpected end of data"),
+            DecodeError::NonCanonicalVarInt => write!(f, "non-canonical varint"),
+            DecodeError::OversizedLength(n) => write!(f, "length {} exceeds remaining data", n),
+            DecodeError::UnsupportedTxVersion(v) => write!(f, "unsupported tx version {}", v),
+            DecodeError::TrailingBytes(n) => write!(f, "{} trailing bytes", n),
+            DecodeError::ValueOverflow => write!(f, "value overflows u64"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub trait Encodable {
+    fn consensus_encode(&self, out: &mut Vec<u8>);
+}
+
+pub trait Decodable: Sized {
+    fn consensus_decode(r: &mut Reader<'_>) -> Result<Self, DecodeError>;
+}
+
+/// Encode 1 giá trị thành byte
+pub fn serialize<T: Encodable>(value: &T) -> Vec<u8> {
+    let mut out = Vec::new();
+    value.consensus_encode(&mut out);
+    out
+}
+
+/// Decode 1 giá trị, phải dùng hết toàn bộ input
+pub fn deserialize<T: Decodable>(bytes: &[u8]) -> Result<T, DecodeError> {
+    let mut r = Reader::new(bytes);
+    let value = T::consensus_decode(&mut r)?;
+    if r.remaining() != 0 {
+        return Err(DecodeError::TrailingBytes(r.remaining()));
+    }
+    Ok(value)
+}
+
+/* =========================
+   READER
+   ========================= */
+
+pub struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Reader { buf }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.buf.len() < n {
+            return Err(DecodeError::UnexpectedEof);
+        }
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    /// Đọc độ dài list; mỗi phần tử tối thiểu 1 byte nên độ dài
+    /// không thể vượt quá số byte còn lại
+    fn length(&mut self) -> Result<usize, DecodeError> {
+        let n = read_varint(self)?;
+        if n > self.remaining() as u64 {
+            return Err(DecodeError::OversizedLength(n));
+        }
+        Ok(n as usize)
+    }
+}
+
+/* =========================
+   PRIMITIVES
+   ========================= */
+
+pub fn write_varint(out: &mut Vec<u8>, n: u64) {
+    if n < 0xfd {
+        out.push(n as u8);
+    } else if n <= 0xffff {
+        out.push(0xfd);
+        out.extend_from_slice(&(n as u16).to_le_bytes());
+    } else if n <= 0xffff_ffff {
+        out.push(0xfe);
+        out.extend_from_slice(&(n as u32).to_le_bytes());
+    } else {
+        out.push(0xff);
+        out.extend_from_slice(&n.to_le_bytes());
+    }
+}
+
+pub fn read_varint(r: &mut Reader<'_>) -> Result<u64, DecodeError> {
+    let prefix = r.array::<1>()?[0];
+    let (n, min) = match prefix {
+        0xfd => (u16::from_le_bytes(r.array()?) as u64, 0xfd),
+        0xfe => (u32::from_le_bytes(r.array()?) as u64, 0x1_0000),
+        0xff => (u64::from_le_bytes(r.array()?), 0x1_0000_0000),
+        n => return Ok(n as u64),
+    };
+    if n < min {
+        return Err(DecodeError::NonCanonicalVarInt);
+    }
+    Ok(n)
+}
+
+pub fn write_var_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+pub fn read_var_bytes(r: &mut Reader<'_>) -> Result<Vec<u8>, DecodeError> {
+    let n = r.length()?;
+    Ok(r.take(n)?.to_vec())
+}
+
+pub fn write_list<T: Encodable>(out: &mut Vec<u8>, items: &[T]) {
+    write_varint(out, items.len() as u64);
+    for item in items {
+        item.consensus_encode(out);
+    }
+}
+
+pub fn read_list<T: Decodable>(r: &mut Reader<'_>) -> Result<Vec<T>, DecodeError> {
+    let n = r.length()?;
+    let mut items = Vec::with_capacity(n);
+    for _ in 0..n {
+        items.push(T::consensus_decode(r)?);
+    }
+    Ok(items)
+}
+
+impl Encodable for u32 {
+    fn consensus_encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Decodable for u32 {
+    fn consensus_decode(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(u32::from_le_bytes(r.array()?))
+    }
+}
+
+impl Encodable for u64 {
+    fn consensus_encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Decodable for u64 {
+    fn consensus_decode(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(u64::from_le_bytes(r.array()?))
+    }
+}
+
+impl Encodable for [u8; 32] {
+    fn consensus_encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+}
+
+impl Decodable for [u8; 32] {
+    fn consensus_decode(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        r.array()
+    }
+}
+
+/* =========================
+   CONSENSUS TYPES
+   ========================= */
+
+impl Encodable for BlockHeader {
+    fn consensus_encode(&self, out: &mut Vec<u8>) {
+        self.version.consensus_encode(out);
+        self.prev_hash.consensus_encode(out);
+        self.merkle_root.consensus_encode(out);
+        self.timestamp.consensus_encode(out);
+        self.bits.consensus_encode(out);
+        self.nonce.consensus_encode(out);
+    }
+}
+
+impl Decodable for BlockHeader {
+    fn consensus_decode(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(BlockHeader {
+            version: Decodable::consensus_decode(r)?,
+            prev_hash: Decodable::consensus_decode(r)?,
+            merkle_root: Decodable::consensus_decode(r)?,
+            timestamp: Decodable::consensus_decode(r)?,
+            bits: Decodable::consensus_decode(r)?,
+            nonce: Decodable::consensus_decode(r)?,
+        })
+    }
+}
+
+impl Encodable for TxInput {
+    fn consensus_encode(&self, out: &mut Vec<u8>) {
+        self.prev_txid.consensus_encode(out);
+        self.vout.consensus_encode(out);
+        write_var_bytes(out, &self.signature);
+        write_var_bytes(out, &self.pubkey);
+    }
+}
+
+impl Decodable for TxInput {
+    fn consensus_decode(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(TxInput {
+            prev_txid: Decodable::consensus_decode(r)?,
+            vout: Decodable::consensus_decode(r)?,
+            signature: read_var_bytes(r)?,
+            pubkey: read_var_bytes(r)?,
+        })
+    }
+}
+
+impl Encodable for TxOutput {
+    fn consensus_encode(&self, out: &mut Vec<u8>) {
+        self.value.consensus_encode(out);
+        write_var_bytes(out, &self.to_address);
+    }
+}
+
+impl Decodable for TxOutput {
+    fn consensus_decode(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(TxOutput {
+            value: Decodable::consensus_decode(r)?,
+            to_address: read_var_bytes(r)?,
+        })
+    }
+}
+
+impl Encodable for Transaction {
+    fn consensus_encode(&self, out: &mut Vec<u8>) {
+        TX_VERSION.consensus_encode(out);
+        write_list(out, &self.inputs);
+        write_list(out, &self.outputs);
+        write_var_bytes(out, &self.data);
+    }
+}
+
+impl Decodable for Transaction {
+    fn consensus_decode(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        let version = u32::consensus_decode(r)?;
+        if version != TX_VERSION {
+            return Err(DecodeError::UnsupportedTxVersion(version));
+        }
+        Ok(Transaction {
+            inputs: read_list(r)?,
+            outputs: read_list(r)?,
+            data: read_var_bytes(r)?,
+        })
+    }
+}
+
+impl Encodable for Block {
+    fn consensus_encode(&self, out: &mut Vec<u8>) {
+        self.header.consensus_encode(out);
+        write_list(out, &self.transactions);
+    }
+}
+
+impl Decodable for Block {
+    fn consensus_decode(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(Block {
+            header: Decodable::consensus_decode(r)?,
+            transactions: read_list(r)?,
+        })
+    }
+}
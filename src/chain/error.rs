@@ -0,0 +1,97 @@
+use std::fmt;
+
+use crate::chain::header_chain::HeaderError;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChainError {
+    UnknownBlock([u8; 32]),
+    GenesisBlock,
+    /// Block không qua được validation khi connect
+    InvalidBlock([u8; 32]),
+    /// Block body không có trên disk
+    MissingData([u8; 32]),
+    /// Undo data không có trên disk
+    MissingUndo([u8; 32]),
+    /// Dữ liệu trên disk không nhất quán
+    Corrupt {
+        hash: [u8; 32],
+        reason: &'static str,
+    },
+    /// Dữ liệu của block đã bị xoá do prune
+    Pruned {
+        hash: [u8; 32],
+        height: u64,
+    },
+    /// Thao tác cần toàn bộ lịch sử block, node đang prune
+    NeedsFullHistory(&'static str),
+    /// Thao tác cần lịch sử đầy đủ, snapshot UTXO set chưa validate xong
+    UnvalidatedSnapshot(&'static str),
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainError::UnknownBlock(h) => write!(f, "block {} not found", hex::encode(h)),
+            ChainError::GenesisBlock => write!(f, "genesis block cannot be changed"),
+            ChainError::InvalidBlock(h) => write!(f, "block {} is invalid", hex::encode(h)),
+            ChainError::MissingData(h) => write!(f, "block data for {} missing", hex::encode(h)),
+            ChainError::MissingUndo(h) => write!(f, "undo data for {} missing", hex::encode(h)),
+            ChainError::Corrupt { hash, reason } => {
+                write!(f, "block {} corrupt: {}", hex::encode(hash), reason)
+            }
+            ChainError::Pruned { hash, height } => write!(
+                f,
+                "data for block {} at height {} has been pruned",
+                hex::encode(hash),
+                height
+            ),
+            ChainError::NeedsFullHistory(op) => {
+                write!(f, "{} requires full block history, but this node is pruned", op)
+            }
+            ChainError::UnvalidatedSnapshot(op) => write!(
+                f,
+                "{} requires full block history, but the utxo snapshot is not validated yet",
+                op
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ChainError {}
+
+/// Lý do block body bị từ chối khi nhận
+#[derive(Debug, PartialEq, Eq)]
+pub enum BlockError {
+    Header(HeaderError),
+    /// Merkle root không khớp header: body có thể bị sửa dọc đường, header vẫn có thể hợp lệ
+    BadMerkleRoot,
+    NoTransactions,
+    /// Block không qua được validation khi connect
+    Invalid([u8; 32]),
+}
+
+impl BlockError {
+    /// Block vi phạm luật consensus (peer gửi nó đáng bị phạt); còn lại có
+    /// thể do đồng hồ lệch, thiếu parent hoặc body bị sửa
+    pub fn is_consensus_failure(&self) -> bool {
+        match self {
+            BlockError::Header(HeaderError::UnknownParent(_) | HeaderError::TimeTooNew) => false,
+            BlockError::Header(_) => true,
+            BlockError::BadMerkleRoot => false,
+            BlockError::NoTransactions | BlockError::Invalid(_) => true,
+        }
+    }
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockError::Header(e) => write!(f, "bad header: {}", e),
+            BlockError::BadMerkleRoot => write!(f, "merkle root mismatch"),
+            BlockError::NoTransactions => write!(f, "block has no transactions"),
+            BlockError::Invalid(h) => write!(f, "block {} is invalid", hex::encode(h)),
+        }
+    }
+}
+
+impl std::error::Error for BlockError {}
@@ -0,0 +1,380 @@
+//! Compact block filter (Golomb-coded set, theo BIP158 "basic").
+//!
+//! Filter của 1 block chứa mọi address nhận output trong block và mọi
+//! outpoint bị tiêu bởi block. Light client tải filter thay vì block để
+//! biết block nào có liên quan tới mình, không lộ address cho peer.
+//!
+//! ```text
+//! element     = to_address | txid 32 | vout u32 LE   (outpoint bị tiêu)
+//! key         = 16 byte đầu của block hash
+//! value(e)    = (siphash24(key, e) * N * M) >> 64
+//! filter      = varint(N) | Golomb-Rice(P) của các delta giữa value đã sắp xếp
+//! filter_hash = sha256d(filter)
+//! header      = sha256d(filter_hash | prev_header)  (prev của genesis = 0)
+//! ```
+
+use std::fmt;
+
+use sha2::{Sha256, Digest};
+
+use crate::chain::block::Block;
+use crate::chain::encode::{read_varint, write_varint, DecodeError, Reader};
+use crate::chain::siphash::{siphash24, siphash_key};
+use crate::chain::state::ChainState;
+
+/// Loại filter duy nhất hiện có
+pub const BASIC_FILTER: u8 = 0;
+/// Số bit phần dư Golomb-Rice
+pub const FILTER_P: u8 = 19;
+/// 1/M = tỉ lệ false positive
+pub const FILTER_M: u64 = 784931;
+
+/// Element cho outpoint bị tiêu, dùng cả khi build lẫn khi client query
+pub fn outpoint_element(txid: &[u8; 32], vout: u32) -> Vec<u8> {
+    let mut e = txid.to_vec();
+    e.extend_from_slice(&vout.to_le_bytes());
+    e
+}
+
+fn block_elements(block: &Block) -> Vec<Vec<u8>> {
+    let mut elements = Vec::new();
+
+    for (i, tx) in block.transactions.iter().enumerate() {
+        for out in &tx.outputs {
+            if !out.to_address.is_empty() {
+                elements.push(out.to_address.clone());
+            }
+        }
+        // input của coinbase không tiêu outpoint nào
+        if i > 0 {
+            for inp in &tx.inputs {
+                elements.push(outpoint_element(&inp.prev_txid, inp.vout));
+            }
+        }
+    }
+
+    elements.sort();
+    elements.dedup();
+    elements
+}
+
+fn hash_to_range(key: (u64, u64), element: &[u8], range: u64) -> u64 {
+    ((siphash24(key.0, key.1, element) as u128 * range as u128) >> 64) as u64
+}
+
+fn filter_key(block_hash: &[u8; 32]) -> (u64, u64) {
+    siphash_key(block_hash[..16].try_into().unwrap())
+}
+
+/* =========================
+   BIT STREAM
+   ========================= */
+
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write_bit(&mut self, bit: bool) {
+        if self.bits == 0 {
+            self.out.push(0);
+        }
+        if bit {
+            *self.out.last_mut().unwrap() |= 0x80 >> self.bits;
+        }
+        self.bits = (self.bits + 1) % 8;
+    }
+
+    fn write_bits(&mut self, value: u64, n: u8) {
+        for i in (0..n).rev() {
+            self.write_bit((value >> i) & 1 == 1);
+        }
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn read_bit(&mut self) -> Result<bool, DecodeError> {
+        let byte = self.data.get(self.pos / 8).ok_or(DecodeError::UnexpectedEof)?;
+        let bit = byte & (0x80 >> (self.pos % 8)) != 0;
+        self.pos += 1;
+        Ok(bit)
+    }
+
+    fn read_bits(&mut self, n: u8) -> Result<u64, DecodeError> {
+        let mut value = 0;
+        for _ in 0..n {
+            value = (value << 1) | self.read_bit()? as u64;
+        }
+        Ok(value)
+    }
+}
+
+fn golomb_decode(r: &mut BitReader<'_>) -> Result<u64, DecodeError> {
+    let mut q = 0u64;
+    while r.read_bit()? {
+        q += 1;
+    }
+    Ok((q << FILTER_P) + r.read_bits(FILTER_P)?)
+}
+
+/* =========================
+   BUILD / MATCH
+   ========================= */
+
+pub fn build_filter(block_hash: &[u8; 32], block: &Block) -> Vec<u8> {
+    let elements = block_elements(block);
+    let n = elements.len() as u64;
+    let key = filter_key(block_hash);
+
+    let mut values: Vec<u64> = elements
+        .iter()
+        .map(|e| hash_to_range(key, e, n * FILTER_M))
+        .collect();
+    values.sort_unstable();
+
+    let mut out = Vec::new();
+    write_varint(&mut out, n);
+
+    let mut bits = BitWriter::default();
+    let mut last = 0;
+    for v in values {
+        let delta = v - last;
+        last = v;
+
+        for _ in 0..(delta >> FILTER_P) {
+            bits.write_bit(true);
+        }
+        bits.write_bit(false);
+        bits.write_bits(delta, FILTER_P);
+    }
+
+    out.extend_from_slice(&bits.out);
+    out
+}
+
+/// Filter có chứa ít nhất 1 trong `queries` không (có thể false positive 1/M)
+pub fn filter_match_any(
+    filter: &[u8],
+    block_hash: &[u8; 32],
+    queries: &[&[u8]],
+) -> Result<bool, DecodeError> {
+    let mut r = Reader::new(filter);
+    let n = read_varint(&mut r)?;
+    if n == 0 || queries.is_empty() {
+        return Ok(false);
+    }
+
+    let key = filter_key(block_hash);
+    let range = n.checked_mul(FILTER_M).ok_or(DecodeError::OversizedLength(n))?;
+    let mut targets: Vec<u64> = queries.iter().map(|q| hash_to_range(key, q, range)).collect();
+    targets.sort_unstable();
+
+    let mut bits = BitReader { data: r.take(r.remaining())?, pos: 0 };
+    let mut value = 0u64;
+    let mut t = 0;
+
+    for _ in 0..n {
+        // filter hỏng có thể cộng dồn quá u64
+        value = value.checked_add(golomb_decode(&mut bits)?).ok_or(DecodeError::ValueOverflow)?;
+
+        while t < targets.len() && targets[t] < value {
+            t += 1;
+        }
+        if t == targets.len() {
+            return Ok(false);
+        }
+        if targets[t] == value {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn sha256d(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(Sha256::digest(data)).into()
+}
+
+pub fn filter_hash(filter: &[u8]) -> [u8; 32] {
+    sha256d(filter)
+}
+
+/// Header của filter, nối filter của block với filter header của parent
+pub fn filter_header(filter_hash: &[u8; 32], prev_header: &[u8; 32]) -> [u8; 32] {
+    let mut data = filter_hash.to_vec();
+    data.extend_from_slice(prev_header);
+    sha256d(&data)
+}
+
+/* =========================
+   FILTER CHAIN
+   ========================= */
+
+/// Số filter tối đa trong 1 lần trả lời GetCFilters
+pub const MAX_CFILTERS: u64 = 1000;
+/// Số filter hash tối đa trong 1 lần trả lời GetCFHeaders
+pub const MAX_CFHEADERS: u64 = 2000;
+
+/// (block hash, filter) theo thứ tự height
+pub type BlockFilters = Vec<([u8; 32], Vec<u8>)>;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FilterRequestError {
+    /// stop_hash không có trong index
+    UnknownStop([u8; 32]),
+    /// start > stop hoặc khoảng quá rộng
+    BadRange { start_height: u64, stop_height: u64 },
+    /// Khoảng hợp lệ nhưng mình chưa có filter (không phải lỗi của peer)
+    Missing([u8; 32]),
+}
+
+impl FilterRequestError {
+    /// Request sai định dạng, đáng bị phạt
+    pub fn is_malformed(&self) -> bool {
+        !matches!(self, FilterRequestError::Missing(_))
+    }
+}
+
+impl fmt::Display for FilterRequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterRequestError::UnknownStop(h) => write!(f, "unknown stop hash {}", hex::encode(h)),
+            FilterRequestError::BadRange { start_height, stop_height } => {
+                write!(f, "bad filter range {}..={}", start_height, stop_height)
+            }
+            FilterRequestError::Missing(h) => write!(f, "no filter for block {}", hex::encode(h)),
+        }
+    }
+}
+
+impl std::error::Error for FilterRequestError {}
+
+impl ChainState {
+    /// Lưu filter của block vừa connect và nối filter header nếu có header của parent
+    pub(crate) fn index_filter(&self, hash: &[u8; 32], block: &Block) {
+        if self.db.get_filter(hash).is_none() {
+            self.db.put_filter(hash, &build_filter(hash, block));
+        }
+        self.connect_filter_header(hash);
+    }
+
+    fn connect_filter_header(&self, hash: &[u8; 32]) -> bool {
+        if self.db.get_filter_header(hash).is_some() {
+            return true;
+        }
+
+        let entry = match self.index.get(hash) {
+            Some(e) => e,
+            None => return false,
+        };
+        let prev_header = if entry.height == 0 {
+            [0u8; 32]
+        } else {
+            match self.db.get_filter_header(&entry.header.prev_hash) {
+                Some(h) => h,
+                None => return false,
+            }
+        };
+        let filter = match self.db.get_filter(hash) {
+            Some(f) => f,
+            None => return false,
+        };
+
+        self.db
+            .put_filter_header(hash, &filter_header(&filter_hash(&filter), &prev_header));
+        true
+    }
+
+    /// DB tạo trước khi có filter: dựng filter cho active chain từ body còn trên disk
+    pub(crate) fn backfill_filters(&self) {
+        if self.db.get_filter_header(&self.tip).is_some() {
+            return;
+        }
+
+        for h in 0..=self.tip_height() {
+            let hash = self.block_hash_at(h).unwrap();
+            if self.db.get_filter(&hash).is_none() {
+                match self.get_block(&hash) {
+                    Some(block) => self.db.put_filter(&hash, &build_filter(&hash, &block)),
+                    None => break,
+                }
+            }
+            if !self.connect_filter_header(&hash) {
+                break;
+            }
+        }
+    }
+
+    /// Nối filter header cho block trên active chain từ `height` (sau khi lấp được
+    /// khoảng trống, vd background validation của snapshot tới base)
+    pub(crate) fn fill_filter_headers(&self, height: u64) {
+        for h in height..=self.tip_height() {
+            let hash = self.block_hash_at(h).unwrap();
+            if !self.connect_filter_header(&hash) {
+                break;
+            }
+        }
+    }
+
+    /// Block từ `start_height` tới `stop_hash` (trên nhánh của stop_hash)
+    fn filter_range(
+        &self,
+        start_height: u64,
+        stop_hash: &[u8; 32],
+        max: u64,
+    ) -> Result<Vec<[u8; 32]>, FilterRequestError> {
+        let stop = self.index.get(stop_hash).ok_or(FilterRequestError::UnknownStop(*stop_hash))?;
+        if start_height > stop.height || stop.height - start_height >= max {
+            return Err(FilterRequestError::BadRange { start_height, stop_height: stop.height });
+        }
+
+        Ok((start_height..=stop.height)
+            .map(|h| self.index.get_ancestor(stop_hash, h).unwrap().hash)
+            .collect())
+    }
+
+    /// Trả lời GetCFilters
+    pub fn get_cfilters(
+        &self,
+        start_height: u64,
+        stop_hash: &[u8; 32],
+    ) -> Result<BlockFilters, FilterRequestError> {
+        self.filter_range(start_height, stop_hash, MAX_CFILTERS)?
+            .into_iter()
+            .map(|h| self.db.get_filter(&h).map(|f| (h, f)).ok_or(FilterRequestError::Missing(h)))
+            .collect()
+    }
+
+    /// Trả lời GetCFHeaders: filter header ngay trước start và filter hash của từng block
+    pub fn get_cfheaders(
+        &self,
+        start_height: u64,
+        stop_hash: &[u8; 32],
+    ) -> Result<([u8; 32], Vec<[u8; 32]>), FilterRequestError> {
+        let range = self.filter_range(start_height, stop_hash, MAX_CFHEADERS)?;
+
+        let prev_header = if start_height == 0 {
+            [0u8; 32]
+        } else {
+            let prev = self.index.get_ancestor(stop_hash, start_height - 1).unwrap();
+            self.db.get_filter_header(&prev.hash).ok_or(FilterRequestError::Missing(prev.hash))?
+        };
+
+        let hashes = range
+            .iter()
+            .map(|h| {
+                // chỉ trả khi header đã nối được, tránh phục vụ chain filter dở dang
+                self.db.get_filter_header(h).ok_or(FilterRequestError::Missing(*h))?;
+                self.db.get_filter(h).map(|f| filter_hash(&f)).ok_or(FilterRequestError::Missing(*h))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok((prev_header, hashes))
+    }
+}
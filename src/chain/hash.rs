@@ -1,8 +1,8 @@
 use sha2::{Sha256, Digest};
-use bincode;
+use super::encode::serialize;
 use super::header::BlockHeader;
 
 pub fn hash_header(header: &BlockHeader) -> [u8; 32] {
-    let encoded = bincode::serialize(header).unwrap();
+    let encoded = serialize(header);
     Sha256::digest(encoded).into()
 }
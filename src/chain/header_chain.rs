@@ -1,37 +1,260 @@
-use std::collections::HashMap;
-use crate::chain::header::BlockHeader;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use crate::chain::hash::hash_header;
+use crate::chain::header::BlockHeader;
+use crate::chain::index::{BlockIndexEntry, BlockStatus};
+use crate::chain::state::ChainState;
+use crate::pow::retarget::next_bits;
 use crate::pow::verify::verify_pow;
+use crate::pow::work::work_from_bits;
+
+/// Số header tối đa trong 1 message Headers
+pub const MAX_HEADERS_RESULTS: usize = 2000;
+/// Timestamp của block không được vượt quá giờ hiện tại chừng này (giây)
+pub const MAX_FUTURE_BLOCK_TIME: u64 = 2 * 60 * 60;
+/// Số block dùng để tính median time past
+pub const MEDIAN_TIME_SPAN: usize = 11;
+/// Chỉ tải body của block cách điểm rẽ nhánh với active chain tối đa chừng này
+pub const BLOCK_DOWNLOAD_WINDOW: u64 = 1024;
 
-pub struct HeaderChain {
-    pub headers: HashMap<[u8; 32], BlockHeader>,
-    pub tip: [u8; 32],
+#[derive(Debug, PartialEq, Eq)]
+pub enum HeaderError {
+    /// Chưa có parent trong index (không phải lỗi của peer)
+    UnknownParent([u8; 32]),
+    /// Parent đã bị đánh dấu invalid
+    FailedParent([u8; 32]),
+    BadDifficulty { expected: u32, got: u32 },
+    HighHash,
+    /// Timestamp không lớn hơn median time past
+    TimeTooOld,
+    TimeTooNew,
 }
 
-impl HeaderChain {
-    pub fn new(genesis: BlockHeader) -> Self {
-        let hash = hash_header(&genesis);
-        let mut headers = HashMap::new();
-        headers.insert(hash, genesis);
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderError::UnknownParent(h) => write!(f, "unknown parent {}", hex::encode(h)),
+            HeaderError::FailedParent(h) => write!(f, "parent {} is invalid", hex::encode(h)),
+            HeaderError::BadDifficulty { expected, got } => {
+                write!(f, "bits {:08x}, expected {:08x}", got, expected)
+            }
+            HeaderError::HighHash => write!(f, "proof of work failed"),
+            HeaderError::TimeTooOld => write!(f, "timestamp not after median time past"),
+            HeaderError::TimeTooNew => write!(f, "timestamp too far in the future"),
+        }
+    }
+}
+
+impl std::error::Error for HeaderError {}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/* =========================
+   HEADER VALIDATION
+   ========================= */
+
+impl ChainState {
+    /// Thêm header vào index nếu hợp lệ; header đã có thì trả về luôn
+    pub fn accept_header(&mut self, header: &BlockHeader) -> bool {
+        self.process_header(header).is_ok()
+    }
+
+    /// Kiểm tra header theo parent (difficulty, PoW, timestamp) rồi thêm vào index
+    pub fn process_header(&mut self, header: &BlockHeader) -> Result<[u8; 32], HeaderError> {
+        let hash = hash_header(header);
+        if let Some(entry) = self.index.get(&hash) {
+            if entry.status.is_failed() {
+                return Err(HeaderError::FailedParent(header.prev_hash));
+            }
+            return Ok(hash);
+        }
+
+        let parent = match self.index.get(&header.prev_hash) {
+            Some(p) if p.status.is_failed() => return Err(HeaderError::FailedParent(p.hash)),
+            Some(p) => p,
+            None => return Err(HeaderError::UnknownParent(header.prev_hash)),
+        };
 
-        HeaderChain {
-            headers,
-            tip: hash,
+        // kiểm tra bits trước PoW: bits lạ không được đưa vào bits_to_target
+        let expected = next_bits(&self.params.pow, &self.index, parent);
+        if header.bits != expected {
+            return Err(HeaderError::BadDifficulty { expected, got: header.bits });
+        }
+        if !verify_pow(header) {
+            return Err(HeaderError::HighHash);
         }
+        if header.timestamp <= self.median_time_past(parent) {
+            return Err(HeaderError::TimeTooOld);
+        }
+        if header.timestamp > now() + MAX_FUTURE_BLOCK_TIME {
+            return Err(HeaderError::TimeTooNew);
+        }
+
+        let mut status = BlockStatus::default();
+        status.set(BlockStatus::HEADER_VALID);
+
+        let entry = BlockIndexEntry {
+            hash,
+            header: header.clone(),
+            height: parent.height + 1,
+            chainwork: parent.chainwork + work_from_bits(header.bits),
+            status,
+            data_pos: None,
+            tx_count: 0,
+            chain_tx: 0,
+            skip: None,
+        };
+
+        let better = Self::is_better(&entry, self.index.get(&self.best_header).unwrap());
+
+        self.db.put_index(&entry);
+        self.index.insert(entry);
+        if better {
+            self.best_header = hash;
+        }
+        Ok(hash)
     }
 
-    pub fn add(&mut self, header: BlockHeader) -> bool {
-        if !verify_pow(&header) {
-            return false;
+    /// Median timestamp của tối đa 11 block tính từ `entry` ngược về
+    pub fn median_time_past(&self, entry: &BlockIndexEntry) -> u64 {
+        let mut times = Vec::with_capacity(MEDIAN_TIME_SPAN);
+        let mut walk = Some(entry);
+
+        while let Some(e) = walk {
+            times.push(e.header.timestamp);
+            if times.len() == MEDIAN_TIME_SPAN || e.height == 0 {
+                break;
+            }
+            walk = self.index.get(&e.header.prev_hash);
         }
 
-        if !self.headers.contains_key(&header.prev_hash) {
-            return false;
+        times.sort_unstable();
+        times[times.len() / 2]
+    }
+
+    /// Header most-work đã biết (có thể chưa có body)
+    pub fn best_header(&self) -> &BlockIndexEntry {
+        self.index.get(&self.best_header).unwrap()
+    }
+
+    /// Tìm lại best header sau khi có block bị đánh dấu / gỡ invalid
+    pub(crate) fn recompute_best_header(&mut self) {
+        let mut best = self.tip_entry();
+        for entry in self.index.iter() {
+            if !entry.status.is_failed() && Self::is_better(entry, best) {
+                best = entry;
+            }
         }
+        self.best_header = best.hash;
+    }
+}
+
+/* =========================
+   SYNC HELPERS
+   ========================= */
+
+impl ChainState {
+    /// Block locator: 10 block gần nhất rồi giãn cách gấp đôi, kết thúc ở genesis
+    pub fn block_locator(&self, hash: &[u8; 32]) -> Vec<[u8; 32]> {
+        let mut locator = Vec::new();
+        let mut height = match self.index.get(hash) {
+            Some(e) => e.height,
+            None => return locator,
+        };
+        let mut step = 1;
+
+        loop {
+            locator.push(self.index.get_ancestor(hash, height).unwrap().hash);
+            if height == 0 {
+                break;
+            }
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+
+        locator
+    }
+
+    /// Trả lời GetHeaders: header trên active chain sau block chung đầu tiên
+    /// trong locator, dừng ở `stop` (0 = không giới hạn) hoặc MAX_HEADERS_RESULTS
+    pub fn headers_after_locator(&self, locator: &[[u8; 32]], stop: &[u8; 32]) -> Vec<BlockHeader> {
+        let fork_height = locator
+            .iter()
+            .find(|h| self.index.is_ancestor(h, &self.tip))
+            .and_then(|h| self.index.get(h))
+            .map(|e| e.height)
+            .unwrap_or(0);
+
+        let mut headers = Vec::new();
+        for height in fork_height + 1..=self.tip_height() {
+            let entry = self.index.get_ancestor(&self.tip, height).unwrap();
+            headers.push(entry.header.clone());
 
-        let hash = hash_header(&header);
-        self.headers.insert(hash, header);
-        self.tip = hash;
-        true
+            if &entry.hash == stop || headers.len() == MAX_HEADERS_RESULTS {
+                break;
+            }
+        }
+        headers
+    }
+
+    /// Block nên tải body tiếp theo, theo thứ tự height trên nhánh của best header.
+    /// Còn chỗ thì thêm body dưới base của snapshot cho background validation.
+    /// `skip`: block đang được tải ở peer khác.
+    pub fn blocks_to_download(&self, max: usize, skip: impl Fn(&[u8; 32]) -> bool) -> Vec<[u8; 32]> {
+        let mut picks = Vec::new();
+        let best = self.best_header();
+        if max > 0 && best.chainwork > self.tip_entry().chainwork {
+            let fork = self.index.last_common_ancestor(&self.tip, &best.hash).unwrap();
+            let fork_height = self.index.get(&fork).unwrap().height;
+            let end = best.height.min(fork_height + BLOCK_DOWNLOAD_WINDOW);
+            self.pick_missing(&best.hash, fork_height + 1..=end, max, &skip, &mut picks);
+        }
+
+        // cursor thứ 2: block ASSUMED_VALID từ chỗ background validation đang chờ
+        if let Some((base, next)) = self.snapshot_download_start() {
+            let base_height = self.index.get(&base).unwrap().height;
+            let end = base_height.min(next + BLOCK_DOWNLOAD_WINDOW - 1);
+            self.pick_missing(&base, next..=end, max, &skip, &mut picks);
+        }
+        picks
+    }
+
+    /// Thêm vào `picks` block chưa có body trong `heights` trên nhánh tới `tip`
+    fn pick_missing(
+        &self,
+        tip: &[u8; 32],
+        heights: std::ops::RangeInclusive<u64>,
+        max: usize,
+        skip: &impl Fn(&[u8; 32]) -> bool,
+        picks: &mut Vec<[u8; 32]>,
+    ) {
+        for height in heights {
+            if picks.len() >= max {
+                return;
+            }
+            let entry = self.index.get_ancestor(tip, height).unwrap();
+            if entry.status.has(BlockStatus::HAVE_DATA) || skip(&entry.hash) {
+                continue;
+            }
+            picks.push(entry.hash);
+        }
+    }
+
+    /// Block chưa được yêu cầu chỉ được nhận nếu có thể trở thành tip mới
+    pub fn is_potential_tip(&self, header: &BlockHeader) -> bool {
+        match self.index.get(&header.prev_hash) {
+            Some(parent) => {
+                parent.chainwork + work_from_bits(header.bits) > self.tip_entry().chainwork
+            }
+            None => false,
+        }
     }
 }
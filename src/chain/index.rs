@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+
+use crate::chain::header::BlockHeader;
+
+/// Trạng thái validation của 1 block trong index (bit flags)
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct BlockStatus(u32);
+
+impl BlockStatus {
+    /// Header đã qua PoW + có parent trong index
+    pub const HEADER_VALID: u32 = 1 << 0;
+    /// Block body đã được lưu trên disk
+    pub const HAVE_DATA: u32 = 1 << 1;
+    /// Undo data đã được lưu (block đã từng được connect)
+    pub const HAVE_UNDO: u32 = 1 << 2;
+    /// Block đã được connect thành công vào UTXO set
+    pub const FULLY_VALID: u32 = 1 << 3;
+    /// Bản thân block invalid
+    pub const FAILED: u32 = 1 << 4;
+    /// Block nằm sau 1 block invalid
+    pub const FAILED_PARENT: u32 = 1 << 5;
+    /// Block nằm dưới base của snapshot UTXO set, chưa được validate từ genesis
+    pub const ASSUMED_VALID: u32 = 1 << 6;
+
+    pub fn has(&self, flag: u32) -> bool {
+        self.0 & flag == flag
+    }
+
+    pub fn set(&mut self, flag: u32) {
+        self.0 |= flag;
+    }
+
+    pub fn clear(&mut self, flag: u32) {
+        self.0 &= !flag;
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.0 & (Self::FAILED | Self::FAILED_PARENT) != 0
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BlockIndexEntry {
+    pub hash: [u8; 32],
+    pub header: BlockHeader,
+    pub height: u64,
+    pub chainwork: u128,
+    pub status: BlockStatus,
+    /// Vị trí block body trong ChainDB (None = chưa có / đã prune)
+    pub data_pos: Option<u64>,
+    /// Số tx trong block (0 = chưa có body)
+    pub tx_count: u64,
+    /// Tổng số tx từ genesis tới block này; 0 = thiếu body ở đâu đó phía trước
+    pub chain_tx: u64,
+    /// Skip pointer tới ancestor ở height `skip_height(height)`
+    #[serde(skip)]
+    pub skip: Option<[u8; 32]>,
+}
+
+/// Chiều cao mà skip pointer của block ở `height` trỏ tới
+fn skip_height(height: u64) -> u64 {
+    fn invert_lowest_one(n: u64) -> u64 {
+        n & n.wrapping_sub(1)
+    }
+
+    if height < 2 {
+        return 0;
+    }
+
+    if height & 1 == 1 {
+        invert_lowest_one(invert_lowest_one(height - 1)) + 1
+    } else {
+        invert_lowest_one(height)
+    }
+}
+
+/// Index của mọi header đã biết (chỉ header + metadata, body nằm trên disk)
+#[derive(Default)]
+pub struct BlockIndex {
+    entries: HashMap<[u8; 32], BlockIndexEntry>,
+    /// parent -> các block con, để đi xuống con cháu không phải quét cả index
+    children: HashMap<[u8; 32], Vec<[u8; 32]>>,
+}
+
+impl BlockIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Thêm entry; parent phải có sẵn trong index (trừ genesis)
+    pub fn insert(&mut self, mut entry: BlockIndexEntry) {
+        entry.skip = if entry.height == 0 {
+            None
+        } else {
+            self.get_ancestor(&entry.header.prev_hash, skip_height(entry.height))
+                .map(|e| e.hash)
+        };
+        let (hash, prev, height) = (entry.hash, entry.header.prev_hash, entry.height);
+        if self.entries.insert(hash, entry).is_none() && height > 0 {
+            self.children.entry(prev).or_default().push(hash);
+        }
+    }
+
+    pub fn get(&self, hash: &[u8; 32]) -> Option<&BlockIndexEntry> {
+        self.entries.get(hash)
+    }
+
+    pub fn get_mut(&mut self, hash: &[u8; 32]) -> Option<&mut BlockIndexEntry> {
+        self.entries.get_mut(hash)
+    }
+
+    pub fn contains(&self, hash: &[u8; 32]) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &BlockIndexEntry> {
+        self.entries.values()
+    }
+
+    pub fn children(&self, hash: &[u8; 32]) -> &[[u8; 32]] {
+        self.children.get(hash).map_or(&[], |c| c.as_slice())
+    }
+
+    /// Mọi con cháu của `hash` (không gồm chính nó), trên mọi nhánh
+    pub fn descendants(&self, hash: &[u8; 32]) -> Vec<[u8; 32]> {
+        let mut out = Vec::new();
+        let mut stack = self.children(hash).to_vec();
+        while let Some(h) = stack.pop() {
+            stack.extend_from_slice(self.children(&h));
+            out.push(h);
+        }
+        out
+    }
+
+    /// Ancestor của `hash` ở `height`, O(log n) nhờ skip pointer
+    pub fn get_ancestor(&self, hash: &[u8; 32], height: u64) -> Option<&BlockIndexEntry> {
+        let mut walk = self.entries.get(hash)?;
+        if height > walk.height {
+            return None;
+        }
+
+        while walk.height > height {
+            let h_skip = skip_height(walk.height);
+            let h_skip_prev = skip_height(walk.height - 1);
+
+            // chỉ nhảy skip nếu không "vượt quá" và không tệ hơn đi qua parent rồi nhảy
+            let take_skip = walk.skip.is_some()
+                && (h_skip == height
+                    || (h_skip > height
+                        && !(h_skip_prev + 2 < h_skip && h_skip_prev >= height)));
+
+            walk = if take_skip {
+                self.entries.get(&walk.skip.unwrap())?
+            } else {
+                self.entries.get(&walk.header.prev_hash)?
+            };
+        }
+
+        Some(walk)
+    }
+
+    /// Block chung cuối cùng của 2 nhánh
+    pub fn last_common_ancestor(&self, a: &[u8; 32], b: &[u8; 32]) -> Option<[u8; 32]> {
+        let ea = self.entries.get(a)?;
+        let eb = self.entries.get(b)?;
+        let height = ea.height.min(eb.height);
+
+        let mut ha = self.get_ancestor(a, height)?;
+        let mut hb = self.get_ancestor(b, height)?;
+
+        while ha.hash != hb.hash {
+            ha = self.entries.get(&ha.header.prev_hash)?;
+            hb = self.entries.get(&hb.header.prev_hash)?;
+        }
+
+        Some(ha.hash)
+    }
+
+    /// `ancestor` có nằm trên nhánh dẫn tới `hash` không
+    pub fn is_ancestor(&self, ancestor: &[u8; 32], hash: &[u8; 32]) -> bool {
+        let height = match self.entries.get(ancestor) {
+            Some(e) => e.height,
+            None => return false,
+        };
+        self.get_ancestor(hash, height)
+            .map(|e| &e.hash == ancestor)
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hash giả: nhánh + height
+    fn hash(branch: u8, height: u64) -> [u8; 32] {
+        let mut h = [0u8; 32];
+        h[0] = branch;
+        h[1..9].copy_from_slice(&height.to_le_bytes());
+        h
+    }
+
+    fn add(index: &mut BlockIndex, hash: [u8; 32], prev: [u8; 32], height: u64) {
+        index.insert(BlockIndexEntry {
+            hash,
+            header: BlockHeader {
+                version: 1,
+                prev_hash: prev,
+                merkle_root: [0u8; 32],
+                timestamp: height,
+                bits: 0x1f00ffff,
+                nonce: 0,
+            },
+            height,
+            chainwork: height as u128,
+            status: BlockStatus::default(),
+            data_pos: None,
+            tx_count: 0,
+            chain_tx: 0,
+            skip: None,
+        });
+    }
+
+    const MAIN: u8 = 0;
+    const FORK: u8 = 1;
+    const FORK_POINT: u64 = 40;
+    const MAIN_TIP: u64 = 300;
+    const FORK_TIP: u64 = 170;
+
+    /// Main chain 0..=MAIN_TIP, fork tách ra sau FORK_POINT tới FORK_TIP
+    fn forked_index() -> BlockIndex {
+        let mut index = BlockIndex::new();
+        add(&mut index, hash(MAIN, 0), [0u8; 32], 0);
+        for h in 1..=MAIN_TIP {
+            add(&mut index, hash(MAIN, h), hash(MAIN, h - 1), h);
+        }
+        add(&mut index, hash(FORK, FORK_POINT + 1), hash(MAIN, FORK_POINT), FORK_POINT + 1);
+        for h in FORK_POINT + 2..=FORK_TIP {
+            add(&mut index, hash(FORK, h), hash(FORK, h - 1), h);
+        }
+        index
+    }
+
+    #[test]
+    fn get_ancestor_across_fork() {
+        let index = forked_index();
+
+        for h in 0..=MAIN_TIP {
+            assert_eq!(index.get_ancestor(&hash(MAIN, MAIN_TIP), h).unwrap().hash, hash(MAIN, h));
+        }
+        for h in 0..=FORK_TIP {
+            let branch = if h > FORK_POINT { FORK } else { MAIN };
+            assert_eq!(index.get_ancestor(&hash(FORK, FORK_TIP), h).unwrap().hash, hash(branch, h));
+        }
+        // skip pointer trên fork trỏ về đúng nhánh
+        for h in FORK_POINT + 1..=FORK_TIP {
+            let skip = index.get(&hash(FORK, h)).unwrap().skip.unwrap();
+            let target = skip_height(h);
+            let branch = if target > FORK_POINT { FORK } else { MAIN };
+            assert_eq!(skip, hash(branch, target));
+        }
+
+        assert!(index.get_ancestor(&hash(FORK, FORK_TIP), FORK_TIP + 1).is_none());
+        assert!(index.get_ancestor(&[9u8; 32], 0).is_none());
+    }
+
+    #[test]
+    fn last_common_ancestor_across_fork() {
+        let index = forked_index();
+        let fork_point = Some(hash(MAIN, FORK_POINT));
+
+        assert_eq!(index.last_common_ancestor(&hash(MAIN, MAIN_TIP), &hash(FORK, FORK_TIP)), fork_point);
+        assert_eq!(index.last_common_ancestor(&hash(FORK, FORK_TIP), &hash(MAIN, MAIN_TIP)), fork_point);
+        assert_eq!(index.last_common_ancestor(&hash(MAIN, 100), &hash(FORK, FORK_POINT + 1)), fork_point);
+        // 1 bên là ancestor của bên kia
+        assert_eq!(index.last_common_ancestor(&hash(MAIN, MAIN_TIP), &hash(MAIN, 7)), Some(hash(MAIN, 7)));
+        assert_eq!(index.last_common_ancestor(&hash(FORK, 90), &hash(FORK, 90)), Some(hash(FORK, 90)));
+        assert_eq!(index.last_common_ancestor(&hash(FORK, 90), &[9u8; 32]), None);
+    }
+
+    #[test]
+    fn is_ancestor_across_fork() {
+        let index = forked_index();
+
+        assert!(index.is_ancestor(&hash(MAIN, FORK_POINT), &hash(MAIN, MAIN_TIP)));
+        assert!(index.is_ancestor(&hash(MAIN, FORK_POINT), &hash(FORK, FORK_TIP)));
+        assert!(index.is_ancestor(&hash(MAIN, 0), &hash(FORK, FORK_TIP)));
+        assert!(index.is_ancestor(&hash(FORK, 100), &hash(FORK, 100)));
+
+        assert!(!index.is_ancestor(&hash(MAIN, FORK_POINT + 1), &hash(FORK, FORK_TIP)));
+        assert!(!index.is_ancestor(&hash(FORK, FORK_POINT + 1), &hash(MAIN, MAIN_TIP)));
+        assert!(!index.is_ancestor(&hash(MAIN, MAIN_TIP), &hash(MAIN, 10)));
+        assert!(!index.is_ancestor(&[9u8; 32], &hash(MAIN, MAIN_TIP)));
+    }
+
+    #[test]
+    fn descendants_cover_both_branches() {
+        let index = forked_index();
+
+        let mut below_fork = index.descendants(&hash(MAIN, FORK_POINT));
+        below_fork.sort();
+        let mut expected: Vec<[u8; 32]> = (FORK_POINT + 1..=MAIN_TIP)
+            .map(|h| hash(MAIN, h))
+            .chain((FORK_POINT + 1..=FORK_TIP).map(|h| hash(FORK, h)))
+            .collect();
+        expected.sort();
+        assert_eq!(below_fork, expected);
+
+        assert_eq!(index.descendants(&hash(FORK, FORK_TIP - 2)), vec![hash(FORK, FORK_TIP - 1), hash(FORK, FORK_TIP)]);
+        assert!(index.descendants(&hash(MAIN, MAIN_TIP)).is_empty());
+        assert_eq!(index.children(&hash(MAIN, FORK_POINT)).len(), 2);
+    }
+}
@@ -1,24 +1,33 @@
 use crate::chain::block::{Block, merkle_root};
 use crate::chain::header::BlockHeader;
 use crate::chain::tx::Transaction;
-
-
-
-use sha2::{Sha256, Digest};
-use bincode;
+use crate::chain::hash::hash_header;
 
 
 pub mod block;
+pub mod encode;
+pub mod error;
 pub mod header;
 pub mod tx;
 pub mod validation;
 pub mod hash;
+pub mod index;
 pub mod state;
 pub mod reward;
 pub mod txid;
 pub mod utxo;
 pub mod sign;
 pub mod undo;
+pub mod verify;
+pub mod reindex;
+pub mod prune;
+pub mod txindex;
+pub mod params;
+pub mod snapshot;
+pub mod sethash;
+pub mod siphash;
+pub mod filter;
+pub mod header_chain;
 
 
 
@@ -50,7 +59,5 @@ pub fn genesis_block() -> Block {
 }
 
 pub fn genesis_hash() -> [u8; 32] {
-    let genesis = genesis_block();
-    let encoded = bincode::serialize(&genesis.header).unwrap();
-    Sha256::digest(encoded).into()
+    hash_header(&genesis_block().header)
 }
@@ -0,0 +1,98 @@
+use crate::chain::block::Block;
+use crate::chain::genesis_block;
+
+/// Network mà node đang chạy
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum Network {
+    Main,
+    Regtest,
+}
+
+/// Snapshot UTXO set đã được công bố cho 1 block, dùng cho loadtxoutset
+#[derive(Clone, Debug)]
+pub struct AssumeUtxoData {
+    pub height: u64,
+    pub block_hash: [u8; 32],
+    /// Hash nội dung UTXO set tại block (xem chain::snapshot::utxo_content_hash)
+    pub content_hash: [u8; 32],
+    pub coin_count: u64,
+}
+
+fn hash32(hex: &str) -> [u8; 32] {
+    hex::decode(hex).unwrap().try_into().unwrap()
+}
+
+/// Luật difficulty
+#[derive(Clone, Debug)]
+pub struct PowParams {
+    /// Target dễ nhất được phép (dạng compact)
+    pub pow_limit_bits: u32,
+    /// Thời gian mong muốn giữa 2 block (giây)
+    pub target_spacing: u64,
+    /// Số block giữa 2 lần chỉnh difficulty
+    pub retarget_interval: u64,
+    /// Regtest: difficulty giữ nguyên như parent
+    pub no_retargeting: bool,
+}
+
+impl PowParams {
+    pub fn target_timespan(&self) -> u64 {
+        self.target_spacing * self.retarget_interval
+    }
+}
+
+/// Tham số riêng của từng network
+#[derive(Clone, Debug)]
+pub struct ChainParams {
+    pub network: Network,
+    /// 4 byte đầu mỗi frame P2P, node khác network bị ngắt ngay
+    pub magic: [u8; 4],
+    pub pow: PowParams,
+    /// Snapshot được phép load; snapshot không có trong danh sách bị từ chối
+    pub assumeutxo: Vec<AssumeUtxoData>,
+}
+
+impl ChainParams {
+    pub fn new(network: Network) -> Self {
+        match network {
+            Network::Main => ChainParams {
+                network,
+                magic: [0xe9, 0x67, 0x67, 0x01],
+                pow: PowParams {
+                    pow_limit_bits: 0x1f00ffff,
+                    target_spacing: 60,
+                    retarget_interval: 1440,
+                    no_retargeting: false,
+                },
+                // chưa có snapshot nào được công bố
+                assumeutxo: vec![],
+            },
+            Network::Regtest => ChainParams {
+                network,
+                magic: [0xe9, 0x67, 0x67, 0xfa],
+                pow: PowParams {
+                    pow_limit_bits: 0x1f00ffff,
+                    target_spacing: 60,
+                    retarget_interval: 1440,
+                    no_retargeting: true,
+                },
+                // chain tất định do tests/assumeutxo.rs dựng
+                assumeutxo: vec![AssumeUtxoData {
+                    height: 50,
+                    block_hash: hash32("0000650c99f6b16c95b6533c0d75990f212d4a59e66a25376d3e08e7b333c1aa"),
+                    content_hash: hash32("3e63089cddc612192533d42baaa253bafde296fe9986de44c4cc964855ea7b3a"),
+                    coin_count: 56,
+                }],
+            },
+        }
+    }
+
+    /// Mọi network hiện dùng chung genesis, phân biệt bằng luật PoW
+    pub fn genesis_block(&self) -> Block {
+        genesis_block()
+    }
+
+    pub fn assumeutxo_for(&self, block_hash: &[u8; 32]) -> Option<&AssumeUtxoData> {
+        self.assumeutxo.iter().find(|a| &a.block_hash == block_hash)
+    }
+}
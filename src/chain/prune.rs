@@ -0,0 +1,67 @@
+use crate::chain::index::BlockStatus;
+use crate::chain::state::ChainState;
+
+/// Luôn giữ body + undo của ít nhất chừng này block gần tip (đủ cho reorg thường gặp)
+pub const MIN_BLOCKS_TO_KEEP: u64 = 288;
+
+impl ChainState {
+    /// Bật prune với ngưỡng dung lượng block + undo (byte)
+    pub fn set_prune_target(&mut self, target: Option<u64>) {
+        self.prune_target = target;
+        self.maybe_prune();
+    }
+
+    pub fn is_pruned(&self) -> bool {
+        self.db.pruned_height().is_some()
+    }
+
+    /// Xoá body + undo của block cũ trên active chain cho tới khi dưới ngưỡng.
+    /// Header (index) và UTXO set được giữ nguyên.
+    pub(crate) fn maybe_prune(&mut self) {
+        let target = match self.prune_target {
+            Some(t) => t,
+            None => return,
+        };
+
+        // background validation còn cần body của block dưới base snapshot
+        if self.snapshot_pending() {
+            return;
+        }
+
+        let tip_height = self.tip_height();
+        if self.db.stored_bytes() <= target || tip_height <= MIN_BLOCKS_TO_KEEP {
+            return;
+        }
+
+        let cutoff = tip_height - MIN_BLOCKS_TO_KEEP;
+        let start = self.db.pruned_height().map(|h| h + 1).unwrap_or(1);
+        let mut pruned_to = None;
+
+        for height in start..=cutoff {
+            if self.db.stored_bytes() <= target {
+                break;
+            }
+
+            let hash = self.block_hash_at(height).unwrap();
+            let entry = self.index.get_mut(&hash).unwrap();
+
+            if let Some(pos) = entry.data_pos.take() {
+                self.db.delete_block(pos);
+            }
+            self.db.delete_undo(&hash);
+
+            entry.status.clear(BlockStatus::HAVE_DATA | BlockStatus::HAVE_UNDO);
+            self.db.put_index(entry);
+            pruned_to = Some(height);
+        }
+
+        if let Some(height) = pruned_to {
+            self.db.set_pruned_height(height);
+            log::info!(
+                "pruned block data up to height {} ({} bytes stored)",
+                height,
+                self.db.stored_bytes()
+            );
+        }
+    }
+}
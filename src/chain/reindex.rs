@@ -0,0 +1,108 @@
+use crate::chain::error::ChainError;
+use crate::chain::hash::hash_header;
+use crate::chain::index::BlockStatus;
+use crate::chain::params::ChainParams;
+use crate::chain::state::ChainState;
+use crate::storage::sleddb::ChainDB;
+
+impl ChainState {
+    /// Dựng lại block index + UTXO set từ các block body đã lưu trong DB.
+    /// Block body được giữ nguyên vị trí, không ghi lại. Block bị đánh dấu
+    /// invalid (vd bởi invalidateblock) vẫn invalid sau khi dựng lại.
+    /// Record không decode được bị bỏ qua; trả kèm số record đã bỏ.
+    pub fn reindex(
+        params: ChainParams,
+        db: ChainDB,
+        mut progress: impl FnMut(u64, u64),
+    ) -> Result<(ChainState, u64), ChainError> {
+        // block đã prune không thể dựng lại từ disk
+        if db.pruned_height().is_some() {
+            return Err(ChainError::NeedsFullHistory("reindex"));
+        }
+        // snapshot đã bị chứng minh sai thì reindex là cách để bỏ nó
+        if db.get_snapshot().is_some() && !db.snapshot_invalid() {
+            return Err(ChainError::UnvalidatedSnapshot("reindex"));
+        }
+
+        let mut pending = db.block_positions();
+        let total = pending.len() as u64;
+
+        // index sắp bị xoá: giữ lại block invalid để đánh dấu lại sau khi replay
+        let mut skipped = 0u64;
+        let mut invalidated = Vec::new();
+        for entry in db.iter_index() {
+            match entry {
+                Ok(e) if e.status.has(BlockStatus::FAILED) => invalidated.push((e.height, e.hash)),
+                Ok(_) => {}
+                Err(e) => {
+                    log::warn!("reindex: skipping {}", e);
+                    skipped += 1;
+                }
+            }
+        }
+        invalidated.sort_unstable();
+
+        db.clear_chainstate();
+        let mut chain = ChainState::load_or_init(params, db);
+
+        // block có thể được lưu trước parent (headers-first): lặp tới khi hết tiến triển
+        let mut done = 0u64;
+        loop {
+            let mut retry = Vec::new();
+
+            for pos in pending.iter().copied() {
+                let block = match chain.db.get_block(pos) {
+                    Ok(Some(b)) => b,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        log::warn!("reindex: skipping {}", e);
+                        skipped += 1;
+                        done += 1;
+                        progress(done, total);
+                        continue;
+                    }
+                };
+                let hash = hash_header(&block.header);
+
+                // bản copy thừa (vd genesis vừa được ghi lại)
+                if let Some(entry) = chain.index.get(&hash) {
+                    if entry.data_pos.is_some() && entry.data_pos != Some(pos) {
+                        chain.db.delete_block(pos);
+                        done += 1;
+                        progress(done, total);
+                        continue;
+                    }
+                }
+
+                if !chain.index.contains(&block.header.prev_hash) {
+                    retry.push(pos);
+                    continue;
+                }
+
+                if let Err(e) = chain.accept_block(block, Some(pos)) {
+                    log::debug!("reindex: stored block at {} rejected: {}", pos, e);
+                }
+                done += 1;
+                progress(done, total);
+            }
+
+            if retry.is_empty() || retry.len() == pending.len() {
+                break;
+            }
+            pending = retry;
+        }
+
+        // block invalid thật bị replay phát hiện lại; block do invalidateblock
+        // thì phải gỡ khỏi active chain như lúc trước
+        for (_, hash) in invalidated {
+            if chain.index.contains(&hash) {
+                if let Err(e) = chain.invalidate_block(&hash) {
+                    log::warn!("cannot re-invalidate block {}: {}", hex::encode(hash), e);
+                }
+            }
+        }
+
+        chain.db.flush();
+        Ok((chain, skipped))
+    }
+}
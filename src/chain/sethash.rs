@@ -0,0 +1,200 @@
+//! Rolling hash của UTXO set (ECMH trên secp256k1).
+//!
+//! Mỗi coin được map thành 1 điểm trên curve, hash của set là tổng các điểm.
+//! Phép cộng giao hoán nên hash không phụ thuộc thứ tự thêm / xoá, và mỗi
+//! coin thêm / xoá chỉ tốn 1 phép cộng điểm.
+//!
+//! ```text
+//! point(coin) = điểm có x = sha256(encode(coin) | counter u32), y chẵn,
+//!               counter nhỏ nhất sao cho x nằm trên curve
+//! hash(set)   = sha256(compressed(Σ point(coin)))   ([0; 32] nếu set rỗng)
+//! ```
+
+use std::collections::HashMap;
+
+use secp256k1::PublicKey;
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+
+use crate::chain::encode::serialize;
+use crate::chain::utxo::UTXO;
+
+/// Điểm ứng với coin; `negate` = điểm đối xứng (cùng x, y lẻ) để xoá coin
+fn coin_point(coin: &UTXO, negate: bool) -> PublicKey {
+    let data = serialize(coin);
+    let mut buf = [0u8; 33];
+    buf[0] = if negate { 0x03 } else { 0x02 };
+
+    for counter in 0u32.. {
+        let mut hasher = Sha256::new();
+        hasher.update(&data);
+        hasher.update(counter.to_le_bytes());
+        buf[1..].copy_from_slice(&hasher.finalize());
+
+        if let Ok(point) = PublicKey::from_slice(&buf) {
+            return point;
+        }
+    }
+    unreachable!()
+}
+
+/// Tổng các điểm của UTXO set, None = điểm vô cực (set rỗng hoặc triệt tiêu)
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct UtxoSetHash(Option<PublicKey>);
+
+impl UtxoSetHash {
+    fn combine(&mut self, point: PublicKey) {
+        self.0 = match self.0 {
+            None => Some(point),
+            // combine lỗi khi tổng là điểm vô cực
+            Some(acc) => acc.combine(&point).ok(),
+        };
+    }
+
+    pub fn add(&mut self, coin: &UTXO) {
+        self.combine(coin_point(coin, false));
+    }
+
+    pub fn remove(&mut self, coin: &UTXO) {
+        self.combine(coin_point(coin, true));
+    }
+
+    pub fn digest(&self) -> [u8; 32] {
+        match self.0 {
+            Some(p) => Sha256::digest(p.serialize()).into(),
+            None => [0u8; 32],
+        }
+    }
+}
+
+/// Thống kê UTXO set tại 1 block, lưu theo block hash
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct UtxoStats {
+    pub hash: UtxoSetHash,
+    pub coins: u64,
+    pub total_amount: u64,
+}
+
+impl UtxoStats {
+    pub fn from_set(utxos: &HashMap<([u8; 32], u32), UTXO>) -> Self {
+        let mut stats = UtxoStats::default();
+        for coin in utxos.values() {
+            stats.add(coin);
+        }
+        stats
+    }
+
+    pub fn add(&mut self, coin: &UTXO) {
+        self.hash.add(coin);
+        self.coins = self.coins.checked_add(1).expect("utxo count overflow");
+        self.total_amount = self.total_amount.checked_add(coin.value).expect("utxo amount overflow");
+    }
+
+    /// Coin phải đang nằm trong set, nếu không stats đã hỏng
+    pub fn remove(&mut self, coin: &UTXO) {
+        self.hash.remove(coin);
+        self.coins = self.coins.checked_sub(1).expect("removing coin from empty utxo stats");
+        self.total_amount = self.total_amount.checked_sub(coin.value).expect("utxo amount underflow");
+    }
+}
+
+/// Dạng lưu trên disk: điểm ghi compressed (rỗng = vô cực)
+#[derive(Serialize, Deserialize)]
+struct StoredStats {
+    point: Vec<u8>,
+    coins: u64,
+    total_amount: u64,
+}
+
+impl Serialize for UtxoStats {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        StoredStats {
+            point: self.hash.0.map(|p| p.serialize().to_vec()).unwrap_or_default(),
+            coins: self.coins,
+            total_amount: self.total_amount,
+        }
+        .serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for UtxoStats {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let stored = StoredStats::deserialize(d)?;
+        let point = if stored.point.is_empty() {
+            None
+        } else {
+            Some(PublicKey::from_slice(&stored.point).map_err(serde::de::Error::custom)?)
+        };
+
+        Ok(UtxoStats {
+            hash: UtxoSetHash(point),
+            coins: stored.coins,
+            total_amount: stored.total_amount,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coin(n: u8, value: u64) -> UTXO {
+        UTXO {
+            txid: [n; 32],
+            vout: n as u32,
+            value,
+            address: vec![n; 20],
+            height: n as u64,
+        }
+    }
+
+    #[test]
+    fn add_then_remove_is_empty() {
+        let coins = [coin(1, 50), coin(2, 7), coin(3, 1_000)];
+        let mut stats = UtxoStats::default();
+        for c in &coins {
+            stats.add(c);
+        }
+        assert_ne!(stats.hash.digest(), [0u8; 32]);
+
+        for c in &coins {
+            stats.remove(c);
+        }
+        assert_eq!(stats, UtxoStats::default());
+        assert_eq!(stats.hash.digest(), [0u8; 32]);
+    }
+
+    #[test]
+    fn order_independent() {
+        let coins = [coin(1, 50), coin(2, 7), coin(3, 1_000), coin(4, 0)];
+
+        let mut forward = UtxoStats::default();
+        coins.iter().for_each(|c| forward.add(c));
+
+        let mut backward = UtxoStats::default();
+        coins.iter().rev().for_each(|c| backward.add(c));
+
+        // thêm rồi xoá xen kẽ cũng ra cùng set
+        let mut mixed = UtxoStats::default();
+        mixed.add(&coins[2]);
+        mixed.add(&coin(9, 5));
+        mixed.add(&coins[0]);
+        mixed.remove(&coin(9, 5));
+        mixed.add(&coins[3]);
+        mixed.add(&coins[1]);
+
+        assert_eq!(forward, backward);
+        assert_eq!(forward, mixed);
+        assert_eq!(forward.hash.digest(), mixed.hash.digest());
+        assert_eq!(forward.coins, 4);
+        assert_eq!(forward.total_amount, 1_057);
+    }
+
+    #[test]
+    #[should_panic(expected = "utxo amount overflow")]
+    fn amount_overflow_panics() {
+        let mut stats = UtxoStats::default();
+        stats.add(&coin(1, u64::MAX));
+        stats.add(&coin(2, 1));
+    }
+}
@@ -1,5 +1,6 @@
 use secp256k1::{Secp256k1, SecretKey, PublicKey, Message};
 use sha2::{Sha256, Digest};
+use crate::chain::encode::serialize;
 use crate::chain::tx::Transaction;
 
 pub fn sign_tx(tx: &Transaction, sk: &SecretKey) -> Vec<u8> {
@@ -31,7 +32,69 @@ pub fn verify_tx(tx: &Transaction) -> bool {
     true
 }
 
-fn tx_hash(tx: &Transaction) -> [u8; 32] {
-    let encoded = bincode::serialize(tx).unwrap();
-    Sha256::digest(encoded).into()
+/// Sighash: consensus encoding của tx với mọi signature để trống. Bên ký
+/// hash tx lúc chưa có chữ ký, nên bên kiểm tra cũng phải bỏ chữ ký ra
+/// (chữ ký không thể tự ký chính nó)
+pub fn tx_hash(tx: &Transaction) -> [u8; 32] {
+    let mut unsigned = tx.clone();
+    for inp in &mut unsigned.inputs {
+        inp.signature.clear();
+    }
+    Sha256::digest(serialize(&unsigned)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::tx::{TxInput, TxOutput};
+
+    fn signed_tx(sk: &SecretKey) -> Transaction {
+        let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), sk);
+        let mut tx = Transaction {
+            inputs: (0..2u8)
+                .map(|i| TxInput {
+                    prev_txid: [i; 32],
+                    vout: i as u32,
+                    signature: vec![],
+                    pubkey: pubkey.serialize().to_vec(),
+                })
+                .collect(),
+            outputs: vec![TxOutput { value: 50, to_address: b"addr".to_vec() }],
+            data: vec![],
+        };
+        let sig = sign_tx(&tx, sk);
+        for inp in &mut tx.inputs {
+            inp.signature = sig.clone();
+        }
+        tx
+    }
+
+    #[test]
+    fn signed_tx_verifies() {
+        let sk = SecretKey::from_slice(&[7u8; 32]).unwrap();
+        let tx = signed_tx(&sk);
+        assert!(verify_tx(&tx));
+
+        // sửa nội dung thì chữ ký không còn khớp
+        let mut changed = tx.clone();
+        changed.outputs[0].value += 1;
+        assert!(!verify_tx(&changed));
+    }
+
+    #[test]
+    fn signature_not_in_sighash() {
+        let sk = SecretKey::from_slice(&[7u8; 32]).unwrap();
+        let tx = signed_tx(&sk);
+
+        let mut unsigned = tx.clone();
+        for inp in &mut unsigned.inputs {
+            inp.signature.clear();
+        }
+        let mut resigned = tx.clone();
+        resigned.inputs[1].signature = vec![0xaa; 71];
+
+        assert_eq!(tx_hash(&tx), tx_hash(&unsigned));
+        assert_eq!(tx_hash(&tx), tx_hash(&resigned));
+        assert!(!verify_tx(&resigned));
+    }
 }
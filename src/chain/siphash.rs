@@ -0,0 +1,64 @@
+/// SipHash-2-4 với key 128 bit (k0, k1), dùng cho compact block filter
+/// và short ID của compact block
+pub fn siphash24(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    let mut v0 = 0x736f6d6570736575 ^ k0;
+    let mut v1 = 0x646f72616e646f6d ^ k1;
+    let mut v2 = 0x6c7967656e657261 ^ k0;
+    let mut v3 = 0x7465646279746573 ^ k1;
+
+    macro_rules! round {
+        () => {
+            v0 = v0.wrapping_add(v1);
+            v1 = v1.rotate_left(13);
+            v1 ^= v0;
+            v0 = v0.rotate_left(32);
+            v2 = v2.wrapping_add(v3);
+            v3 = v3.rotate_left(16);
+            v3 ^= v2;
+            v0 = v0.wrapping_add(v3);
+            v3 = v3.rotate_left(21);
+            v3 ^= v0;
+            v2 = v2.wrapping_add(v1);
+            v1 = v1.rotate_left(17);
+            v1 ^= v2;
+            v2 = v2.rotate_left(32);
+        };
+    }
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let m = u64::from_le_bytes(chunk.try_into().unwrap());
+        v3 ^= m;
+        round!();
+        round!();
+        v0 ^= m;
+    }
+
+    // block cuối: byte còn lại + độ dài (mod 256) ở byte cao nhất
+    let mut last = [0u8; 8];
+    let rest = chunks.remainder();
+    last[..rest.len()].copy_from_slice(rest);
+    last[7] = data.len() as u8;
+    let m = u64::from_le_bytes(last);
+
+    v3 ^= m;
+    round!();
+    round!();
+    v0 ^= m;
+
+    v2 ^= 0xff;
+    round!();
+    round!();
+    round!();
+    round!();
+
+    v0 ^ v1 ^ v2 ^ v3
+}
+
+/// Key SipHash từ 16 byte (2 số u64 little-endian)
+pub fn siphash_key(bytes: &[u8; 16]) -> (u64, u64) {
+    (
+        u64::from_le_bytes(bytes[..8].try_into().unwrap()),
+        u64::from_le_bytes(bytes[8..].try_into().unwrap()),
+    )
+}
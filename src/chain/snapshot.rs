@@ -0,0 +1,439 @@
+//! Snapshot UTXO set (dumptxoutset / loadtxoutset).
+//!
+//! Node mới có thể bắt đầu từ 1 snapshot đã được công bố trong ChainParams
+//! thay vì connect lại từ genesis. Phần lịch sử phía dưới base của snapshot
+//! được validate dần ở background; khi tới base, UTXO set dựng lại phải có
+//! cùng content hash với snapshot.
+//!
+//! ```text
+//! file   = magic "utxo" | version u32 | base_hash 32 | height u64
+//!          | coin_count u64 | content_hash 32
+//!          | varint(n) BlockHeader*          (height 1..=height)
+//!          | Coin * coin_count
+//! Coin   = txid 32 | vout u32 | value u64 | var_bytes(address) | height u64
+//! ```
+//!
+//! Content hash = sha256 của mọi Coin đã encode, sắp xếp theo (txid, vout).
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+
+use crate::chain::encode::{
+    read_list, read_var_bytes, write_list, write_var_bytes, Decodable, DecodeError, Encodable,
+    Reader,
+};
+use crate::chain::hash::hash_header;
+use crate::chain::header::BlockHeader;
+use crate::chain::index::BlockStatus;
+use crate::chain::sethash::UtxoStats;
+use crate::chain::state::ChainState;
+use crate::chain::utxo::UTXO;
+
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"utxo";
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Thông tin snapshot, ghi ở đầu file và lưu trong DB khi đang dùng snapshot
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotMetadata {
+    pub base_hash: [u8; 32],
+    pub height: u64,
+    pub coin_count: u64,
+    pub content_hash: [u8; 32],
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(std::io::Error),
+    Decode(DecodeError),
+    BadMagic,
+    UnsupportedVersion(u32),
+    /// Snapshot không có trong danh sách assumeutxo của network
+    UnknownSnapshot([u8; 32]),
+    /// Dữ liệu trong file không khớp với header / assumeutxo
+    Mismatch(&'static str),
+    /// Header trong snapshot không nối được vào chain
+    InvalidHeaders,
+    /// Chain đã có block ngoài genesis
+    ChainNotEmpty,
+    /// Đang có snapshot chưa validate xong
+    AlreadyLoaded,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "io error: {}", e),
+            SnapshotError::Decode(e) => write!(f, "malformed snapshot: {}", e),
+            SnapshotError::BadMagic => write!(f, "not a utxo snapshot file"),
+            SnapshotError::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {}", v),
+            SnapshotError::UnknownSnapshot(h) => {
+                write!(f, "no assumeutxo data for block {}", hex::encode(h))
+            }
+            SnapshotError::Mismatch(what) => write!(f, "snapshot {} mismatch", what),
+            SnapshotError::InvalidHeaders => write!(f, "snapshot headers do not connect"),
+            SnapshotError::ChainNotEmpty => {
+                write!(f, "chain already has blocks beyond genesis")
+            }
+            SnapshotError::AlreadyLoaded => {
+                write!(f, "a snapshot is already loaded and not yet validated")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(e: std::io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+impl From<DecodeError> for SnapshotError {
+    fn from(e: DecodeError) -> Self {
+        SnapshotError::Decode(e)
+    }
+}
+
+impl Encodable for UTXO {
+    fn consensus_encode(&self, out: &mut Vec<u8>) {
+        self.txid.consensus_encode(out);
+        self.vout.consensus_encode(out);
+        self.value.consensus_encode(out);
+        write_var_bytes(out, &self.address);
+        self.height.consensus_encode(out);
+    }
+}
+
+impl Decodable for UTXO {
+    fn consensus_decode(r: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(UTXO {
+            txid: Decodable::consensus_decode(r)?,
+            vout: Decodable::consensus_decode(r)?,
+            value: Decodable::consensus_decode(r)?,
+            address: read_var_bytes(r)?,
+            height: Decodable::consensus_decode(r)?,
+        })
+    }
+}
+
+/// Coin sắp theo (txid, vout) để file + content hash không phụ thuộc thứ tự HashMap
+fn sorted_coins(utxos: &HashMap<([u8; 32], u32), UTXO>) -> Vec<&UTXO> {
+    let mut keys: Vec<&([u8; 32], u32)> = utxos.keys().collect();
+    keys.sort();
+    keys.into_iter().map(|k| &utxos[k]).collect()
+}
+
+pub fn utxo_content_hash(utxos: &HashMap<([u8; 32], u32), UTXO>) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    let mut buf = Vec::new();
+    for coin in sorted_coins(utxos) {
+        buf.clear();
+        coin.consensus_encode(&mut buf);
+        hasher.update(&buf);
+    }
+    hasher.finalize().into()
+}
+
+/// Trạng thái validate lịch sử từ genesis tới base của snapshot
+pub(crate) struct BackgroundValidation {
+    meta: SnapshotMetadata,
+    /// Block cuối cùng đã validate
+    height: u64,
+    utxos: HashMap<([u8; 32], u32), UTXO>,
+    stats: UtxoStats,
+}
+
+/* =========================
+   DUMP / LOAD
+   ========================= */
+
+impl ChainState {
+    /// Ghi UTXO set tại tip ra file, trả về metadata để công bố trong ChainParams
+    pub fn dump_txoutset(&self, path: &Path) -> Result<SnapshotMetadata, SnapshotError> {
+        let height = self.tip_height();
+        let meta = SnapshotMetadata {
+            base_hash: self.tip,
+            height,
+            coin_count: self.utxos.len() as u64,
+            content_hash: utxo_content_hash(&self.utxos),
+        };
+
+        let headers: Vec<BlockHeader> = (1..=height)
+            .map(|h| self.index.get_ancestor(&self.tip, h).unwrap().header.clone())
+            .collect();
+
+        let mut out = SNAPSHOT_MAGIC.to_vec();
+        SNAPSHOT_VERSION.consensus_encode(&mut out);
+        meta.base_hash.consensus_encode(&mut out);
+        meta.height.consensus_encode(&mut out);
+        meta.coin_count.consensus_encode(&mut out);
+        meta.content_hash.consensus_encode(&mut out);
+        write_list(&mut out, &headers);
+        for coin in sorted_coins(&self.utxos) {
+            coin.consensus_encode(&mut out);
+        }
+
+        // ghi file tạm rồi rename để không để lại snapshot dở dang
+        let tmp = path.with_extension("incomplete");
+        fs::write(&tmp, &out)?;
+        fs::rename(&tmp, path)?;
+        Ok(meta)
+    }
+
+    /// Dùng snapshot làm UTXO set, tip nhảy thẳng tới base của snapshot.
+    /// Chỉ chấp nhận snapshot có trong `params.assumeutxo`.
+    pub fn load_txoutset(&mut self, path: &Path) -> Result<SnapshotMetadata, SnapshotError> {
+        if self.db.get_snapshot().is_some() {
+            return Err(SnapshotError::AlreadyLoaded);
+        }
+        if self.tip_height() != 0 {
+            return Err(SnapshotError::ChainNotEmpty);
+        }
+
+        let bytes = fs::read(path)?;
+        let mut r = Reader::new(&bytes);
+
+        if r.take(4)? != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = u32::consensus_decode(&mut r)?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let meta = SnapshotMetadata {
+            base_hash: Decodable::consensus_decode(&mut r)?,
+            height: Decodable::consensus_decode(&mut r)?,
+            coin_count: Decodable::consensus_decode(&mut r)?,
+            content_hash: Decodable::consensus_decode(&mut r)?,
+        };
+
+        let expected = self
+            .params
+            .assumeutxo_for(&meta.base_hash)
+            .ok_or(SnapshotError::UnknownSnapshot(meta.base_hash))?;
+        if expected.height != meta.height {
+            return Err(SnapshotError::Mismatch("height"));
+        }
+        if expected.coin_count != meta.coin_count {
+            return Err(SnapshotError::Mismatch("coin count"));
+        }
+        if expected.content_hash != meta.content_hash {
+            return Err(SnapshotError::Mismatch("content hash"));
+        }
+
+        let headers: Vec<BlockHeader> = read_list(&mut r)?;
+        if headers.len() as u64 != meta.height
+            || headers.last().map(hash_header).unwrap_or(self.tip) != meta.base_hash
+        {
+            return Err(SnapshotError::Mismatch("header chain"));
+        }
+
+        let mut coins = HashMap::new();
+        for _ in 0..meta.coin_count {
+            let coin = UTXO::consensus_decode(&mut r)?;
+            coins.insert((coin.txid, coin.vout), coin);
+        }
+        if r.remaining() != 0 {
+            return Err(DecodeError::TrailingBytes(r.remaining()).into());
+        }
+        if coins.len() as u64 != meta.coin_count {
+            return Err(SnapshotError::Mismatch("coin count"));
+        }
+        if utxo_content_hash(&coins) != meta.content_hash {
+            return Err(SnapshotError::Mismatch("content hash"));
+        }
+
+        for header in &headers {
+            if !self.accept_header(header) {
+                return Err(SnapshotError::InvalidHeaders);
+            }
+        }
+
+        // ---- áp dụng: từ đây không còn lỗi ----
+
+        let old: Vec<([u8; 32], u32)> = self.utxos.keys().copied().collect();
+        for (txid, vout) in old {
+            self.db.delete_utxo(&txid, vout);
+        }
+        for coin in coins.values() {
+            self.db.put_utxo(coin);
+        }
+        self.utxos = coins;
+        self.utxo_stats = UtxoStats::from_set(&self.utxos);
+        self.db.put_utxo_stats(&meta.base_hash, &self.utxo_stats);
+
+        // chain_tx thật chưa biết khi chưa có body: giả định 1 tx / block,
+        // link_block tính lại khi body tới
+        for h in 1..=meta.height {
+            let hash = self.index.get_ancestor(&meta.base_hash, h).unwrap().hash;
+            let entry = self.index.get_mut(&hash).unwrap();
+            entry.status.set(BlockStatus::ASSUMED_VALID);
+            entry.chain_tx = h + 1;
+            self.db.put_index(entry);
+        }
+
+        self.tip = meta.base_hash;
+        self.db.set_tip(&meta.base_hash, meta.height);
+        self.db.put_snapshot(&meta);
+        self.rebuild_candidates();
+        self.resume_snapshot_validation();
+        self.notify_tip_changed();
+
+        log::info!(
+            "loaded utxo snapshot at height {} ({} coins)",
+            meta.height,
+            meta.coin_count
+        );
+        Ok(meta)
+    }
+}
+
+/* =========================
+   BACKGROUND VALIDATION
+   ========================= */
+
+impl ChainState {
+    /// Còn snapshot chưa được xác nhận bằng lịch sử từ genesis
+    pub fn snapshot_pending(&self) -> bool {
+        self.background.is_some()
+    }
+
+    /// (base của snapshot, height block tiếp theo background validation cần)
+    pub(crate) fn snapshot_download_start(&self) -> Option<([u8; 32], u64)> {
+        self.background.as_ref().map(|bg| (bg.meta.base_hash, bg.height + 1))
+    }
+
+    /// Block dưới base snapshot chưa có body: nhận cả khi không yêu cầu
+    pub fn needs_snapshot_block(&self, hash: &[u8; 32]) -> bool {
+        self.background.is_some()
+            && self
+                .index
+                .get(hash)
+                .is_some_and(|e| e.status.has(BlockStatus::ASSUMED_VALID) && !e.status.has(BlockStatus::HAVE_DATA))
+    }
+
+    /// Tiếp tục background validation từ tiến độ đã lưu trong DB, chưa có
+    /// thì bắt đầu từ genesis
+    pub(crate) fn resume_snapshot_validation(&mut self) {
+        let meta = match self.db.get_snapshot() {
+            Some(m) if !self.db.snapshot_invalid() => m,
+            _ => return,
+        };
+
+        let (height, coins) = match self.db.get_background_height() {
+            Some(height) => (height, self.db.iter_background_utxos()),
+            None => {
+                let genesis = self.block_hash_at(0).unwrap();
+                let undo = self.db.get_undo(&genesis).ok().flatten().expect("genesis undo missing");
+                self.db.begin_batch();
+                for coin in &undo.created {
+                    self.db.put_background_utxo(coin);
+                }
+                self.db.set_background_height(0);
+                self.db.commit_batch();
+                (0, undo.created)
+            }
+        };
+
+        let utxos = coins.into_iter().map(|u| ((u.txid, u.vout), u)).collect();
+        if height > 0 {
+            log::info!("resuming utxo snapshot validation at height {}", height + 1);
+        }
+        self.background = Some(BackgroundValidation {
+            meta,
+            height,
+            stats: UtxoStats::from_set(&utxos),
+            utxos,
+        });
+    }
+
+    /// Validate tối đa `max_blocks` block tiếp theo dưới base của snapshot.
+    /// Dừng khi gặp block chưa có body. Trả về số block đã validate.
+    /// Snapshot bị chứng minh sai thì đặt `db.snapshot_invalid()`; người gọi
+    /// phải dừng node ngay vì UTXO set đang dùng không đúng.
+    pub fn validate_snapshot_step(&mut self, max_blocks: u64) -> u64 {
+        let mut bg = match self.background.take() {
+            Some(bg) => bg,
+            None => return 0,
+        };
+
+        let mut done = 0;
+        while done < max_blocks && bg.height < bg.meta.height {
+            let height = bg.height + 1;
+            let hash = self.index.get_ancestor(&bg.meta.base_hash, height).unwrap().hash;
+
+            let block = match self.get_block(&hash) {
+                Some(b) => b,
+                None => break,
+            };
+
+            let undo = match Self::check_block_inputs(&bg.utxos, &block, height) {
+                Some(u) => u,
+                None => {
+                    log::error!(
+                        "snapshot validation failed: block {} at height {} is invalid",
+                        hex::encode(hash),
+                        height
+                    );
+                    self.db.set_snapshot_invalid();
+                    return done;
+                }
+            };
+
+            // UTXO set + height đã validate ghi cùng batch để restart tiếp tục đúng chỗ
+            self.db.begin_batch();
+            for u in &undo.spent {
+                bg.utxos.remove(&(u.txid, u.vout));
+                bg.stats.remove(u);
+                self.db.delete_background_utxo(&u.txid, u.vout);
+            }
+            for u in &undo.created {
+                bg.utxos.insert((u.txid, u.vout), u.clone());
+                bg.stats.add(u);
+                self.db.put_background_utxo(u);
+            }
+            self.db.put_undo(&hash, &undo);
+            self.db.put_utxo_stats(&hash, &bg.stats);
+            self.index_filter(&hash, &block);
+
+            let entry = self.index.get_mut(&hash).unwrap();
+            entry.status.set(BlockStatus::HAVE_UNDO | BlockStatus::FULLY_VALID);
+            entry.status.clear(BlockStatus::ASSUMED_VALID);
+            self.db.put_index(entry);
+            self.db.set_background_height(height);
+            self.db.commit_batch();
+
+            bg.height = height;
+            done += 1;
+        }
+
+        if bg.height < bg.meta.height {
+            self.background = Some(bg);
+            return done;
+        }
+
+        if utxo_content_hash(&bg.utxos) == bg.meta.content_hash {
+            log::info!("utxo snapshot at height {} validated", bg.meta.height);
+            self.db.begin_batch();
+            self.db.clear_snapshot();
+            self.db.clear_background();
+            self.db.commit_batch();
+            // filter header phía trên base chờ header của base
+            self.fill_filter_headers(bg.meta.height + 1);
+        } else {
+            log::error!(
+                "snapshot validation failed: utxo set at height {} does not match snapshot",
+                bg.meta.height
+            );
+            self.db.set_snapshot_invalid();
+        }
+        done
+    }
+}
@@ -1,29 +1,47 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
-use crate::chain::block::Block;
-use crate::chain::header::BlockHeader;
+use crate::chain::block::{Block, merkle_root};
+use crate::chain::error::{BlockError, ChainError};
+use crate::chain::params::ChainParams;
 use crate::chain::hash::hash_header;
+use crate::chain::index::{BlockIndex, BlockIndexEntry, BlockStatus};
+use crate::chain::reward::BLOCK_REWARD;
+use crate::chain::sethash::UtxoStats;
+use crate::chain::sign::verify_tx;
+use crate::chain::snapshot::BackgroundValidation;
 use crate::chain::txid::txid;
 use crate::chain::utxo::UTXO;
 use crate::chain::undo::BlockUndo;
+use crate::events::{ChainEvent, EventBus};
 use crate::storage::sleddb::ChainDB;
-use crate::pow::verify::verify_pow;
 use crate::pow::work::work_from_bits;
 
-#[derive(Clone)]
-pub struct BlockMeta {
-    pub block: Block,
-    pub parent: [u8; 32],
-    pub height: u64,
-    pub total_work: u128,
-    pub undo: BlockUndo,
-}
-
 pub struct ChainState {
-    pub blocks: HashMap<[u8; 32], BlockMeta>,
+    pub index: BlockIndex,
     pub tip: [u8; 32],
     pub utxos: HashMap<([u8; 32], u32), UTXO>,
+    /// Số coin, tổng giá trị và rolling hash của `utxos`
+    pub utxo_stats: UtxoStats,
     pub db: ChainDB,
+    pub params: ChainParams,
+    /// Header most-work đã biết, có thể vượt xa tip khi đang tải body (xem chain::header_chain)
+    pub(crate) best_header: [u8; 32],
+    /// Block có đủ body tới genesis, ứng viên cho best chain (luôn chứa tip)
+    candidates: HashSet<[u8; 32]>,
+    /// parent -> block con đã có body nhưng phía trước còn thiếu body
+    unlinked: HashMap<[u8; 32], Vec<[u8; 32]>>,
+    /// Ngưỡng dung lượng block + undo khi chạy prune (None = giữ toàn bộ)
+    pub(crate) prune_target: Option<u64>,
+    /// Đang duy trì txindex / addressindex (xem chain::txindex)
+    pub(crate) txindex: bool,
+    pub(crate) addressindex: bool,
+    /// Validate lịch sử dưới base của snapshot UTXO set (xem chain::snapshot)
+    pub(crate) background: Option<BackgroundValidation>,
+    /// Phát block connected / disconnected / tip changed cho subscriber
+    pub events: EventBus,
+    /// Tip đã báo qua on_tip_changed lần gần nhất
+    notified_tip: [u8; 32],
 }
 
 /* =========================
@@ -31,79 +49,227 @@ pub struct ChainState {
    ========================= */
 
 impl ChainState {
-    pub fn load_or_init(genesis: Block, db: ChainDB) -> Self {
-        let genesis_hash = hash_header(&genesis.header);
+    pub fn load_or_init(params: ChainParams, db: ChainDB) -> Self {
+        let mut entries: Vec<BlockIndexEntry> = db
+            .iter_index()
+            .collect::<Result<_, _>>()
+            .unwrap_or_else(|e| panic!("{}, run reindex", e));
+        let db_flags = (db.has_txindex(), db.has_addressindex());
+
+        let mut chain = ChainState {
+            index: BlockIndex::new(),
+            tip: [0u8; 32],
+            utxos: HashMap::new(),
+            utxo_stats: UtxoStats::default(),
+            db,
+            params,
+            best_header: [0u8; 32],
+            candidates: HashSet::new(),
+            unlinked: HashMap::new(),
+            prune_target: None,
+            txindex: db_flags.0,
+            addressindex: db_flags.1,
+            background: None,
+            events: EventBus::new(),
+            notified_tip: [0u8; 32],
+        };
 
-        if db.get_tip().is_none() {
-            let mut utxos = HashMap::new();
+        if entries.is_empty() {
+            let genesis = chain.params.genesis_block();
+            chain.init_genesis(genesis);
+            return chain;
+        }
 
-            let coinbase = &genesis.transactions[0];
-            let cbid = txid(coinbase);
+        // parent phải vào index trước con để tính skip pointer
+        entries.sort_by_key(|e| e.height);
+        for entry in entries {
+            if entry.status.has(BlockStatus::HAVE_DATA) && entry.chain_tx == 0 {
+                chain.unlinked
+                    .entry(entry.header.prev_hash)
+                    .or_default()
+                    .push(entry.hash);
+            }
+            if entry.chain_tx > 0 && !entry.status.is_failed() {
+                chain.candidates.insert(entry.hash);
+            }
+            chain.index.insert(entry);
+        }
 
-            for (vout, out) in coinbase.outputs.iter().enumerate() {
-                let utxo = UTXO {
-                    txid: cbid,
-                    vout: vout as u32,
-                    value: out.value,
-                    address: out.to_address.clone(),
-                    height: 0,
-                };
-                utxos.insert((utxo.txid, utxo.vout), utxo);
+        for utxo in chain.db.iter_utxos() {
+            chain.utxos.insert((utxo.txid, utxo.vout), utxo);
+        }
+
+        let (tip, _) = chain.db.get_tip().expect("block index without tip");
+        chain.tip = tip;
+        // DB cũ chưa có stats: tính lại 1 lần từ UTXO set
+        chain.utxo_stats = match chain.db.get_utxo_stats(&tip) {
+            Some(stats) => stats,
+            None => {
+                let stats = UtxoStats::from_set(&chain.utxos);
+                chain.db.put_utxo_stats(&tip, &stats);
+                stats
             }
+        };
+        chain.candidates.insert(tip);
+        chain.recompute_best_header();
+
+        chain.notified_tip = tip;
+        chain.backfill_filters();
+        chain.activate_best_chain();
+        chain.resume_snapshot_validation();
+        chain
+    }
 
-            let meta = BlockMeta {
-                block: genesis.clone(),
-                parent: [0u8; 32],
-                height: 0,
-                total_work: 0,
-                undo: BlockUndo {
-                    spent: vec![],
-                    created: vec![],
-                },
-            };
-
-            let mut blocks = HashMap::new();
-            blocks.insert(genesis_hash, meta);
-
-            db.put_block(&genesis_hash, &genesis);
-            db.set_tip(&genesis_hash, 0);
-
-            return ChainState {
-                blocks,
-                tip: genesis_hash,
-                utxos,
-                db,
-            };
-        }
-
-        let (tip, _) = db.get_tip().unwrap();
-        let block = db.get_block(&tip).unwrap();
-
-        let mut blocks = HashMap::new();
-        blocks.insert(
-            tip,
-            BlockMeta {
-                block: block.clone(),
-                parent: block.header.prev_hash,
+    fn init_genesis(&mut self, genesis: Block) {
+        let hash = hash_header(&genesis.header);
+        let pos = self.db.put_block(&genesis);
+
+        let mut undo = BlockUndo::new();
+        let coinbase = &genesis.transactions[0];
+        let cbid = txid(coinbase);
+
+        for (vout, out) in coinbase.outputs.iter().enumerate() {
+            undo.created.push(UTXO {
+                txid: cbid,
+                vout: vout as u32,
+                value: out.value,
+                address: out.to_address.clone(),
                 height: 0,
-                total_work: 0,
-                undo: BlockUndo {
-                    spent: vec![],
-                    created: vec![],
-                },
-            },
-        );
+            });
+        }
 
-        ChainState {
-            blocks,
-            tip,
-            utxos: HashMap::new(),
-            db,
+        self.apply_block(&undo);
+        self.db.put_undo(&hash, &undo);
+        self.db.put_utxo_stats(&hash, &self.utxo_stats);
+
+        let mut status = BlockStatus::default();
+        status.set(BlockStatus::HEADER_VALID);
+        status.set(BlockStatus::HAVE_DATA);
+        status.set(BlockStatus::HAVE_UNDO);
+        status.set(BlockStatus::FULLY_VALID);
+
+        let entry = BlockIndexEntry {
+            hash,
+            header: genesis.header.clone(),
+            height: 0,
+            chainwork: work_from_bits(genesis.header.bits),
+            status,
+            data_pos: Some(pos),
+            tx_count: genesis.transactions.len() as u64,
+            chain_tx: genesis.transactions.len() as u64,
+            skip: None,
+        };
+
+        self.db.put_index(&entry);
+        self.index.insert(entry);
+        self.index_filter(&hash, &genesis);
+        self.candidates.insert(hash);
+
+        self.tip = hash;
+        self.best_header = hash;
+        self.notified_tip = hash;
+        self.db.set_tip(&hash, 0);
+    }
+
+    pub fn tip_entry(&self) -> &BlockIndexEntry {
+        self.index.get(&self.tip).expect("tip not in index")
+    }
+
+    pub fn tip_height(&self) -> u64 {
+        self.tip_entry().height
+    }
+
+    /// Hash của block ở `height` trên active chain
+    pub fn block_hash_at(&self, height: u64) -> Option<[u8; 32]> {
+        self.index.get_ancestor(&self.tip, height).map(|e| e.hash)
+    }
+
+    /// Đọc block body từ disk
+    pub fn get_block(&self, hash: &[u8; 32]) -> Option<Block> {
+        let pos = self.index.get(hash)?.data_pos?;
+        match self.db.get_block(pos) {
+            Ok(block) => block,
+            Err(e) => {
+                log::error!("{} for block {}", e, hex::encode(hash));
+                None
+            }
+        }
+    }
+
+    /// Block body được phép gửi cho peer: đã connect hợp lệ hoặc nằm trên active chain
+    pub fn get_servable_block(&self, hash: &[u8; 32]) -> Option<Block> {
+        let entry = self.index.get(hash)?;
+        let servable = !entry.status.is_failed()
+            && (entry.status.has(BlockStatus::FULLY_VALID) || self.index.is_ancestor(hash, &self.tip));
+        if !servable {
+            return None;
         }
+        self.get_block(hash)
     }
+}
+
+/* =========================
+   OPERATOR CONTROLS
+   ========================= */
+
+impl ChainState {
+    /// Đánh dấu block + con cháu invalid, reorg sang best chain còn lại
+    pub fn invalidate_block(&mut self, hash: &[u8; 32]) -> Result<(), ChainError> {
+        let entry = self.index.get(hash).ok_or(ChainError::UnknownBlock(*hash))?;
+        if entry.height == 0 {
+            return Err(ChainError::GenesisBlock);
+        }
+
+        if self.index.is_ancestor(hash, &self.tip) {
+            let parent = entry.header.prev_hash;
+            self.check_reorg_data(&parent, &parent)?;
+
+            while self.index.is_ancestor(hash, &self.tip) {
+                self.disconnect_tip()?;
+            }
+        }
+
+        self.mark_failed(hash);
+        self.rebuild_candidates();
+        self.activate_best_chain();
+        Ok(())
+    }
+
+    /// Gỡ trạng thái invalid của block, con cháu và ancestor của nó
+    pub fn reconsider_block(&mut self, hash: &[u8; 32]) -> Result<(), ChainError> {
+        if !self.index.contains(hash) {
+            return Err(ChainError::UnknownBlock(*hash));
+        }
+
+        let mut affected = self.index.descendants(hash);
+        affected.push(*hash);
+
+        let mut walk = self.index.get(hash).unwrap().header.prev_hash;
+        while let Some(entry) = self.index.get(&walk) {
+            affected.push(entry.hash);
+            walk = entry.header.prev_hash;
+        }
+
+        // chỉ block vừa gỡ invalid mới có thể thành candidate / best header mới
+        for h in affected {
+            let entry = self.index.get_mut(&h).unwrap();
+            if !entry.status.is_failed() {
+                continue;
+            }
+            entry.status.clear(BlockStatus::FAILED | BlockStatus::FAILED_PARENT);
+            self.db.put_index(entry);
 
-    pub fn accept_header(&self, header: &BlockHeader) -> bool {
-        verify_pow(header) && self.blocks.contains_key(&header.prev_hash)
+            let entry = self.index.get(&h).unwrap();
+            if entry.chain_tx > 0 {
+                self.candidates.insert(h);
+            }
+            if Self::is_better(entry, self.index.get(&self.best_header).unwrap()) {
+                self.best_header = h;
+            }
+        }
+
+        self.activate_best_chain();
+        Ok(())
     }
 }
 
@@ -113,141 +279,418 @@ impl ChainState {
 
 impl ChainState {
     pub fn add_block(&mut self, block: Block) -> bool {
-        if !verify_pow(&block.header) {
-            return false;
+        self.accept_block(block, None).is_ok()
+    }
+
+    /// `stored_pos`: block đã nằm trên disk ở vị trí này (reindex), không ghi lại
+    pub(crate) fn accept_block(&mut self, block: Block, stored_pos: Option<u64>) -> Result<(), BlockError> {
+        let hash = self.process_header(&block.header).map_err(BlockError::Header)?;
+        if self.index.get(&hash).unwrap().status.has(BlockStatus::HAVE_DATA) {
+            return Ok(());
         }
 
-        let parent = block.header.prev_hash;
-        let parent_meta = match self.blocks.get(&parent) {
-            Some(m) => m.clone(),
-            None => return false,
+        // body không khớp header: có thể do peer sửa body, không đánh dấu header invalid
+        if merkle_root(&block.transactions) != block.header.merkle_root {
+            return Err(BlockError::BadMerkleRoot);
+        }
+
+        if block.transactions.is_empty() {
+            self.mark_failed(&hash);
+            return Err(BlockError::NoTransactions);
+        }
+
+        let pos = stored_pos.unwrap_or_else(|| self.db.put_block(&block));
+        let entry = self.index.get_mut(&hash).unwrap();
+        entry.data_pos = Some(pos);
+        entry.tx_count = block.transactions.len() as u64;
+        entry.status.set(BlockStatus::HAVE_DATA);
+        self.db.put_index(entry);
+
+        self.link_block(hash);
+        self.activate_best_chain();
+
+        if self.index.get(&hash).unwrap().status.is_failed() {
+            return Err(BlockError::Invalid(hash));
+        }
+        Ok(())
+    }
+
+    /// Tính chain_tx cho block vừa có body và mọi block con đang chờ nó
+    fn link_block(&mut self, hash: [u8; 32]) {
+        let prev = self.index.get(&hash).unwrap().header.prev_hash;
+        let parent_chain_tx = self.index.get(&prev).map(|p| p.chain_tx).unwrap_or(0);
+
+        if parent_chain_tx == 0 {
+            self.unlinked.entry(prev).or_default().push(hash);
+            return;
+        }
+
+        let mut queue = vec![(hash, parent_chain_tx)];
+        while let Some((h, parent_chain_tx)) = queue.pop() {
+            let entry = self.index.get_mut(&h).unwrap();
+            entry.chain_tx = parent_chain_tx + entry.tx_count;
+            self.db.put_index(entry);
+
+            let chain_tx = entry.chain_tx;
+            if !entry.status.is_failed() {
+                self.candidates.insert(h);
+            }
+
+            for child in self.unlinked.remove(&h).unwrap_or_default() {
+                queue.push((child, chain_tx));
+            }
+        }
+    }
+
+    pub(crate) fn is_better(a: &BlockIndexEntry, b: &BlockIndexEntry) -> bool {
+        a.chainwork > b.chainwork || (a.chainwork == b.chainwork && a.height > b.height)
+    }
+
+    fn best_candidate(&self) -> [u8; 32] {
+        let mut best = self.tip_entry();
+        for hash in &self.candidates {
+            let entry = self.index.get(hash).unwrap();
+            if !entry.status.is_failed() && Self::is_better(entry, best) {
+                best = entry;
+            }
+        }
+        best.hash
+    }
+
+    /// Chuyển active chain sang nhánh most-work hợp lệ
+    fn activate_best_chain(&mut self) {
+        loop {
+            let best = self.best_candidate();
+            if best == self.tip {
+                break;
+            }
+
+            let fork = self.index.last_common_ancestor(&self.tip, &best).unwrap();
+
+            // không bắt đầu reorg nếu thiếu undo/body (prune) giữa chừng
+            if let Err(e) = self.check_reorg_data(&fork, &best) {
+                log::warn!("not switching to {}: {}", hex::encode(best), e);
+                self.candidates.remove(&best);
+                continue;
+            }
+
+            while self.tip != fork {
+                if let Err(e) = self.disconnect_tip() {
+                    log::error!("reorg aborted: {}", e);
+                    return;
+                }
+            }
+
+            let fork_height = self.index.get(&fork).unwrap().height;
+            let best_height = self.index.get(&best).unwrap().height;
+            let path: Vec<[u8; 32]> = (fork_height + 1..=best_height)
+                .map(|h| self.index.get_ancestor(&best, h).unwrap().hash)
+                .collect();
+
+            for hash in path {
+                match self.connect_block(&hash) {
+                    Ok(()) => {}
+                    Err(ChainError::MissingData(_)) => {
+                        self.candidates.remove(&best);
+                        break;
+                    }
+                    // lỗi disk, không phải lỗi của block
+                    Err(e @ ChainError::Corrupt { .. }) => {
+                        log::error!("cannot connect {}: {}", hex::encode(hash), e);
+                        self.candidates.remove(&best);
+                        break;
+                    }
+                    Err(_) => {
+                        self.mark_failed(&hash);
+                        break;
+                    }
+                }
+            }
+        }
+
+        let tip = self.tip_entry().clone();
+        let index = &self.index;
+        self.candidates.retain(|h| {
+            *h == tip.hash || !Self::is_better(&tip, index.get(h).unwrap())
+        });
+
+        self.notify_tip_changed();
+
+        self.maybe_prune();
+    }
+
+    /// Báo tip mới 1 lần sau cả chuỗi disconnect / connect
+    pub(crate) fn notify_tip_changed(&mut self) {
+        if self.tip == self.notified_tip {
+            return;
+        }
+        self.notified_tip = self.tip;
+        self.events.publish(ChainEvent::TipChanged {
+            hash: self.tip,
+            height: self.tip_height(),
+        });
+    }
+
+    /// Mọi block cần disconnect phải còn undo, mọi block cần connect phải còn body
+    fn check_reorg_data(&self, fork: &[u8; 32], best: &[u8; 32]) -> Result<(), ChainError> {
+        let mut walk = self.tip_entry();
+        while walk.hash != *fork {
+            if !walk.status.has(BlockStatus::HAVE_UNDO) {
+                return Err(self.missing_data_error(walk));
+            }
+            walk = self.index.get(&walk.header.prev_hash).unwrap();
+        }
+
+        let mut walk = self.index.get(best).unwrap();
+        while walk.hash != *fork {
+            if !walk.status.has(BlockStatus::HAVE_DATA) {
+                return Err(self.missing_data_error(walk));
+            }
+            walk = self.index.get(&walk.header.prev_hash).unwrap();
+        }
+
+        Ok(())
+    }
+
+    /// Phân biệt dữ liệu bị prune với dữ liệu chưa từng có
+    pub(crate) fn missing_data_error(&self, entry: &BlockIndexEntry) -> ChainError {
+        match self.db.pruned_height() {
+            Some(pruned) if entry.height <= pruned => ChainError::Pruned {
+                hash: entry.hash,
+                height: entry.height,
+            },
+            _ => ChainError::MissingData(entry.hash),
+        }
+    }
+
+    fn connect_block(&mut self, hash: &[u8; 32]) -> Result<(), ChainError> {
+        let entry = self.index.get(hash).unwrap();
+        let height = entry.height;
+
+        let pos = entry.data_pos.ok_or(ChainError::MissingData(*hash))?;
+        let block = self
+            .db
+            .get_block(pos)
+            .map_err(|_| ChainError::Corrupt { hash: *hash, reason: "stored body cannot be decoded" })?
+            .ok_or(ChainError::MissingData(*hash))?;
+
+        let undo = Self::check_block_inputs(&self.utxos, &block, height)
+            .ok_or(ChainError::InvalidBlock(*hash))?;
+
+        // UTXO, undo, index và tip ghi chung 1 batch: crash giữa chừng không để DB lệch tip
+        self.db.begin_batch();
+        self.apply_block(&undo);
+        self.db.put_undo(hash, &undo);
+        self.db.put_utxo_stats(hash, &self.utxo_stats);
+        self.index_filter(hash, &block);
+        self.index_connected(hash, height, &block, &undo);
+
+        let entry = self.index.get_mut(hash).unwrap();
+        entry.status.set(BlockStatus::HAVE_UNDO);
+        entry.status.set(BlockStatus::FULLY_VALID);
+        self.db.put_index(entry);
+
+        self.tip = *hash;
+        self.db.set_tip(hash, height);
+        self.db.commit_batch();
+
+        if self.events.has_subscribers() {
+            self.events.publish(ChainEvent::BlockConnected {
+                block: Arc::new(block),
+                hash: *hash,
+                height,
+            });
+        }
+        Ok(())
+    }
+
+    fn disconnect_tip(&mut self) -> Result<(), ChainError> {
+        let entry = self.tip_entry();
+        if entry.height == 0 {
+            return Err(ChainError::GenesisBlock);
+        }
+
+        let hash = entry.hash;
+        let parent = entry.header.prev_hash;
+        let height = entry.height;
+
+        let undo = match self.db.get_undo(&hash) {
+            Ok(Some(u)) => u,
+            Ok(None) if self.db.pruned_height().is_some() => {
+                return Err(self.missing_data_error(entry))
+            }
+            Ok(None) => return Err(ChainError::MissingUndo(hash)),
+            Err(_) => return Err(ChainError::Corrupt { hash, reason: "undo data cannot be decoded" }),
         };
 
-        let mut undo = BlockUndo {
-            spent: vec![],
-            created: vec![],
+        let notify = self.events.has_subscribers();
+        let block = if self.txindex || self.addressindex || notify {
+            Some(self.get_block(&hash).ok_or(ChainError::MissingData(hash))?)
+        } else {
+            None
         };
 
+        self.db.begin_batch();
+        if let Some(block) = &block {
+            self.index_disconnected(block, &undo);
+        }
+
+        self.rollback_block(&undo);
+        self.candidates.insert(hash);
+
+        self.tip = parent;
+        self.db.set_tip(&parent, height - 1);
+        self.db.commit_batch();
+
+        if let (Some(block), true) = (block, notify) {
+            self.events.publish(ChainEvent::BlockDisconnected {
+                block: Arc::new(block),
+                hash,
+                height,
+            });
+        }
+        Ok(())
+    }
+
+    /// Kiểm tra tx trong block với UTXO set cho trước, trả về undo data nếu hợp lệ
+    pub(crate) fn check_block_inputs(
+        utxos: &HashMap<([u8; 32], u32), UTXO>,
+        block: &Block,
+        height: u64,
+    ) -> Option<BlockUndo> {
+        let mut undo = BlockUndo::new();
+        let mut created: Vec<UTXO> = Vec::new();
+        let mut spent_in_block: HashSet<([u8; 32], u32)> = HashSet::new();
+        let mut fees = 0u64;
+
         for tx in block.transactions.iter().skip(1) {
-            let mut in_sum = 0;
-            let mut out_sum = 0;
-
-for inp in &tx.inputs {
-    let key = (inp.prev_txid, inp.vout);
-    let utxo = match self.utxos.get(&key) {
-        Some(u) => u.clone(),
-        None => return false,
-    };
-    in_sum += utxo.value;
-    undo.spent.push(utxo);
-}
+            if !verify_tx(tx) {
+                return None;
+            }
 
+            let mut in_sum = 0u64;
+            for inp in &tx.inputs {
+                let key = (inp.prev_txid, inp.vout);
+                if !spent_in_block.insert(key) {
+                    return None;
+                }
+
+                // output tạo ra trong chính block này
+                if let Some(u) = created.iter().find(|u| (u.txid, u.vout) == key) {
+                    in_sum = in_sum.checked_add(u.value)?;
+                    continue;
+                }
+
+                let utxo = utxos.get(&key)?;
+                in_sum = in_sum.checked_add(utxo.value)?;
+                undo.spent.push(utxo.clone());
+            }
 
+            let mut out_sum = 0u64;
             for out in &tx.outputs {
-                out_sum += out.value;
+                out_sum = out_sum.checked_add(out.value)?;
             }
 
             if in_sum < out_sum {
-                return false;
+                return None;
             }
-        }
+            fees += in_sum - out_sum;
 
-        for u in &undo.spent {
-            self.utxos.remove(&(u.txid, u.vout));
+            let id = txid(tx);
+            for (vout, out) in tx.outputs.iter().enumerate() {
+                created.push(UTXO {
+                    txid: id,
+                    vout: vout as u32,
+                    value: out.value,
+                    address: out.to_address.clone(),
+                    height,
+                });
+            }
         }
 
         let cb = &block.transactions[0];
-        let cbid = txid(cb);
+        let mut cb_sum = 0u64;
+        for out in &cb.outputs {
+            cb_sum = cb_sum.checked_add(out.value)?;
+        }
+        if cb_sum > BLOCK_REWARD + fees {
+            return None;
+        }
 
+        let cbid = txid(cb);
         for (vout, out) in cb.outputs.iter().enumerate() {
-            let utxo = UTXO {
+            undo.created.push(UTXO {
                 txid: cbid,
                 vout: vout as u32,
                 value: out.value,
                 address: out.to_address.clone(),
-                height: parent_meta.height + 1,
-            };
-            self.utxos.insert((utxo.txid, utxo.vout), utxo.clone());
-            undo.created.push(utxo);
-        }
-
-        let hash = hash_header(&block.header);
-        let meta = BlockMeta {
-            block: block.clone(),
-            parent,
-            height: parent_meta.height + 1,
-            total_work: parent_meta.total_work + work_from_bits(block.header.bits),
-            undo,
-        };
-
-        self.blocks.insert(hash, meta);
-        self.maybe_reorg(hash);
-        true
-    }
+                height,
+            });
+        }
 
-    fn maybe_reorg(&mut self, candidate: [u8; 32]) {
-        let cand = self.blocks.get(&candidate).unwrap().clone();
-        let best = self.blocks.get(&self.tip).unwrap().clone();
+        undo.created.extend(
+            created
+                .into_iter()
+                .filter(|u| !spent_in_block.contains(&(u.txid, u.vout))),
+        );
 
-        if cand.total_work > best.total_work
-            || (cand.total_work == best.total_work && cand.height > best.height)
-        {
-            self.reorg(self.tip, candidate);
-        }
+        Some(undo)
     }
 
-    fn reorg(&mut self, old_tip: [u8; 32], new_tip: [u8; 32]) {
-        if old_tip == new_tip {
-            return;
-        }
-
-        let mut a = old_tip;
-        let mut b = new_tip;
-        let mut path_a = Vec::new();
-        let mut path_b = Vec::new();
+    /// Đánh dấu block invalid và mọi block con cháu là FAILED_PARENT
+    fn mark_failed(&mut self, hash: &[u8; 32]) {
+        let descendants = self.index.descendants(hash);
 
-        while a != b {
-            let ma = self.blocks.get(&a).unwrap();
-            let mb = self.blocks.get(&b).unwrap();
+        let entry = self.index.get_mut(hash).unwrap();
+        entry.status.set(BlockStatus::FAILED);
+        self.db.put_index(entry);
+        self.candidates.remove(hash);
 
-            if ma.height >= mb.height {
-                path_a.push(a);
-                a = ma.parent;
-            } else {
-                path_b.push(b);
-                b = mb.parent;
-            }
+        for h in descendants {
+            let entry = self.index.get_mut(&h).unwrap();
+            entry.status.set(BlockStatus::FAILED_PARENT);
+            self.db.put_index(entry);
+            self.candidates.remove(&h);
         }
 
-        for h in &path_a {
-            let undo = self.blocks.get(h).unwrap().undo.clone();
-            self.rollback_block(&undo);
+        if self.index.get(&self.best_header).unwrap().status.is_failed() {
+            self.recompute_best_header();
         }
+    }
 
-        for h in path_b.iter().rev() {
-            let undo = self.blocks.get(h).unwrap().undo.clone();
-            self.apply_block(&undo);
+    /// Đưa lại mọi block đủ data và không invalid vào candidates
+    pub(crate) fn rebuild_candidates(&mut self) {
+        for entry in self.index.iter() {
+            if entry.chain_tx > 0 && !entry.status.is_failed() {
+                self.candidates.insert(entry.hash);
+            }
         }
-
-        self.tip = new_tip;
-        self.db.set_tip(&new_tip, self.blocks.get(&new_tip).unwrap().height);
+        self.recompute_best_header();
     }
 
     fn rollback_block(&mut self, undo: &BlockUndo) {
         for u in &undo.created {
             self.utxos.remove(&(u.txid, u.vout));
+            self.utxo_stats.remove(u);
+            self.db.delete_utxo(&u.txid, u.vout);
         }
         for u in &undo.spent {
             self.utxos.insert((u.txid, u.vout), u.clone());
+            self.utxo_stats.add(u);
+            self.db.put_utxo(u);
         }
     }
 
     fn apply_block(&mut self, undo: &BlockUndo) {
         for u in &undo.spent {
             self.utxos.remove(&(u.txid, u.vout));
+            self.utxo_stats.remove(u);
+            self.db.delete_utxo(&u.txid, u.vout);
         }
         for u in &undo.created {
             self.utxos.insert((u.txid, u.vout), u.clone());
+            self.utxo_stats.add(u);
+            self.db.put_utxo(u);
         }
     }
 }
@@ -0,0 +1,104 @@
+use crate::chain::block::Block;
+use crate::chain::error::ChainError;
+use crate::chain::state::ChainState;
+use crate::chain::tx::Transaction;
+use crate::chain::undo::BlockUndo;
+use crate::storage::sleddb::{AddressOutput, TxLocation};
+
+/* =========================
+   TX INDEX / ADDRESS INDEX
+   ========================= */
+
+impl ChainState {
+    /// Bật / tắt txindex và addressindex.
+    /// Bật trên chain đã có sẵn => dựng lại từ genesis (cần đủ block body);
+    /// tắt => xoá index để không bị lệch so với tip.
+    pub fn set_indexes(&mut self, txindex: bool, addressindex: bool) -> Result<(), ChainError> {
+        let enabling = (txindex && !self.txindex) || (addressindex && !self.addressindex);
+        if enabling && (self.is_pruned() || self.prune_target.is_some()) {
+            return Err(ChainError::NeedsFullHistory("txindex / addressindex"));
+        }
+
+        if !txindex && self.txindex {
+            self.db.clear_txindex();
+            self.txindex = false;
+        }
+        if !addressindex && self.addressindex {
+            self.db.clear_addressindex();
+            self.addressindex = false;
+        }
+
+        let build_tx = txindex && !self.txindex;
+        let build_addr = addressindex && !self.addressindex;
+        if !build_tx && !build_addr {
+            return Ok(());
+        }
+
+        let tip_height = self.tip_height();
+        log::info!("building indexes for {} blocks", tip_height + 1);
+
+        for height in 0..=tip_height {
+            let hash = self.block_hash_at(height).unwrap();
+            let block = self.get_block(&hash).ok_or(ChainError::MissingData(hash))?;
+
+            if build_tx {
+                self.db.index_block_txs(&hash, height, &block);
+            }
+            if build_addr {
+                let undo = self
+                    .db
+                    .get_undo(&hash)
+                    .map_err(|_| ChainError::Corrupt { hash, reason: "undo data cannot be decoded" })?
+                    .ok_or(ChainError::MissingUndo(hash))?;
+                self.db.index_block_addresses(&block, &undo, height);
+            }
+        }
+
+        if build_tx {
+            self.db.set_txindex(true);
+            self.txindex = true;
+        }
+        if build_addr {
+            self.db.set_addressindex(true);
+            self.addressindex = true;
+        }
+        Ok(())
+    }
+
+    /// Tx trên active chain theo txid (cần txindex)
+    pub fn get_transaction(&self, id: &[u8; 32]) -> Option<(TxLocation, Transaction)> {
+        let loc = self.db.get_tx_location(id)?;
+        let block = self.get_block(&loc.block_hash)?;
+        let tx = block.transactions.get(loc.position as usize)?.clone();
+        Some((loc, tx))
+    }
+
+    /// Mọi output từng trả cho address trên active chain (cần addressindex)
+    pub fn get_address_history(&self, address: &[u8]) -> Vec<AddressOutput> {
+        self.db.get_address_outputs(address)
+    }
+
+    pub(crate) fn index_connected(
+        &self,
+        hash: &[u8; 32],
+        height: u64,
+        block: &Block,
+        undo: &BlockUndo,
+    ) {
+        if self.txindex {
+            self.db.index_block_txs(hash, height, block);
+        }
+        if self.addressindex {
+            self.db.index_block_addresses(block, undo, height);
+        }
+    }
+
+    pub(crate) fn index_disconnected(&self, block: &Block, undo: &BlockUndo) {
+        if self.txindex {
+            self.db.unindex_block_txs(block);
+        }
+        if self.addressindex {
+            self.db.unindex_block_addresses(block, undo);
+        }
+    }
+}
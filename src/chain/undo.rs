@@ -1,8 +1,10 @@
+use serde::{Serialize, Deserialize};
+
 use crate::chain::utxo::UTXO;
 
 /// Undo information cho 1 block
 /// Dùng để rollback UTXO khi reorg
-#[derive(Clone)]
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct BlockUndo {
     /// Các UTXO đã bị consume trong block này
     pub spent: Vec<UTXO>,
@@ -0,0 +1,125 @@
+use crate::chain::block::merkle_root;
+use crate::chain::error::ChainError;
+use crate::chain::hash::hash_header;
+use crate::chain::index::BlockStatus;
+use crate::chain::sign::verify_tx;
+use crate::chain::state::ChainState;
+use crate::pow::verify::verify_pow;
+
+/// Mức kiểm tra của verifychain:
+/// 0 = đọc block body từ disk
+/// 1 = kiểm tra PoW, merkle root, chữ ký
+/// 2 = kiểm tra undo data
+/// 3 = disconnect các block (trên bản copy UTXO set) bằng undo data
+/// 4 = connect lại và so sánh UTXO set với tip hiện tại
+pub const MAX_VERIFY_LEVEL: u32 = 4;
+
+impl ChainState {
+    /// Kiểm tra `depth` block gần tip nhất (0 = toàn bộ chain).
+    /// Không ghi gì xuống DB.
+    pub fn verify_chain(
+        &self,
+        depth: u64,
+        level: u32,
+        mut progress: impl FnMut(u64, u64),
+    ) -> Result<(), ChainError> {
+        let tip_height = self.tip_height();
+        let depth = if depth == 0 || depth > tip_height { tip_height } else { depth };
+
+        let mut utxos = self.utxos.clone();
+        let mut disconnected = Vec::new();
+
+        for i in 0..depth {
+            let entry = self.index.get_ancestor(&self.tip, tip_height - i).unwrap();
+            // phía dưới là block của snapshot chưa validate, chưa có body / undo
+            if entry.status.has(BlockStatus::ASSUMED_VALID) {
+                break;
+            }
+            let hash = entry.hash;
+            let corrupt = |reason| ChainError::Corrupt { hash, reason };
+
+            // ---- level 0 ----
+            let block = match entry.data_pos.map(|pos| self.db.get_block(pos)) {
+                Some(Ok(Some(block))) => block,
+                Some(Err(_)) => return Err(corrupt("stored body cannot be decoded")),
+                _ => return Err(self.missing_data_error(entry)),
+            };
+
+            if hash_header(&block.header) != hash {
+                return Err(corrupt("stored body does not match index"));
+            }
+
+            // ---- level 1 ----
+            if level >= 1 {
+                if !verify_pow(&block.header) {
+                    return Err(corrupt("proof of work invalid"));
+                }
+                if block.transactions.is_empty()
+                    || merkle_root(&block.transactions) != block.header.merkle_root
+                {
+                    return Err(corrupt("merkle root mismatch"));
+                }
+                if !block.transactions.iter().skip(1).all(verify_tx) {
+                    return Err(corrupt("invalid signature"));
+                }
+            }
+
+            // ---- level 2 ----
+            if level >= 2 {
+                let undo = match self.db.get_undo(&hash) {
+                    Ok(Some(undo)) => undo,
+                    Ok(None) => {
+                        return Err(match self.missing_data_error(entry) {
+                            ChainError::MissingData(h) => ChainError::MissingUndo(h),
+                            e => e,
+                        })
+                    }
+                    Err(_) => return Err(corrupt("undo data cannot be decoded")),
+                };
+
+                // ---- level 3 ----
+                if level >= 3 {
+                    for u in &undo.created {
+                        if utxos.remove(&(u.txid, u.vout)).as_ref() != Some(u) {
+                            return Err(corrupt("utxo set inconsistent with undo data"));
+                        }
+                    }
+                    for u in &undo.spent {
+                        if utxos.insert((u.txid, u.vout), u.clone()).is_some() {
+                            return Err(corrupt("undo data restores an unspent output"));
+                        }
+                    }
+                    disconnected.push((entry.height, block));
+                }
+            }
+
+            progress(i + 1, depth);
+        }
+
+        // ---- level 4 ----
+        if level >= 4 {
+            for (height, block) in disconnected.into_iter().rev() {
+                let hash = hash_header(&block.header);
+                let undo = Self::check_block_inputs(&utxos, &block, height).ok_or(
+                    ChainError::Corrupt { hash, reason: "block failed to reconnect" },
+                )?;
+
+                for u in &undo.spent {
+                    utxos.remove(&(u.txid, u.vout));
+                }
+                for u in undo.created {
+                    utxos.insert((u.txid, u.vout), u);
+                }
+            }
+
+            if utxos != self.utxos {
+                return Err(ChainError::Corrupt {
+                    hash: self.tip,
+                    reason: "utxo set differs after reconnecting blocks",
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
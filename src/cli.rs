@@ -1,42 +1,541 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
 use clap::{Parser, Subcommand};
 
-use crate::node::run_node;
+use crate::node::{load_node_identity, read_passphrase, run_node};
 use crate::config::NodeConfig;
 use crate::chain::state::ChainState;
 use crate::storage::sleddb::ChainDB;
-use crate::chain::genesis_block;
 use crate::chain::tx::{Transaction, TxInput, TxOutput};
 use crate::chain::sign::sign_tx;
+use crate::chain::verify::MAX_VERIFY_LEVEL;
+use crate::chain::encode::serialize;
+use crate::chain::params::{ChainParams, Network};
+use crate::p2p::eviction::DEFAULT_MAX_INBOUND;
+use crate::p2p::network::DEFAULT_MAX_OUTBOUND;
+use crate::net::ban::Subnet;
+use crate::rpc::{call, RpcRequest, RpcResponse, DEFAULT_RPC_BIND};
+use crate::wallet::persist::save_wallet;
+use crate::wallet::seed::MasterSeed;
 
 
 #[derive(Parser)]
 pub struct Cli {
+    /// Thư mục chứa chain database
+    #[arg(long, global = true, default_value = "./egg-chain")]
+    pub datadir: String,
+
+    #[arg(long, global = true, value_enum, default_value = "main")]
+    pub network: Network,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
-    Run,
+    Run {
+        /// Address lắng nghe kết nối inbound
+        #[arg(long, value_name = "ADDR", default_value = "0.0.0.0:8333")]
+        bind: String,
+        /// Address quảng bá cho peer (mặc định: --bind nếu là IP cụ thể)
+        #[arg(long, value_name = "ADDR")]
+        externalip: Option<std::net::SocketAddr>,
+        /// Peer để kết nối outbound (host:port), dùng nhiều lần được
+        #[arg(long = "connect", value_name = "ADDR")]
+        peers: Vec<String>,
+        /// Số kết nối outbound tối đa
+        #[arg(long, default_value_t = DEFAULT_MAX_OUTBOUND)]
+        maxoutbound: usize,
+        /// Số kết nối inbound tối đa
+        #[arg(long, default_value_t = DEFAULT_MAX_INBOUND)]
+        maxinbound: usize,
+        /// Chỉ giữ khoảng <MB> block body + undo data, xoá phần cũ hơn
+        #[arg(long, value_name = "MB")]
+        prune: Option<u64>,
+        /// Duy trì index txid -> block (không dùng được cùng --prune)
+        #[arg(long)]
+        txindex: bool,
+        /// Duy trì index address -> output / tx tiêu output
+        #[arg(long)]
+        addressindex: bool,
+        /// Address nghe control RPC (chỉ loopback, RPC không xác thực)
+        #[arg(long, value_name = "ADDR", default_value = DEFAULT_RPC_BIND)]
+        rpcbind: String,
+        /// Giới hạn tốc độ upload toàn node (KB/s)
+        #[arg(long, value_name = "KB")]
+        maxuploadrate: Option<u64>,
+        /// Giới hạn tốc độ download toàn node (KB/s)
+        #[arg(long, value_name = "KB")]
+        maxdownloadrate: Option<u64>,
+        /// Tổng upload mỗi 24 giờ (MB); hết thì chỉ phục vụ block gần tip
+        #[arg(long, value_name = "MB")]
+        maxuploadtarget: Option<u64>,
+        /// Kết nối outbound không mã hoá (chỉ transport v1)
+        #[arg(long)]
+        nov2transport: bool,
+        /// Identity pubkey (hex) của node tin cậy: không bị ban / evict,
+        /// không giới hạn theo peer, được relay trước; chỉ áp dụng cho kết
+        /// nối transport v2. Dùng nhiều lần được
+        #[arg(long = "trustednode", value_name = "PUBKEY")]
+        trusted_nodes: Vec<secp256k1::PublicKey>,
+        /// File chứa passphrase của wallet.dat; identity key của node dẫn
+        /// xuất từ master seed trong wallet (không có thì chạy ẩn danh)
+        #[arg(long, value_name = "FILE")]
+        walletpassphrasefile: Option<PathBuf>,
+    },
     Send {
         txid: String,
         vout: u32,
         to: String,
         value: u64,
     },
+    /// Đánh dấu block (và con cháu) invalid, reorg sang chain khác (qua control RPC)
+    #[command(name = "invalidateblock")]
+    InvalidateBlock {
+        hash: String,
+        #[arg(long, value_name = "ADDR", default_value = DEFAULT_RPC_BIND)]
+        rpcconnect: String,
+    },
+    /// Gỡ trạng thái invalid đã đặt bởi invalidateblock
+    #[command(name = "reconsiderblock")]
+    ReconsiderBlock {
+        hash: String,
+        #[arg(long, value_name = "ADDR", default_value = DEFAULT_RPC_BIND)]
+        rpcconnect: String,
+    },
+    /// Kiểm tra lại các block gần tip (PoW, merkle, chữ ký, undo data)
+    #[command(name = "verifychain")]
+    VerifyChain {
+        /// Số block kiểm tra tính từ tip (0 = toàn bộ)
+        #[arg(long, default_value_t = 6)]
+        depth: u64,
+        /// Mức kiểm tra 0-4
+        #[arg(long, default_value_t = 3)]
+        level: u32,
+    },
+    /// Dựng lại block index và UTXO set từ block body đã lưu
+    Reindex,
+    /// Ghi UTXO set tại tip ra file snapshot
+    #[command(name = "dumptxoutset")]
+    DumpTxOutSet {
+        path: PathBuf,
+    },
+    /// Bootstrap chain từ snapshot đã được công bố cho network này
+    #[command(name = "loadtxoutset")]
+    LoadTxOutSet {
+        path: PathBuf,
+    },
+    /// Số coin, tổng giá trị và hash của UTXO set (tại tip hoặc tại 1 block)
+    #[command(name = "gettxoutsetinfo")]
+    GetTxOutSetInfo {
+        /// Block trên chain (mặc định: tip)
+        #[arg(long)]
+        block: Option<String>,
+        #[arg(long, value_name = "ADDR", default_value = DEFAULT_RPC_BIND)]
+        rpcconnect: String,
+    },
+    /// Tra cứu tx theo txid (cần chạy node với --txindex)
+    #[command(name = "getrawtransaction")]
+    GetRawTransaction {
+        txid: String,
+        #[arg(long, value_name = "ADDR", default_value = DEFAULT_RPC_BIND)]
+        rpcconnect: String,
+    },
+    /// Liệt kê output nhận và tx đã tiêu của 1 address (cần --addressindex)
+    #[command(name = "getaddresshistory")]
+    GetAddressHistory {
+        address: String,
+        #[arg(long, value_name = "ADDR", default_value = DEFAULT_RPC_BIND)]
+        rpcconnect: String,
+    },
+    /// Tạo wallet.dat mới trong datadir (master seed mã hoá bằng passphrase)
+    #[command(name = "createwallet")]
+    CreateWallet {
+        /// File chứa passphrase
+        #[arg(long, value_name = "FILE")]
+        passphrasefile: PathBuf,
+    },
+    /// Identity pubkey của node, dẫn xuất từ master seed trong wallet.dat
+    #[command(name = "nodeidentity")]
+    NodeIdentity {
+        /// File chứa passphrase
+        #[arg(long, value_name = "FILE")]
+        passphrasefile: PathBuf,
+    },
+    /// Các peer đang kết nối của node đang chạy (qua control RPC)
+    #[command(name = "getpeerinfo")]
+    GetPeerInfo {
+        /// Address control RPC của node
+        #[arg(long, value_name = "ADDR", default_value = DEFAULT_RPC_BIND)]
+        rpcconnect: String,
+    },
+    /// Ban / bỏ ban 1 IP hoặc subnet (CIDR, vd 10.0.0.0/8) trên node đang chạy
+    #[command(name = "setban")]
+    SetBan {
+        subnet: Subnet,
+        command: BanCommand,
+        /// Thời gian ban, mặc định 1 giờ
+        #[arg(long, value_name = "SECONDS")]
+        bantime: Option<u64>,
+        #[arg(long, value_name = "ADDR", default_value = DEFAULT_RPC_BIND)]
+        rpcconnect: String,
+    },
+    /// Các IP / subnet đang bị ban
+    #[command(name = "listbans")]
+    ListBans {
+        #[arg(long, value_name = "ADDR", default_value = DEFAULT_RPC_BIND)]
+        rpcconnect: String,
+    },
+    /// Bỏ mọi ban
+    #[command(name = "clearbanned")]
+    ClearBanned {
+        #[arg(long, value_name = "ADDR", default_value = DEFAULT_RPC_BIND)]
+        rpcconnect: String,
+    },
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum BanCommand {
+    Add,
+    Remove,
+}
+
+fn open_chain(datadir: &str, network: Network) -> ChainState {
+    let db = ChainDB::open(datadir);
+    ChainState::load_or_init(ChainParams::new(network), db)
+}
+
+fn parse_hash(s: &str) -> [u8; 32] {
+    match hex::decode(s).ok().and_then(|b| b.try_into().ok()) {
+        Some(h) => h,
+        None => {
+            eprintln!("invalid hash: {}", s);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// In tiến độ mỗi khi qua 1 mốc 10%
+fn report_progress(label: &str) -> impl FnMut(u64, u64) + '_ {
+    let mut last = 0;
+    move |done, total| {
+        let pct = done * 100 / total.max(1);
+        if pct / 10 > last / 10 || done == total {
+            println!("{}: {}/{} ({}%)", label, done, total, pct);
+            last = pct;
+        }
+    }
+}
+
+fn fmt_ms(d: Option<Duration>) -> String {
+    d.map_or("-".to_string(), |d| format!("{:.1}ms", d.as_secs_f64() * 1000.0))
+}
+
+fn fmt_ago(d: Option<Duration>) -> String {
+    d.map_or("never".to_string(), |d| format!("{}s ago", d.as_secs()))
+}
+
+/// Gọi control RPC, thoát nếu lỗi
+fn rpc(addr: &str, req: &RpcRequest, command: &str) -> RpcResponse {
+    match call(addr, req) {
+        Ok(RpcResponse::Error(e)) => {
+            eprintln!("{} failed: {}", command, e);
+            std::process::exit(1);
+        }
+        Ok(resp) => resp,
+        Err(e) => {
+            eprintln!("cannot reach node at {}: {}", addr, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn print_tip(chain: &ChainState) {
+    println!("tip {} height {}", hex::encode(chain.tip), chain.tip_height());
+}
+
+fn print_tip_response(resp: RpcResponse) {
+    match resp {
+        RpcResponse::Tip { hash, height } => println!("tip {} height {}", hex::encode(hash), height),
+        _ => unreachable!(),
+    }
 }
 
 impl Cli {
     pub fn execute(&self) {
         match &self.command {
-            Commands::Run => {
-                let db = ChainDB::open("./egg-chain");
-                let genesis = genesis_block();
-                let chain = ChainState::load_or_init(genesis, db);
-                let config = NodeConfig::default();
+            Commands::Run { bind, externalip, peers, maxoutbound, maxinbound, prune, txindex, addressindex, rpcbind, maxuploadrate, maxdownloadrate, maxuploadtarget, nov2transport, trusted_nodes, walletpassphrasefile } => {
+                let mut chain = open_chain(&self.datadir, self.network);
+                if chain.db.snapshot_invalid() {
+                    eprintln!("utxo snapshot failed background validation, run reindex");
+                    std::process::exit(1);
+                }
+
+                let config = NodeConfig {
+                    datadir: PathBuf::from(&self.datadir),
+                    bind_addr: bind.clone(),
+                    external_addr: *externalip,
+                    peers: peers.clone(),
+                    max_outbound: *maxoutbound,
+                    max_inbound: *maxinbound,
+                    prune_target: prune.map(|mb| mb * 1024 * 1024),
+                    rpc_bind: rpcbind.clone(),
+                    max_upload_rate: maxuploadrate.map(|kb| kb * 1024),
+                    max_download_rate: maxdownloadrate.map(|kb| kb * 1024),
+                    max_upload_target: maxuploadtarget.map(|mb| mb * 1024 * 1024),
+                    v2_transport: !*nov2transport,
+                    trusted_nodes: trusted_nodes.clone(),
+                    wallet_passphrase_file: walletpassphrasefile.clone(),
+                };
+
+                if config.prune_target == Some(0) {
+                    eprintln!("--prune must be greater than 0");
+                    std::process::exit(1);
+                }
+                if [maxuploadrate, maxdownloadrate, maxuploadtarget].contains(&&Some(0)) {
+                    eprintln!("--maxuploadrate / --maxdownloadrate / --maxuploadtarget must be greater than 0");
+                    std::process::exit(1);
+                }
+                if config.prune_target.is_some() && (*txindex || *addressindex) {
+                    eprintln!("--prune is incompatible with --txindex / --addressindex");
+                    std::process::exit(1);
+                }
+                if let Err(e) = chain.set_indexes(*txindex, *addressindex) {
+                    eprintln!("cannot enable index: {}", e);
+                    std::process::exit(1);
+                }
+                chain.set_prune_target(config.prune_target);
+
                 run_node(config, chain);
             }
 
+            Commands::InvalidateBlock { hash, rpcconnect } => {
+                let req = RpcRequest::InvalidateBlock { hash: parse_hash(hash) };
+                print_tip_response(rpc(rpcconnect, &req, "invalidateblock"));
+            }
+
+            Commands::VerifyChain { depth, level } => {
+                let chain = open_chain(&self.datadir, self.network);
+                let level = (*level).min(MAX_VERIFY_LEVEL);
+
+                match chain.verify_chain(*depth, level, report_progress("verifychain")) {
+                    Ok(()) => println!("verifychain: no problems found (level {})", level),
+                    Err(e) => {
+                        eprintln!("verifychain failed: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+
+            Commands::Reindex => {
+                let db = ChainDB::open(&self.datadir);
+                match ChainState::reindex(ChainParams::new(self.network), db, report_progress("reindex")) {
+                    Ok((chain, skipped)) => {
+                        if skipped > 0 {
+                            println!("skipped {} corrupt records", skipped);
+                        }
+                        print_tip(&chain);
+                    }
+                    Err(e) => {
+                        eprintln!("reindex failed: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+
+            Commands::DumpTxOutSet { path } => {
+                let chain = open_chain(&self.datadir, self.network);
+                match chain.dump_txoutset(path) {
+                    Ok(meta) => {
+                        println!("base {} height {}", hex::encode(meta.base_hash), meta.height);
+                        println!("coins {}", meta.coin_count);
+                        println!("content hash {}", hex::encode(meta.content_hash));
+                    }
+                    Err(e) => {
+                        eprintln!("dumptxoutset failed: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+
+            Commands::LoadTxOutSet { path } => {
+                let mut chain = open_chain(&self.datadir, self.network);
+                if let Err(e) = chain.load_txoutset(path) {
+                    eprintln!("loadtxoutset failed: {}", e);
+                    std::process::exit(1);
+                }
+                print_tip(&chain);
+            }
+
+            Commands::GetTxOutSetInfo { block, rpcconnect } => {
+                let req = RpcRequest::GetTxOutSetInfo { block: block.as_deref().map(parse_hash) };
+                let (hash, height, stats) = match rpc(rpcconnect, &req, "gettxoutsetinfo") {
+                    RpcResponse::TxOutSetInfo { hash, height, stats } => (hash, height, stats),
+                    _ => unreachable!(),
+                };
+
+                println!("block {} height {}", hex::encode(hash), height);
+                println!("coins {}", stats.coins);
+                println!("total amount {}", stats.total_amount);
+                println!("set hash {}", hex::encode(stats.hash.digest()));
+            }
+
+            Commands::GetRawTransaction { txid, rpcconnect } => {
+                let req = RpcRequest::GetRawTransaction { txid: parse_hash(txid) };
+                let (loc, tx) = match rpc(rpcconnect, &req, "getrawtransaction") {
+                    RpcResponse::RawTransaction { loc, tx } => (loc, tx),
+                    _ => unreachable!(),
+                };
+                println!(
+                    "block {} height {} position {}",
+                    hex::encode(loc.block_hash),
+                    loc.height,
+                    loc.position
+                );
+                println!("{}", hex::encode(serialize(&tx)));
+            }
+
+            Commands::GetAddressHistory { address, rpcconnect } => {
+                let req = RpcRequest::GetAddressHistory { address: address.as_bytes().to_vec() };
+                let history = match rpc(rpcconnect, &req, "getaddresshistory") {
+                    RpcResponse::AddressHistory(history) => history,
+                    _ => unreachable!(),
+                };
+
+                let mut balance = 0;
+                for out in history {
+                    match out.spent_by {
+                        Some((spender, height)) => println!(
+                            "{}:{} value {} height {} spent by {} at height {}",
+                            hex::encode(out.txid),
+                            out.vout,
+                            out.value,
+                            out.height,
+                            hex::encode(spender),
+                            height
+                        ),
+                        None => {
+                            balance += out.value;
+                            println!(
+                                "{}:{} value {} height {} unspent",
+                                hex::encode(out.txid),
+                                out.vout,
+                                out.value,
+                                out.height
+                            );
+                        }
+                    }
+                }
+                println!("balance {}", balance);
+            }
+
+            Commands::CreateWallet { passphrasefile } => {
+                let config = NodeConfig { datadir: PathBuf::from(&self.datadir), ..NodeConfig::default() };
+                let wallet_file = config.wallet_file();
+                let passphrase = read_passphrase(passphrasefile).unwrap_or_else(|e| {
+                    eprintln!("cannot read {}: {}", passphrasefile.display(), e);
+                    std::process::exit(1);
+                });
+                if passphrase.is_empty() {
+                    eprintln!("passphrase must not be empty");
+                    std::process::exit(1);
+                }
+                let created = std::fs::create_dir_all(&config.datadir)
+                    .and_then(|_| save_wallet(&wallet_file, &MasterSeed::generate(), &passphrase));
+                if let Err(e) = created {
+                    eprintln!("cannot create {}: {}", wallet_file.display(), e);
+                    std::process::exit(1);
+                }
+                println!("created {}", wallet_file.display());
+            }
+
+            Commands::NodeIdentity { passphrasefile } => {
+                let config = NodeConfig { datadir: PathBuf::from(&self.datadir), ..NodeConfig::default() };
+                let wallet_file = config.wallet_file();
+                let identity = read_passphrase(passphrasefile)
+                    .and_then(|p| load_node_identity(&wallet_file, &p))
+                    .unwrap_or_else(|e| {
+                        eprintln!("cannot open {}: {}", wallet_file.display(), e);
+                        std::process::exit(1);
+                    });
+                let secp = secp256k1::Secp256k1::signing_only();
+                println!("{}", secp256k1::PublicKey::from_secret_key(&secp, &identity));
+            }
+
+            Commands::GetPeerInfo { rpcconnect } => {
+                let peers = match rpc(rpcconnect, &RpcRequest::GetPeerInfo, "getpeerinfo") {
+                    RpcResponse::PeerInfo(peers) => peers,
+                    _ => unreachable!(),
+                };
+
+                for p in &peers {
+                    println!(
+                        "{} {} {} version {} services {:x} start height {}",
+                        p.id,
+                        p.addr,
+                        if p.inbound { "inbound" } else { "outbound" },
+                        p.version,
+                        p.services,
+                        p.start_height
+                    );
+                    println!(
+                        "    conn {}s  last send {}  last recv {}  blocks in flight {}",
+                        p.conn_time.as_secs(),
+                        fmt_ago(p.last_send),
+                        fmt_ago(p.last_recv),
+                        p.blocks_in_flight
+                    );
+                    println!(
+                        "    ping {}  min ping {}  ping wait {}",
+                        fmt_ms(p.ping_time),
+                        fmt_ms(p.min_ping),
+                        fmt_ms(p.ping_wait)
+                    );
+                    match p.session_id {
+                        Some(id) => println!("    transport v2  session {}", hex::encode(id)),
+                        None => println!("    transport v1"),
+                    }
+                    if let Some(pk) = &p.identity {
+                        println!("    identity {}{}", hex::encode(pk), if p.whitelisted { "  trusted" } else { "" });
+                    }
+                }
+                println!("{} peers", peers.len());
+            }
+
+            Commands::SetBan { subnet, command, bantime, rpcconnect } => {
+                let req = RpcRequest::SetBan {
+                    subnet: *subnet,
+                    add: matches!(command, BanCommand::Add),
+                    duration: bantime.map(Duration::from_secs),
+                };
+                rpc(rpcconnect, &req, "setban");
+            }
+
+            Commands::ListBans { rpcconnect } => {
+                let bans = match rpc(rpcconnect, &RpcRequest::ListBans, "listbans") {
+                    RpcResponse::Bans(bans) => bans,
+                    _ => unreachable!(),
+                };
+                for (subnet, entry) in &bans {
+                    println!(
+                        "{} banned at {} until {} ({})",
+                        subnet, entry.created, entry.until, entry.reason
+                    );
+                }
+                println!("{} bans", bans.len());
+            }
+
+            Commands::ClearBanned { rpcconnect } => {
+                rpc(rpcconnect, &RpcRequest::ClearBanned, "clearbanned");
+            }
+
+            Commands::ReconsiderBlock { hash, rpcconnect } => {
+                let req = RpcRequest::ReconsiderBlock { hash: parse_hash(hash) };
+                print_tip_response(rpc(rpcconnect, &req, "reconsiderblock"));
+            }
+
             Commands::Send { txid, vout, to, value } => {
                 use secp256k1::SecretKey;
 
@@ -1,14 +1,97 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use secp256k1::PublicKey;
+
+use crate::p2p::message::{NODE_COMPACT_FILTERS, NODE_NETWORK, NODE_NETWORK_LIMITED};
+use crate::p2p::eviction::DEFAULT_MAX_INBOUND;
+use crate::p2p::network::DEFAULT_MAX_OUTBOUND;
+use crate::rpc::DEFAULT_RPC_BIND;
+
 #[derive(Clone)]
 pub struct NodeConfig {
+    /// Thư mục dữ liệu, chứa peers.dat
+    pub datadir: PathBuf,
     pub bind_addr: String,
+    /// Address quảng bá cho peer khi bind_addr không dùng được (vd 0.0.0.0, sau NAT)
+    pub external_addr: Option<SocketAddr>,
+    /// Address (host:port) để mở kết nối outbound
     pub peers: Vec<String>,
+    /// Số kết nối outbound tối đa
+    pub max_outbound: usize,
+    /// Số kết nối inbound tối đa
+    pub max_inbound: usize,
+    /// Ngưỡng dung lượng block data khi prune (byte), None = full node
+    pub prune_target: Option<u64>,
+    /// Address nghe control RPC (xem crate::rpc)
+    pub rpc_bind: String,
+    /// Giới hạn băng thông toàn node (byte/s), None = không giới hạn
+    pub max_upload_rate: Option<u64>,
+    pub max_download_rate: Option<u64>,
+    /// Tổng byte upload mỗi ngày; hết thì không phục vụ block cũ
+    pub max_upload_target: Option<u64>,
+    /// Kết nối outbound bằng transport v2 (mã hoá), peer cũ thì lùi về v1
+    pub v2_transport: bool,
+    /// Identity pubkey của node tin cậy (xem PeerContext::trusted)
+    pub trusted_nodes: Vec<PublicKey>,
+    /// File chứa passphrase mở wallet.dat; None = không có identity key
+    pub wallet_passphrase_file: Option<PathBuf>,
 }
 
-impl NodeConfig {
-    pub fn default() -> Self {
+impl Default for NodeConfig {
+    fn default() -> Self {
         NodeConfig {
+            datadir: PathBuf::from("./egg-chain"),
             bind_addr: "0.0.0.0:8333".to_string(),
+            external_addr: None,
             peers: vec![],
+            max_outbound: DEFAULT_MAX_OUTBOUND,
+            max_inbound: DEFAULT_MAX_INBOUND,
+            prune_target: None,
+            rpc_bind: DEFAULT_RPC_BIND.to_string(),
+            max_upload_rate: None,
+            max_download_rate: None,
+            max_upload_target: None,
+            v2_transport: true,
+            trusted_nodes: vec![],
+            wallet_passphrase_file: None,
+        }
+    }
+}
+
+impl NodeConfig {
+    /// Service flags quảng bá cho peer: node prune chỉ phục vụ block gần tip.
+    /// `pruned`: database đã từng prune (ChainState::is_pruned), kể cả khi
+    /// lần này chạy không có --prune
+    pub fn services(&self, pruned: bool) -> u64 {
+        if self.prune_target.is_some() || pruned {
+            NODE_NETWORK_LIMITED | NODE_COMPACT_FILTERS
+        } else {
+            NODE_NETWORK | NODE_NETWORK_LIMITED | NODE_COMPACT_FILTERS
         }
     }
+
+    /// Address tự quảng bá qua Addr; None nếu chỉ bind vào địa chỉ không cụ thể
+    pub fn advertised_addr(&self) -> Option<SocketAddr> {
+        if self.external_addr.is_some() {
+            return self.external_addr;
+        }
+        self.bind_addr
+            .parse::<SocketAddr>()
+            .ok()
+            .filter(|a| !a.ip().is_unspecified())
+    }
+
+    pub fn peers_file(&self) -> PathBuf {
+        self.datadir.join("peers.dat")
+    }
+
+    pub fn banlist_file(&self) -> PathBuf {
+        self.datadir.join("banlist.dat")
+    }
+
+    /// Master seed đã mã hoá; identity key của node dẫn xuất từ seed này
+    pub fn wallet_file(&self) -> PathBuf {
+        self.datadir.join("wallet.dat")
+    }
 }
@@ -0,0 +1,174 @@
+//! Event bus cho thay đổi của chain và mempool.
+//!
+//! Mỗi subscriber có 1 queue giới hạn và 1 thread riêng: validation chỉ
+//! đẩy event vào queue (không bao giờ chờ), subscriber xử lý event theo
+//! đúng thứ tự phát ra. Queue đầy thì event bị bỏ và subscriber được báo
+//! qua `on_events_dropped` ngay tại vị trí bị hụt để tự đồng bộ lại.
+
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::chain::block::Block;
+use crate::chain::tx::Transaction;
+
+/// Các hàm đều có mặc định rỗng, subscriber chỉ cần implement phần quan tâm
+pub trait ChainListener: Send {
+    /// Block được connect vào active chain
+    fn on_block_connected(&mut self, _block: &Block, _hash: &[u8; 32], _height: u64) {}
+    /// Block bị gỡ khỏi active chain (reorg / invalidateblock)
+    fn on_block_disconnected(&mut self, _block: &Block, _hash: &[u8; 32], _height: u64) {}
+    /// Tip mới sau khi 1 lần activate chain hoàn tất (sau cả chuỗi disconnect / connect)
+    fn on_tip_changed(&mut self, _tip: &[u8; 32], _height: u64) {}
+    /// Tx được nhận vào mempool
+    fn on_tx_accepted(&mut self, _tx: &Transaction, _fee: u64) {}
+    /// `count` event ngay trước event kế tiếp đã bị bỏ vì queue đầy
+    fn on_events_dropped(&mut self, _count: u64) {}
+}
+
+#[derive(Clone)]
+pub enum ChainEvent {
+    BlockConnected {
+        block: Arc<Block>,
+        hash: [u8; 32],
+        height: u64,
+    },
+    BlockDisconnected {
+        block: Arc<Block>,
+        hash: [u8; 32],
+        height: u64,
+    },
+    TipChanged {
+        hash: [u8; 32],
+        height: u64,
+    },
+    TxAccepted {
+        tx: Arc<Transaction>,
+        fee: u64,
+    },
+}
+
+enum Message {
+    Event(ChainEvent),
+    Dropped(u64),
+    /// Báo lại khi đã xử lý hết event phía trước
+    Sync(SyncSender<()>),
+}
+
+struct Subscriber {
+    id: u64,
+    queue: SyncSender<Message>,
+    /// Số event đã bỏ, chưa báo cho subscriber
+    missed: u64,
+}
+
+#[derive(Default)]
+struct Subscribers {
+    next_id: u64,
+    list: Vec<Subscriber>,
+}
+
+/// Handle dùng chung giữa ChainState và Mempool; clone = cùng 1 bus
+#[derive(Clone, Default)]
+pub struct EventBus {
+    inner: Arc<Mutex<Subscribers>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Đăng ký listener với queue tối đa `capacity` event; trả về id để huỷ
+    pub fn subscribe(&self, listener: impl ChainListener + 'static, capacity: usize) -> u64 {
+        let (queue, rx) = sync_channel(capacity.max(1));
+        thread::spawn(move || dispatch(listener, rx));
+
+        let mut subs = self.inner.lock().unwrap();
+        let id = subs.next_id;
+        subs.next_id += 1;
+        subs.list.push(Subscriber { id, queue, missed: 0 });
+        id
+    }
+
+    /// Huỷ đăng ký; event còn trong queue vẫn được xử lý nốt
+    pub fn unsubscribe(&self, id: u64) {
+        self.inner.lock().unwrap().list.retain(|s| s.id != id);
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        !self.inner.lock().unwrap().list.is_empty()
+    }
+
+    /// Đẩy event cho mọi subscriber, không chờ
+    pub fn publish(&self, event: ChainEvent) {
+        let mut subs = self.inner.lock().unwrap();
+
+        // thread của subscriber đã dừng (listener panic) => bỏ luôn
+        subs.list.retain_mut(|sub| {
+            if sub.missed > 0 {
+                match sub.queue.try_send(Message::Dropped(sub.missed)) {
+                    Ok(()) => sub.missed = 0,
+                    Err(TrySendError::Full(_)) => {
+                        sub.missed += 1;
+                        return true;
+                    }
+                    Err(TrySendError::Disconnected(_)) => return false,
+                }
+            }
+
+            match sub.queue.try_send(Message::Event(event.clone())) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    sub.missed += 1;
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+
+    /// Chờ mọi subscriber xử lý xong event đã phát (dùng khi shutdown / test)
+    pub fn sync(&self) {
+        // lấy luôn số event đã bỏ chưa báo, để subscriber biết trước khi sync trả về
+        let queues: Vec<(SyncSender<Message>, u64)> = self
+            .inner
+            .lock()
+            .unwrap()
+            .list
+            .iter_mut()
+            .map(|s| (s.queue.clone(), std::mem::take(&mut s.missed)))
+            .collect();
+
+        for (queue, missed) in queues {
+            if missed > 0 && queue.send(Message::Dropped(missed)).is_err() {
+                continue;
+            }
+            let (done, wait) = sync_channel(1);
+            if queue.send(Message::Sync(done)).is_ok() {
+                let _ = wait.recv();
+            }
+        }
+    }
+}
+
+fn dispatch(mut listener: impl ChainListener, rx: Receiver<Message>) {
+    for msg in rx {
+        match msg {
+            Message::Event(ChainEvent::BlockConnected { block, hash, height }) => {
+                listener.on_block_connected(&block, &hash, height)
+            }
+            Message::Event(ChainEvent::BlockDisconnected { block, hash, height }) => {
+                listener.on_block_disconnected(&block, &hash, height)
+            }
+            Message::Event(ChainEvent::TipChanged { hash, height }) => {
+                listener.on_tip_changed(&hash, height)
+            }
+            Message::Event(ChainEvent::TxAccepted { tx, fee }) => listener.on_tx_accepted(&tx, fee),
+            Message::Dropped(count) => listener.on_events_dropped(count),
+            Message::Sync(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
@@ -6,5 +6,8 @@ pub mod config;
 pub mod storage;
 pub mod cli;
 pub mod mempool;
+pub mod events;
+pub mod rpc;
+pub mod wallet;
 mod orphan;
 mod net;
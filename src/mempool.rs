@@ -1,8 +1,13 @@
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
+use crate::chain::block::Block;
+use crate::chain::sign::verify_tx;
+use crate::chain::state::ChainState;
 use crate::chain::tx::Transaction;
 use crate::chain::txid::txid;
 use crate::chain::utxo::UTXO;
+use crate::events::{ChainEvent, ChainListener, EventBus};
 
 #[derive(Clone)]
 pub struct MempoolTx {
@@ -10,14 +15,25 @@ pub struct MempoolTx {
     pub fee: u64,
 }
 
+#[derive(Default)]
 pub struct Mempool {
     pub txs: HashMap<[u8; 32], MempoolTx>,
+    /// Phát on_tx_accepted, dùng chung bus với ChainState
+    pub events: EventBus,
 }
 
 impl Mempool {
     pub fn new() -> Self {
         Mempool {
             txs: HashMap::new(),
+            events: EventBus::new(),
+        }
+    }
+
+    pub fn with_events(events: EventBus) -> Self {
+        Mempool {
+            txs: HashMap::new(),
+            events,
         }
     }
 
@@ -27,20 +43,48 @@ impl Mempool {
         tx: Transaction,
         utxos: &HashMap<([u8; 32], u32), UTXO>,
     ) -> bool {
+        self.insert(tx, utxos, true)
+    }
+
+    /// Nhận lại tx của block bị gỡ khỏi active chain; không relay lại
+    pub fn readd(&mut self, tx: Transaction, utxos: &HashMap<([u8; 32], u32), UTXO>) -> bool {
+        self.insert(tx, utxos, false)
+    }
+
+    fn insert(&mut self, tx: Transaction, utxos: &HashMap<([u8; 32], u32), UTXO>, announce: bool) -> bool {
         let id = txid(&tx);
         if self.txs.contains_key(&id) {
             return false;
         }
 
+        if !verify_tx(&tx) {
+            return false;
+        }
+
         let fee = match calc_fee(&tx, utxos) {
             Some(f) => f,
             None => return false,
         };
 
+        if announce && self.events.has_subscribers() {
+            self.events.publish(ChainEvent::TxAccepted {
+                tx: Arc::new(tx.clone()),
+                fee,
+            });
+        }
+
         self.txs.insert(id, MempoolTx { tx, fee });
         true
     }
 
+    /// Bỏ tx có input không còn trong `utxos` (đã vào block, hoặc xung đột
+    /// với tx trong block); trả số tx đã bỏ
+    pub fn remove_spent(&mut self, utxos: &HashMap<([u8; 32], u32), UTXO>) -> usize {
+        let before = self.txs.len();
+        self.txs.retain(|_, m| m.tx.inputs.iter().all(|i| utxos.contains_key(&(i.prev_txid, i.vout))));
+        before - self.txs.len()
+    }
+
     /// Remove tx after it is mined
     pub fn remove(&mut self, tx: &Transaction) {
         let id = txid(tx);
@@ -57,7 +101,7 @@ impl Mempool {
         let mut list: Vec<MempoolTx> = self.txs.values().cloned().collect();
 
         // sort by fee descending
-        list.sort_by(|a, b| b.fee.cmp(&a.fee));
+        list.sort_by_key(|m| std::cmp::Reverse(m.fee));
 
         list.into_iter()
             .take(max)
@@ -86,3 +130,44 @@ fn calc_fee(
         Some(input_sum - output_sum)
     }
 }
+
+/// Giữ mempool khớp với active chain: bỏ tx đã vào block / xung đột,
+/// nhận lại tx của block bị gỡ khi reorg
+struct MempoolListener {
+    chain: Arc<Mutex<ChainState>>,
+    mempool: Arc<Mutex<Mempool>>,
+}
+
+impl MempoolListener {
+    /// Event xử lý sau khi chain đã đi tiếp, nên luôn so với UTXO set hiện tại
+    fn sync(&self) {
+        let chain = self.chain.lock().unwrap();
+        let removed = self.mempool.lock().unwrap().remove_spent(&chain.utxos);
+        if removed > 0 {
+            log::debug!("removed {} mempool txs spent by the active chain", removed);
+        }
+    }
+}
+
+impl ChainListener for MempoolListener {
+    fn on_block_connected(&mut self, _block: &Block, _hash: &[u8; 32], _height: u64) {
+        self.sync();
+    }
+
+    fn on_block_disconnected(&mut self, block: &Block, _hash: &[u8; 32], _height: u64) {
+        let chain = self.chain.lock().unwrap();
+        let mut mempool = self.mempool.lock().unwrap();
+        for tx in block.transactions.iter().filter(|tx| !tx.is_coinbase()) {
+            mempool.readd(tx.clone(), &chain.utxos);
+        }
+    }
+
+    fn on_events_dropped(&mut self, _count: u64) {
+        self.sync();
+    }
+}
+
+/// Cập nhật `mempool` theo block connect / disconnect trên `events`
+pub fn subscribe_mempool(chain: Arc<Mutex<ChainState>>, mempool: Arc<Mutex<Mempool>>, events: &EventBus) {
+    events.subscribe(MempoolListener { chain, mempool }, 10_000);
+}
@@ -1,60 +1,318 @@
-use std::collections::HashMap;
-use std::time::{Instant, Duration};
+//! Điểm misbehavior và danh sách ban.
+//!
+//! Mỗi lỗi của peer (`Misbehavior`) cộng 1 số điểm cho IP của nó; đủ
+//! BAN_THRESHOLD thì IP bị ban BAN_TIME. Ban áp dụng cho cả subnet (CIDR),
+//! có hạn và lý do, được lưu xuống `banlist.dat` trong datadir ngay khi
+//! thay đổi (bincode, ghi file tạm rồi rename) nên còn nguyên sau restart.
+//! Điểm thì chỉ giữ trong bộ nhớ.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
 
 const BAN_THRESHOLD: i32 = 100;
-const BAN_TIME: Duration = Duration::from_secs(60 * 60);
+/// Thời gian ban mặc định, cả tự động lẫn `setban`
+pub const BAN_TIME: Duration = Duration::from_secs(60 * 60);
+const BANLIST_FILE_VERSION: u32 = 1;
+
+/// Lỗi của peer, mỗi loại có điểm riêng
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Misbehavior {
+    /// Frame hỏng (checksum, payload không decode được)
+    MalformedMessage,
+    /// Version / verack sai lúc handshake hoặc gửi lại sau handshake
+    HandshakeViolation,
+    /// Headers / Inv / GetData / Addr quá nhiều item
+    OversizedMessage,
+    InvalidHeader,
+    InvalidBlock,
+    /// Compact block sai định dạng
+    InvalidCompactBlock,
+    /// GetBlockTxn index vượt số tx, hoặc block không có
+    BadBlockTxnRequest,
+    /// GetCFilters / GetCFHeaders khoảng không hợp lệ
+    BadFilterRequest,
+    /// Liên tục gửi Headers không nối được vào chain (xem MAX_UNCONNECTING_HEADERS)
+    UnconnectingHeaders,
+}
+
+impl Misbehavior {
+    pub fn score(self) -> i32 {
+        match self {
+            Misbehavior::InvalidHeader | Misbehavior::InvalidCompactBlock => 100,
+            Misbehavior::OversizedMessage
+            | Misbehavior::BadBlockTxnRequest
+            | Misbehavior::UnconnectingHeaders => 20,
+            Misbehavior::MalformedMessage
+            | Misbehavior::HandshakeViolation
+            | Misbehavior::BadFilterRequest => 10,
+            // block có thể chỉ thua ở nhánh khác, không phạt nặng
+            Misbehavior::InvalidBlock => 5,
+        }
+    }
+}
+
+impl fmt::Display for Misbehavior {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Misbehavior::MalformedMessage => "malformed message",
+            Misbehavior::HandshakeViolation => "handshake violation",
+            Misbehavior::OversizedMessage => "oversized message",
+            Misbehavior::InvalidHeader => "invalid header",
+            Misbehavior::InvalidBlock => "invalid block",
+            Misbehavior::InvalidCompactBlock => "invalid compact block",
+            Misbehavior::BadBlockTxnRequest => "bad getblocktxn",
+            Misbehavior::BadFilterRequest => "bad filter request",
+            Misbehavior::UnconnectingHeaders => "unconnecting headers",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Dải IP dạng CIDR; IP đơn là /32 (IPv4) hoặc /128 (IPv6)
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Subnet {
+    network: IpAddr,
+    prefix: u8,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SubnetParseError(String);
+
+impl fmt::Display for SubnetParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid subnet: {}", self.0)
+    }
+}
+
+impl std::error::Error for SubnetParseError {}
+
+/// IPv4-mapped IPv6 coi như IPv4
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        v4 => v4,
+    }
+}
+
+fn ip_bits(ip: &IpAddr) -> (u128, u8) {
+    match ip {
+        IpAddr::V4(v4) => (u32::from(*v4) as u128, 32),
+        IpAddr::V6(v6) => (u128::from(*v6), 128),
+    }
+}
+
+impl Subnet {
+    /// Subnet chỉ chứa `ip`
+    pub fn single(ip: IpAddr) -> Self {
+        let ip = canonical(ip);
+        Self { network: ip, prefix: ip_bits(&ip).1 }
+    }
+
+    /// Phần host của `ip` bị xoá, vd 10.1.2.3/16 -> 10.1.0.0/16
+    pub fn new(ip: IpAddr, prefix: u8) -> Option<Self> {
+        let ip = canonical(ip);
+        let (bits, width) = ip_bits(&ip);
+        if prefix > width {
+            return None;
+        }
+        let masked = bits & Self::mask(prefix, width);
+        let network = match ip {
+            IpAddr::V4(_) => IpAddr::V4((masked as u32).into()),
+            IpAddr::V6(_) => IpAddr::V6(masked.into()),
+        };
+        Some(Self { network, prefix })
+    }
+
+    fn mask(prefix: u8, width: u8) -> u128 {
+        let all = if width == 32 { u32::MAX as u128 } else { u128::MAX };
+        all & !all.checked_shr(prefix as u32).unwrap_or(0)
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let ip = canonical(*ip);
+        let (bits, width) = ip_bits(&ip);
+        let (net, net_width) = ip_bits(&self.network);
+        width == net_width && bits & Self::mask(self.prefix, width) == net
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = SubnetParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || SubnetParseError(s.to_string());
+        match s.split_once('/') {
+            Some((ip, prefix)) => {
+                let ip: IpAddr = ip.parse().map_err(|_| err())?;
+                let prefix: u8 = prefix.parse().map_err(|_| err())?;
+                Subnet::new(ip, prefix).ok_or_else(err)
+            }
+            None => s.parse().map(Subnet::single).map_err(|_| err()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BanReason {
+    /// Điểm misbehavior đủ ngưỡng; lưu lỗi cuối cùng
+    Misbehaving(Misbehavior),
+    /// `setban`
+    Manual,
+}
 
-#[derive(Clone)]
-pub struct PeerScore {
-    pub score: i32,
-    pub banned_until: Option<Instant>,
+impl fmt::Display for BanReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BanReason::Misbehaving(m) => write!(f, "misbehaving ({})", m),
+            BanReason::Manual => f.write_str("manually added"),
+        }
+    }
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct BanEntry {
+    /// Unix time
+    pub created: u64,
+    pub until: u64,
+    pub reason: BanReason,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BanListFile {
+    version: u32,
+    bans: Vec<(Subnet, BanEntry)>,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[derive(Default)]
 pub struct BanManager {
-    peers: HashMap<String, PeerScore>,
+    scores: HashMap<IpAddr, i32>,
+    bans: BTreeMap<Subnet, BanEntry>,
+    /// banlist.dat; None = chỉ giữ trong bộ nhớ
+    path: Option<PathBuf>,
 }
 
 impl BanManager {
     pub fn new() -> Self {
-        Self {
-            peers: HashMap::new(),
+        Self::default()
+    }
+
+    /// Danh sách rỗng, thay đổi được ghi vào `path`
+    pub fn with_file(path: &Path) -> Self {
+        Self { path: Some(path.to_path_buf()), ..Self::default() }
+    }
+
+    /// Đọc banlist.dat (bỏ ban đã hết hạn); thay đổi sau này ghi lại vào `path`
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut man = Self::with_file(path);
+        let bytes = match fs::read(path) {
+            Ok(b) => b,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(man),
+            Err(e) => return Err(e),
+        };
+
+        let file: BanListFile = bincode::deserialize(&bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if file.version != BANLIST_FILE_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported banlist.dat version"));
         }
+        let now = unix_now();
+        man.bans = file.bans.into_iter().filter(|(_, e)| e.until > now).collect();
+        Ok(man)
     }
 
-    pub fn add_score(&mut self, ip: &str, delta: i32) -> bool {
-        let entry = self.peers.entry(ip.to_string()).or_insert(
-            PeerScore {
-                score: 0,
-                banned_until: None,
-            }
-        );
+    fn save(&self) {
+        let path = match &self.path {
+            Some(p) => p,
+            None => return,
+        };
+        let file = BanListFile {
+            version: BANLIST_FILE_VERSION,
+            bans: self.bans.iter().map(|(s, e)| (*s, *e)).collect(),
+        };
+        let tmp = path.with_extension("dat.tmp");
+        let result = fs::write(&tmp, bincode::serialize(&file).unwrap())
+            .and_then(|_| fs::rename(&tmp, path));
+        if let Err(e) = result {
+            log::warn!("cannot write {}: {}", path.display(), e);
+        }
+    }
+
+    /// Cộng điểm cho `ip`; true nếu IP vừa bị ban (người gọi nên ngắt kết nối)
+    pub fn misbehaving(&mut self, ip: IpAddr, what: Misbehavior) -> bool {
+        let ip = canonical(ip);
+        let score = self.scores.entry(ip).or_insert(0);
 
         // decay nhẹ
-        entry.score -= 1;
-        if entry.score < 0 {
-            entry.score = 0;
+        *score = (*score - 1).max(0) + what.score();
+        if *score < BAN_THRESHOLD {
+            return false;
         }
 
-        entry.score += delta;
+        self.scores.remove(&ip);
+        log::info!("banning {}: {}", ip, what);
+        self.ban(Subnet::single(ip), BAN_TIME, BanReason::Misbehaving(what));
+        true
+    }
+
+    /// Ban `subnet` trong `duration`, ghi đè ban cũ của đúng subnet đó
+    pub fn ban(&mut self, subnet: Subnet, duration: Duration, reason: BanReason) {
+        let created = unix_now();
+        let until = created.saturating_add(duration.as_secs());
+        self.bans.insert(subnet, BanEntry { created, until, reason });
+        self.save();
+    }
 
-        if entry.score >= BAN_THRESHOLD {
-            entry.banned_until = Some(Instant::now() + BAN_TIME);
-            return true;
+    /// false nếu subnet không bị ban
+    pub fn unban(&mut self, subnet: &Subnet) -> bool {
+        let removed = self.bans.remove(subnet).is_some();
+        if removed {
+            self.save();
         }
-        false
+        removed
     }
 
-    pub fn is_banned(&mut self, ip: &str) -> bool {
-        if let Some(peer) = self.peers.get_mut(ip) {
-            if let Some(until) = peer.banned_until {
-                if Instant::now() < until {
-                    return true;
-                } else {
-                    peer.banned_until = None;
-                    peer.score = 0;
-                }
-            }
+    pub fn clear(&mut self) {
+        self.bans.clear();
+        self.scores.clear();
+        self.save();
+    }
+
+    /// Các ban còn hiệu lực
+    pub fn list(&mut self) -> Vec<(Subnet, BanEntry)> {
+        self.sweep();
+        self.bans.iter().map(|(s, e)| (*s, *e)).collect()
+    }
+
+    pub fn is_banned(&mut self, ip: &IpAddr) -> bool {
+        self.sweep();
+        self.bans.keys().any(|s| s.contains(ip))
+    }
+
+    /// Bỏ ban đã hết hạn
+    fn sweep(&mut self) {
+        let now = unix_now();
+        let before = self.bans.len();
+        self.bans.retain(|_, e| e.until > now);
+        if self.bans.len() != before {
+            self.save();
         }
-        false
     }
 }
@@ -1,30 +1,222 @@
-use std::collections::HashMap;
-use std::time::{Instant, Duration};
+//! Giới hạn tốc độ bằng token bucket.
+//!
+//! Mỗi peer có 1 bucket cho từng loại message (`MessageCategory`), tính
+//! theo số item (tx, inv, getdata, addr). Hết token thì event loop ngừng
+//! đọc socket của peer tới khi bucket hồi lại, riêng address vượt mức bị
+//! bỏ bớt. Toàn node có thêm giới hạn byte upload / download mỗi giây và
+//! tổng upload mỗi ngày (`maxuploadtarget`): vượt mức ngày thì không phục
+//! vụ block cũ nữa.
 
-const WINDOW: Duration = Duration::from_secs(1);
-const MAX_MSG_PER_WINDOW: u32 = 50;
+use std::time::{Duration, Instant};
 
-pub struct RateLimiter {
-    peers: HashMap<String, (u32, Instant)>,
+use crate::p2p::message::Message;
+
+/// Thời gian 1 chu kỳ của maxuploadtarget
+pub const UPLOAD_TARGET_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Bucket chứa tối đa `capacity` token, hồi `rate` token mỗi giây. Lấy quá
+/// số token đang có thì thành nợ, phải chờ hồi đủ mới lấy tiếp được.
+pub struct TokenBucket {
+    capacity: f64,
+    rate: f64,
+    tokens: f64,
+    last: Instant,
 }
 
-impl RateLimiter {
-    pub fn new() -> Self {
+impl TokenBucket {
+    pub fn new(rate: f64, capacity: f64, now: Instant) -> Self {
+        Self { capacity, rate, tokens: capacity, last: now }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+    }
+
+    /// Lấy `n` token (có thể nợ); trả thời gian phải chờ nếu đang nợ
+    pub fn take(&mut self, n: f64, now: Instant) -> Option<Duration> {
+        self.refill(now);
+        self.tokens -= n;
+        self.wait()
+    }
+
+    /// Lấy tối đa `n` token không nợ; trả số token lấy được
+    pub fn take_up_to(&mut self, n: f64, now: Instant) -> f64 {
+        let taken = n.min(self.available(now)).floor();
+        self.tokens -= taken;
+        taken
+    }
+
+    pub fn available(&mut self, now: Instant) -> f64 {
+        self.refill(now);
+        self.tokens.max(0.0)
+    }
+
+    /// Thời gian tới khi có lại ít nhất 1 token; None nếu đang có
+    pub fn wait(&self) -> Option<Duration> {
+        if self.tokens >= 1.0 {
+            return None;
+        }
+        Some(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageCategory {
+    Tx,
+    Inv,
+    GetData,
+    Addr,
+    Other,
+}
+
+impl MessageCategory {
+    pub fn of(msg: &Message) -> Self {
+        match msg {
+            Message::Tx { .. } => MessageCategory::Tx,
+            Message::Inv { .. } => MessageCategory::Inv,
+            Message::GetData { .. } => MessageCategory::GetData,
+            Message::Addr { .. } => MessageCategory::Addr,
+            _ => MessageCategory::Other,
+        }
+    }
+
+    /// (token mỗi giây, burst)
+    fn limits(self) -> (f64, f64) {
+        match self {
+            MessageCategory::Tx => (50.0, 500.0),
+            MessageCategory::Inv => (1000.0, 50_000.0),
+            MessageCategory::GetData => (1000.0, 50_000.0),
+            // address gossip chậm: 1 address mỗi 10s, đủ cho 1 lần trả GetAddr
+            MessageCategory::Addr => (0.1, 1000.0),
+            MessageCategory::Other => (200.0, 1000.0),
+        }
+    }
+}
+
+/// Số token 1 message tốn
+fn cost(msg: &Message) -> usize {
+    match msg {
+        Message::Inv { items } | Message::GetData { items } => items.len(),
+        Message::Addr { addrs } => addrs.len(),
+        _ => 1,
+    }
+}
+
+/// Bucket theo loại message của 1 peer
+pub struct PeerRateLimits {
+    buckets: [TokenBucket; 5],
+}
+
+impl PeerRateLimits {
+    pub fn new(now: Instant) -> Self {
+        let bucket = |c: MessageCategory| {
+            let (rate, burst) = c.limits();
+            TokenBucket::new(rate, burst, now)
+        };
         Self {
-            peers: HashMap::new(),
+            buckets: [
+                bucket(MessageCategory::Tx),
+                bucket(MessageCategory::Inv),
+                bucket(MessageCategory::GetData),
+                bucket(MessageCategory::Addr),
+                bucket(MessageCategory::Other),
+            ],
         }
     }
 
-    pub fn allow(&mut self, ip: &str) -> bool {
+    /// Tính token cho message vừa nhận. Address vượt mức bị bỏ khỏi `msg`;
+    /// loại khác trả thời gian cần ngừng đọc của peer.
+    pub fn charge(&mut self, msg: &mut Message, now: Instant) -> Option<Duration> {
+        let category = MessageCategory::of(msg);
+        let bucket = &mut self.buckets[category as usize];
+        if let Message::Addr { addrs } = msg {
+            let allowed = bucket.take_up_to(addrs.len() as f64, now) as usize;
+            if allowed < addrs.len() {
+                log::debug!("dropping {} addresses over rate limit", addrs.len() - allowed);
+                addrs.truncate(allowed);
+            }
+            return None;
+        }
+        bucket.take(cost(msg) as f64, now)
+    }
+}
+
+/// Tổng upload trong chu kỳ UPLOAD_TARGET_WINDOW
+struct UploadTarget {
+    limit: u64,
+    window_start: Instant,
+    sent: u64,
+}
+
+/// Giới hạn băng thông toàn node, dùng chung giữa event loop và worker
+pub struct Bandwidth {
+    upload: Option<TokenBucket>,
+    download: Option<TokenBucket>,
+    target: Option<UploadTarget>,
+    pub total_sent: u64,
+    pub total_recv: u64,
+}
+
+impl Bandwidth {
+    /// Giới hạn byte/s upload, download và byte upload mỗi ngày; None = không giới hạn.
+    /// Burst bằng 1 giây.
+    pub fn new(upload: Option<u64>, download: Option<u64>, upload_target: Option<u64>) -> Self {
         let now = Instant::now();
-        let entry = self.peers.entry(ip.to_string()).or_insert((0, now));
+        let bucket = |rate: u64| TokenBucket::new(rate as f64, rate as f64, now);
+        Self {
+            upload: upload.map(bucket),
+            download: download.map(bucket),
+            target: upload_target.map(|limit| UploadTarget { limit, window_start: now, sent: 0 }),
+            total_sent: 0,
+            total_recv: 0,
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(None, None, None)
+    }
 
-        if now.duration_since(entry.1) > WINDOW {
-            entry.0 = 0;
-            entry.1 = now;
+    /// Ghi nhận byte đã đọc; trả thời gian cần ngừng đọc nếu vượt mức
+    pub fn on_receive(&mut self, bytes: usize, now: Instant) -> Option<Duration> {
+        self.total_recv += bytes as u64;
+        self.download.as_mut()?.take(bytes as f64, now)
+    }
+
+    /// Số byte (tối đa `wanted`) được ghi ngay bây giờ
+    pub fn upload_allowance(&mut self, wanted: usize, now: Instant) -> usize {
+        match &mut self.upload {
+            Some(bucket) => (bucket.available(now) as usize).min(wanted),
+            None => wanted,
         }
+    }
 
-        entry.0 += 1;
-        entry.0 <= MAX_MSG_PER_WINDOW
+    /// Thời gian tới khi ghi tiếp được
+    pub fn upload_wait(&self) -> Option<Duration> {
+        self.upload.as_ref()?.wait()
+    }
+
+    /// Ghi nhận byte đã ghi ra socket
+    pub fn on_send(&mut self, bytes: usize, now: Instant) {
+        self.total_sent += bytes as u64;
+        if let Some(bucket) = &mut self.upload {
+            bucket.take(bytes as f64, now);
+        }
+        if let Some(target) = &mut self.target {
+            if now.duration_since(target.window_start) >= UPLOAD_TARGET_WINDOW {
+                target.window_start = now;
+                target.sent = 0;
+            }
+            target.sent += bytes as u64;
+        }
+    }
+
+    /// Đã upload hết maxuploadtarget trong chu kỳ hiện tại
+    pub fn upload_target_reached(&self, now: Instant) -> bool {
+        match &self.target {
+            Some(t) => now.duration_since(t.window_start) < UPLOAD_TARGET_WINDOW && t.sent >= t.limit,
+            None => false,
+        }
     }
 }
@@ -1,45 +1,144 @@
+use std::collections::HashSet;
+use std::io;
 use std::net::TcpListener;
-use std::sync::{Arc, Mutex};
+use std::path::Path;
+use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
+
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
 
 use crate::config::NodeConfig;
 use crate::chain::state::ChainState;
-use crate::mempool::Mempool;
-use crate::orphan::tx::OrphanTxPool;
-use crate::orphan::block::OrphanBlockPool;
-use crate::p2p::peer::handle_peer;
 use crate::net::ban::BanManager;
-use crate::net::rate::RateLimiter;
+use crate::mempool::subscribe_mempool;
+use crate::net::rate::Bandwidth;
+use crate::p2p::addrman::AddrMan;
+use crate::p2p::network::OutboundManager;
+use crate::p2p::peer::PeerContext;
+use crate::p2p::keepalive::start_keepalive;
+use crate::p2p::relay::start_relay;
+use crate::rpc::start_rpc;
+use crate::wallet::derive::derive_key;
+use crate::wallet::persist::load_wallet;
+use crate::wallet::role::Role;
+
+/// Ghi peers.dat (nếu có thay đổi) sau mỗi khoảng này
+const PEERS_DUMP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Identity key của node: key đầu tiên dưới Role::Node của master seed
+/// trong wallet, giữ nguyên qua các lần chạy
+pub fn load_node_identity(wallet: &Path, passphrase: &str) -> io::Result<SecretKey> {
+    let seed = load_wallet(wallet, passphrase)?;
+    Ok(derive_key(&seed, &Role::Node, 0).secret)
+}
+
+/// Passphrase wallet từ file, bỏ newline ở cuối
+pub fn read_passphrase(path: &Path) -> io::Result<String> {
+    let text = std::fs::read_to_string(path)?;
+    Ok(text.trim_end_matches(['\r', '\n']).to_string())
+}
 
 pub fn run_node(config: NodeConfig, chain: ChainState) {
-    let chain = Arc::new(Mutex::new(chain));
-    let mempool = Arc::new(Mutex::new(Mempool::new()));
-    let orphan_tx = Arc::new(Mutex::new(OrphanTxPool::new()));
-    let orphan_block = Arc::new(Mutex::new(OrphanBlockPool::new()));
-    let ban = Arc::new(Mutex::new(BanManager::new()));
-    let rate = Arc::new(Mutex::new(RateLimiter::new()));
+    // peers.dat hỏng thì bắt đầu lại với bảng rỗng
+    let peers_file = config.peers_file();
+    let addrman = AddrMan::load(&peers_file).unwrap_or_else(|e| {
+        log::warn!("cannot read {}: {}", peers_file.display(), e);
+        AddrMan::new()
+    });
+    log::info!("loaded {} peer addresses", addrman.len());
 
-    let listener = TcpListener::bind(&config.bind_addr).unwrap();
-    println!("Listening on {}", config.bind_addr);
+    // id ngẫu nhiên mỗi lần chạy, để nhận ra kết nối tới chính mình
+    let node_id: [u8; 32] = rand::random();
+    let services = config.services(chain.is_pruned());
+    let mut ctx = PeerContext::new(chain, addrman, node_id, services);
+    ctx.local_addr = config.advertised_addr();
+    ctx.max_inbound = config.max_inbound;
+    ctx.v2_transport = config.v2_transport;
 
-    for stream in listener.incoming() {
-        if let Ok(stream) = stream {
-            let ip = stream.peer_addr().unwrap().ip().to_string();
+    // không mở được wallet thì chạy ẩn danh, không nhận được quyền node tin cậy
+    let wallet_file = config.wallet_file();
+    let identity = config
+        .wallet_passphrase_file
+        .as_ref()
+        .map(|file| read_passphrase(file).and_then(|p| load_node_identity(&wallet_file, &p)));
+    match identity {
+        Some(Ok(sk)) => {
+            let pk = PublicKey::from_secret_key(&Secp256k1::signing_only(), &sk);
+            log::info!("node identity {}", pk);
+            ctx.identity = Some(sk);
+        }
+        Some(Err(e)) => log::warn!("cannot open {}: {}", wallet_file.display(), e),
+        None => log::info!("no wallet passphrase given, running without node identity"),
+    }
+    ctx.trusted = Arc::new(config.trusted_nodes.iter().copied().collect::<HashSet<_>>());
 
-            if ban.lock().unwrap().is_banned(&ip) {
-                continue;
-            }
+    // banlist.dat hỏng thì bắt đầu lại không có ban nào
+    let banlist_file = config.banlist_file();
+    let bans = BanManager::load(&banlist_file).unwrap_or_else(|e| {
+        log::warn!("cannot read {}: {}", banlist_file.display(), e);
+        BanManager::with_file(&banlist_file)
+    });
+    *ctx.ban.lock().unwrap() = bans;
+    *ctx.bandwidth.lock().unwrap() = Bandwidth::new(
+        config.max_upload_rate,
+        config.max_download_rate,
+        config.max_upload_target,
+    );
+    ctx.start();
 
-            let c = chain.clone();
-            let m = mempool.clone();
-            let ot = orphan_tx.clone();
-            let ob = orphan_block.clone();
-            let b = ban.clone();
-            let r = rate.clone();
+    let events = ctx.chain.lock().unwrap().events.clone();
+    start_relay(ctx.peers.clone(), &events);
+    subscribe_mempool(ctx.chain.clone(), ctx.mempool.clone(), &events);
+    start_keepalive(ctx.peers.clone(), ctx.download.clone());
 
-            thread::spawn(move || {
-                handle_peer(stream, ip, c, m, ot, ob, b, r);
-            });
+    match start_rpc(&config.rpc_bind, ctx.clone()) {
+        Ok(addr) => log::info!("rpc listening on {}", addr),
+        Err(e) => log::warn!("cannot start rpc on {}: {}", config.rpc_bind, e),
+    }
+
+    let addrman = ctx.addrman.clone();
+    thread::spawn(move || loop {
+        thread::sleep(PEERS_DUMP_INTERVAL);
+        if let Err(e) = addrman.lock().unwrap().save(&peers_file) {
+            log::warn!("cannot write {}: {}", peers_file.display(), e);
         }
+    });
+
+    // validate lịch sử dưới snapshot UTXO set, nhả lock giữa các batch.
+    // Snapshot sai thì UTXO set đang dùng sai: dừng node ngay khi còn giữ
+    // lock, không phục vụ / nối thêm block nào trên nó
+    if ctx.chain.lock().unwrap().snapshot_pending() {
+        let c = ctx.chain.clone();
+        thread::spawn(move || loop {
+            let (done, pending) = {
+                let mut chain = c.lock().unwrap();
+                let done = chain.validate_snapshot_step(100);
+                if chain.db.snapshot_invalid() {
+                    chain.db.flush();
+                    log::error!("utxo snapshot failed background validation, shutting down; run reindex");
+                    std::process::exit(1);
+                }
+                (done, chain.snapshot_pending())
+            };
+            if !pending {
+                break;
+            }
+            if done == 0 {
+                thread::sleep(Duration::from_secs(1));
+            }
+        });
+    }
+
+    OutboundManager::new(&config.peers, config.max_outbound).start(ctx.clone());
+
+    let listener = TcpListener::bind(&config.bind_addr).unwrap();
+    println!("Listening on {}", config.bind_addr);
+
+    ctx.net.listen(listener).expect("cannot start listener");
+
+    // mọi việc chạy trên các thread nền
+    loop {
+        thread::park();
     }
 }
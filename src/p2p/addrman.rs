@@ -0,0 +1,534 @@
+//! Address manager: các address peer đã biết, dùng cho kết nối outbound.
+//!
+//! Address mới nghe qua `Addr` nằm trong bảng *new*, address đã kết nối
+//! thành công được chuyển sang bảng *tried*. Vị trí trong bảng được tính
+//! bằng SipHash với key bí mật của node, theo network group (/16 IPv4,
+//! /32 IPv6) của address và của peer đã gửi nó: 1 kẻ tấn công chỉ kiểm
+//! soát vài dải IP thì chỉ chiếm được vài bucket, không lấp được cả bảng.
+//!
+//! Lưu xuống `peers.dat` trong datadir (bincode, ghi file tạm rồi rename).
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::seq::IteratorRandom;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+
+use crate::chain::siphash::siphash24;
+
+pub const NEW_BUCKET_COUNT: u64 = 1024;
+pub const TRIED_BUCKET_COUNT: u64 = 256;
+pub const BUCKET_SIZE: u64 = 64;
+/// 1 network group chỉ rơi vào tối đa chừng này bucket tried
+const TRIED_BUCKETS_PER_GROUP: u64 = 8;
+/// 1 nguồn gửi chỉ rơi vào tối đa chừng này bucket new
+const NEW_BUCKETS_PER_SOURCE_GROUP: u64 = 64;
+
+/// Số address tối đa trong 1 message Addr
+pub const MAX_ADDR_TO_SEND: usize = 1000;
+/// GetAddr trả tối đa chừng này % số address đã biết
+const GETADDR_PERCENT: usize = 23;
+
+const HORIZON_DAYS: u64 = 30;
+const MAX_RETRIES: u32 = 3;
+const MIN_FAIL_DAYS: u64 = 7;
+const MAX_FAILURES: u32 = 10;
+
+const PEERS_FILE_VERSION: u32 = 1;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Address quảng bá trong message Addr
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct NetAddress {
+    pub addr: SocketAddr,
+    pub services: u64,
+    /// Lần cuối address được thấy hoạt động (unix time)
+    pub time: u64,
+}
+
+/// Network group: address cùng group coi như cùng 1 nhà cung cấp
+pub fn network_group(ip: &IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(v4) => {
+            let o = v4.octets();
+            vec![4, o[0], o[1]]
+        }
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => network_group(&IpAddr::V4(v4)),
+            None => {
+                let mut group = vec![6];
+                group.extend_from_slice(&v6.octets()[..4]);
+                group
+            }
+        },
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AddrInfo {
+    pub addr: SocketAddr,
+    pub services: u64,
+    pub last_seen: u64,
+    pub last_try: u64,
+    pub last_success: u64,
+    /// Số lần thử liên tiếp không thành công
+    pub attempts: u32,
+    /// Group của peer đã gửi address này
+    pub source_group: Vec<u8>,
+    pub in_tried: bool,
+}
+
+impl AddrInfo {
+    /// Address nên bị bỏ: quá cũ, thời gian ở tương lai, hoặc thử mãi không được
+    pub fn is_terrible(&self, now: u64) -> bool {
+        // vừa thử trong 1 phút: chưa kết luận
+        if self.last_try > 0 && now.saturating_sub(self.last_try) < 60 {
+            return false;
+        }
+        if self.last_seen > now + 10 * 60 {
+            return true;
+        }
+        if now.saturating_sub(self.last_seen) > HORIZON_DAYS * 86400 {
+            return true;
+        }
+        if self.last_success == 0 && self.attempts >= MAX_RETRIES {
+            return true;
+        }
+        now.saturating_sub(self.last_success) > MIN_FAIL_DAYS * 86400 && self.attempts >= MAX_FAILURES
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PeersFile {
+    version: u32,
+    key: (u64, u64),
+    addrs: Vec<AddrInfo>,
+}
+
+pub struct AddrMan {
+    key: (u64, u64),
+    infos: HashMap<SocketAddr, AddrInfo>,
+    /// (bucket, position) -> address
+    new_table: HashMap<(u64, u64), SocketAddr>,
+    tried_table: HashMap<(u64, u64), SocketAddr>,
+    /// Có thay đổi chưa ghi xuống peers.dat
+    dirty: bool,
+}
+
+impl Default for AddrMan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AddrMan {
+    pub fn new() -> Self {
+        let mut rng = rand::thread_rng();
+        Self::with_key((rng.gen(), rng.gen()))
+    }
+
+    fn with_key(key: (u64, u64)) -> Self {
+        Self {
+            key,
+            infos: HashMap::new(),
+            new_table: HashMap::new(),
+            tried_table: HashMap::new(),
+            dirty: false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.infos.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.infos.is_empty()
+    }
+
+    pub fn tried_count(&self) -> usize {
+        self.tried_table.len()
+    }
+
+    pub fn get(&self, addr: &SocketAddr) -> Option<&AddrInfo> {
+        self.infos.get(addr)
+    }
+
+    fn hash(&self, parts: &[&[u8]]) -> u64 {
+        let data: Vec<u8> = parts.concat();
+        siphash24(self.key.0, self.key.1, &data)
+    }
+
+    fn addr_bytes(addr: &SocketAddr) -> Vec<u8> {
+        let mut out = match addr.ip() {
+            IpAddr::V4(v4) => v4.octets().to_vec(),
+            IpAddr::V6(v6) => v6.octets().to_vec(),
+        };
+        out.extend_from_slice(&addr.port().to_le_bytes());
+        out
+    }
+
+    fn tried_slot(&self, addr: &SocketAddr) -> (u64, u64) {
+        let a = Self::addr_bytes(addr);
+        let group = network_group(&addr.ip());
+        let h1 = self.hash(&[&a]) % TRIED_BUCKETS_PER_GROUP;
+        let bucket = self.hash(&[&group, &h1.to_le_bytes()]) % TRIED_BUCKET_COUNT;
+        (bucket, self.position(b"T", bucket, &a))
+    }
+
+    fn new_slot(&self, addr: &SocketAddr, source_group: &[u8]) -> (u64, u64) {
+        let a = Self::addr_bytes(addr);
+        let group = network_group(&addr.ip());
+        let h1 = self.hash(&[&group, source_group]) % NEW_BUCKETS_PER_SOURCE_GROUP;
+        let bucket = self.hash(&[source_group, &h1.to_le_bytes()]) % NEW_BUCKET_COUNT;
+        (bucket, self.position(b"N", bucket, &a))
+    }
+
+    fn position(&self, table: &[u8], bucket: u64, addr: &[u8]) -> u64 {
+        self.hash(&[table, &bucket.to_le_bytes(), addr]) % BUCKET_SIZE
+    }
+
+    fn remove(&mut self, addr: &SocketAddr) {
+        if let Some(info) = self.infos.remove(addr) {
+            if info.in_tried {
+                self.tried_table.remove(&self.tried_slot(addr));
+            } else {
+                self.new_table.remove(&self.new_slot(addr, &info.source_group));
+            }
+        }
+    }
+
+    /// Thêm address nghe từ peer `source`; trả về số address mới
+    pub fn add(&mut self, addrs: &[NetAddress], source: &IpAddr) -> usize {
+        let now = now();
+        let source_group = network_group(source);
+        let mut added = 0;
+
+        for na in addrs {
+            if na.addr.port() == 0 || na.addr.ip().is_unspecified() {
+                continue;
+            }
+            // timestamp ở tương lai: không tin, coi như đã thấy từ 5 ngày trước
+            let time = if na.time > now + 10 * 60 { now - 5 * 86400 } else { na.time };
+
+            if let Some(info) = self.infos.get_mut(&na.addr) {
+                info.services |= na.services;
+                if time > info.last_seen {
+                    info.last_seen = time;
+                    self.dirty = true;
+                }
+                continue;
+            }
+
+            let slot = self.new_slot(&na.addr, &source_group);
+            if let Some(old) = self.new_table.get(&slot).copied() {
+                // giữ address cũ trừ khi nó đã hỏng
+                if !self.infos[&old].is_terrible(now) {
+                    continue;
+                }
+                self.remove(&old);
+            }
+
+            self.new_table.insert(slot, na.addr);
+            self.infos.insert(na.addr, AddrInfo {
+                addr: na.addr,
+                services: na.services,
+                last_seen: time,
+                last_try: 0,
+                last_success: 0,
+                attempts: 0,
+                source_group: source_group.clone(),
+                in_tried: false,
+            });
+            self.dirty = true;
+            added += 1;
+        }
+        added
+    }
+
+    /// Bắt đầu thử kết nối
+    pub fn attempt(&mut self, addr: &SocketAddr) {
+        if let Some(info) = self.infos.get_mut(addr) {
+            info.last_try = now();
+            info.attempts += 1;
+            self.dirty = true;
+        }
+    }
+
+    /// Kết nối outbound + handshake thành công: chuyển sang bảng tried
+    pub fn good(&mut self, addr: &SocketAddr, services: u64) {
+        let now = now();
+        let info = match self.infos.get_mut(addr) {
+            Some(i) => i,
+            None => return,
+        };
+        info.last_success = now;
+        info.last_seen = now;
+        info.last_try = now;
+        info.attempts = 0;
+        info.services = services;
+        self.dirty = true;
+
+        if info.in_tried {
+            return;
+        }
+        let source_group = info.source_group.clone();
+        self.new_table.remove(&self.new_slot(addr, &source_group));
+
+        // chỗ trong tried đã có người: đẩy address đó về bảng new
+        let slot = self.tried_slot(addr);
+        if let Some(old) = self.tried_table.insert(slot, *addr) {
+            let old_slot = self.new_slot(&old, &self.infos[&old].source_group);
+            match self.new_table.entry(old_slot) {
+                Entry::Occupied(_) => {
+                    self.infos.remove(&old);
+                }
+                Entry::Vacant(slot) => {
+                    slot.insert(old);
+                    self.infos.get_mut(&old).unwrap().in_tried = false;
+                }
+            }
+        }
+        self.infos.get_mut(addr).unwrap().in_tried = true;
+    }
+
+    /// Chọn 1 address để kết nối outbound, tried và new mỗi bảng 50%.
+    /// Address thuộc group trong `exclude_groups` bị bỏ qua.
+    pub fn select(&self, exclude_groups: &[Vec<u8>]) -> Option<SocketAddr> {
+        let now = now();
+        let mut rng = rand::thread_rng();
+
+        let usable = |addr: &&SocketAddr| {
+            let info = &self.infos[*addr];
+            !info.is_terrible(now) && !exclude_groups.contains(&network_group(&addr.ip()))
+        };
+
+        let use_tried = !self.tried_table.is_empty() && (self.new_table.is_empty() || rng.gen_bool(0.5));
+        let (first, second) = if use_tried {
+            (&self.tried_table, &self.new_table)
+        } else {
+            (&self.new_table, &self.tried_table)
+        };
+
+        first
+            .values()
+            .filter(usable)
+            .choose(&mut rng)
+            .or_else(|| second.values().filter(usable).choose(&mut rng))
+            .copied()
+    }
+
+    /// Trả lời GetAddr: mẫu ngẫu nhiên các address còn tốt
+    pub fn get_addr(&self) -> Vec<NetAddress> {
+        let now = now();
+        let max = (self.infos.len() * GETADDR_PERCENT / 100).clamp(1, MAX_ADDR_TO_SEND);
+
+        self.infos
+            .values()
+            .filter(|i| !i.is_terrible(now))
+            .choose_multiple(&mut rand::thread_rng(), max)
+            .into_iter()
+            .map(|i| NetAddress { addr: i.addr, services: i.services, time: i.last_seen })
+            .collect()
+    }
+
+    /* =========================
+       PERSISTENCE
+       ========================= */
+
+    /// Đọc peers.dat; file không có thì trả về bảng rỗng
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let bytes = match fs::read(path) {
+            Ok(b) => b,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::new()),
+            Err(e) => return Err(e),
+        };
+
+        let file: PeersFile = bincode::deserialize(&bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if file.version != PEERS_FILE_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported peers.dat version"));
+        }
+
+        // cùng key => cùng vị trí trong bảng như trước khi lưu
+        let mut man = Self::with_key(file.key);
+        for info in file.addrs {
+            if man.infos.contains_key(&info.addr) {
+                continue;
+            }
+            let (table, slot) = if info.in_tried {
+                let slot = man.tried_slot(&info.addr);
+                (&mut man.tried_table, slot)
+            } else {
+                let slot = man.new_slot(&info.addr, &info.source_group);
+                (&mut man.new_table, slot)
+            };
+            if table.contains_key(&slot) {
+                continue;
+            }
+            table.insert(slot, info.addr);
+            man.infos.insert(info.addr, info);
+        }
+        Ok(man)
+    }
+
+    /// Ghi peers.dat nếu có thay đổi
+    pub fn save(&mut self, path: &Path) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let file = PeersFile {
+            version: PEERS_FILE_VERSION,
+            key: self.key,
+            addrs: self.infos.values().cloned().collect(),
+        };
+
+        let tmp = path.with_extension("dat.tmp");
+        fs::write(&tmp, bincode::serialize(&file).unwrap())?;
+        fs::rename(&tmp, path)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::net::Ipv4Addr;
+
+    fn addr(a: u8, b: u8, c: u8, d: u8) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(a, b, c, d)), 8333)
+    }
+
+    fn net(addr: SocketAddr) -> NetAddress {
+        NetAddress { addr, services: 1, time: now() }
+    }
+
+    fn source() -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(9, 9, 9, 9))
+    }
+
+    #[test]
+    fn bucketing_limits_groups() {
+        let mut man = AddrMan::with_key((1, 2));
+
+        // 1 nguồn gửi nhiều group: chỉ rơi vào NEW_BUCKETS_PER_SOURCE_GROUP bucket
+        let addrs: Vec<NetAddress> = (0..=255u8).flat_map(|a| (0..8u8).map(move |b| net(addr(a, b, 1, 1)))).collect();
+        let added = man.add(&addrs, &source());
+        assert!(added > 0);
+        let new_buckets: HashSet<u64> = man.new_table.keys().map(|(bucket, _)| *bucket).collect();
+        assert!(new_buckets.len() as u64 <= NEW_BUCKETS_PER_SOURCE_GROUP);
+        for (slot, a) in &man.new_table {
+            assert_eq!(*slot, man.new_slot(a, &network_group(&source())));
+        }
+
+        // 1 group kết nối được nhiều address: chỉ rơi vào TRIED_BUCKETS_PER_GROUP bucket
+        let mut man = AddrMan::with_key((1, 2));
+        for c in 0..=255u8 {
+            let a = addr(10, 20, c, 1);
+            man.add(&[net(a)], &IpAddr::V4(Ipv4Addr::new(c, 1, 1, 1)));
+            man.good(&a, 1);
+        }
+        let tried_buckets: HashSet<u64> = man.tried_table.keys().map(|(bucket, _)| *bucket).collect();
+        assert!(tried_buckets.len() as u64 <= TRIED_BUCKETS_PER_GROUP);
+        for (slot, a) in &man.tried_table {
+            assert_eq!(*slot, man.tried_slot(a));
+            assert!(man.infos[a].in_tried);
+        }
+        assert_eq!(man.len(), man.new_table.len() + man.tried_table.len());
+    }
+
+    #[test]
+    fn good_displaces_tried_collision() {
+        let mut man = AddrMan::with_key((3, 4));
+        let src = source();
+        let src_group = network_group(&src);
+
+        // 2 address khác group cùng 1 chỗ trong tried
+        let mut by_slot = HashMap::new();
+        let (a, b) = (0..=255u8)
+            .flat_map(|x| (0..=255u8).map(move |y| addr(x, y, 7, 7)))
+            .find_map(|candidate| {
+                let slot = man.tried_slot(&candidate);
+                match by_slot.insert(slot, candidate) {
+                    Some(prev) if man.new_slot(&prev, &src_group) != man.new_slot(&candidate, &src_group) => {
+                        Some((prev, candidate))
+                    }
+                    _ => None,
+                }
+            })
+            .expect("no tried collision found");
+
+        assert_eq!(man.add(&[net(a), net(b)], &src), 2);
+        man.good(&a, 1);
+        assert!(man.infos[&a].in_tried);
+
+        // b chiếm chỗ của a, a về lại bảng new
+        man.good(&b, 1);
+        assert!(man.infos[&b].in_tried);
+        assert!(!man.infos[&a].in_tried);
+        assert_eq!(man.tried_table.get(&man.tried_slot(&b)), Some(&b));
+        assert_eq!(man.new_table.get(&man.new_slot(&a, &src_group)), Some(&a));
+        assert_eq!(man.tried_count(), 1);
+
+        // chỗ cũ của b trong new đã bị c chiếm: a lấy lại chỗ thì b bị bỏ hẳn
+        let [x, y, ..] = match b.ip() {
+            IpAddr::V4(v4) => v4.octets(),
+            IpAddr::V6(_) => unreachable!(),
+        };
+        let b_new = man.new_slot(&b, &src_group);
+        let c = (0..=255u8)
+            .flat_map(|z| (0..=255u8).map(move |w| addr(x, y, z, w)))
+            .find(|c| *c != b && man.new_slot(c, &src_group) == b_new)
+            .expect("no new collision found");
+        assert_eq!(man.add(&[net(c)], &src), 1);
+
+        man.good(&a, 1);
+        assert!(man.infos[&a].in_tried);
+        assert!(man.get(&b).is_none());
+        assert_eq!(man.new_table.get(&b_new), Some(&c));
+        assert_eq!(man.len(), man.new_table.len() + man.tried_table.len());
+    }
+
+    #[test]
+    fn peers_dat_round_trip() {
+        let dir = std::env::temp_dir().join(format!("egg-addrman-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("peers.dat");
+
+        let mut man = AddrMan::new();
+        let addrs: Vec<NetAddress> = (1..50u8).map(|i| net(addr(i, i, 1, 1))).collect();
+        man.add(&addrs, &source());
+        man.good(&addr(3, 3, 1, 1), 1);
+        man.good(&addr(4, 4, 1, 1), 1);
+        man.attempt(&addr(5, 5, 1, 1));
+        man.save(&path).unwrap();
+        assert!(!man.dirty);
+
+        let loaded = AddrMan::load(&path).unwrap();
+        assert_eq!(loaded.key, man.key);
+        assert_eq!(loaded.len(), man.len());
+        assert_eq!(loaded.tried_count(), 2);
+        assert_eq!(loaded.new_table, man.new_table);
+        assert_eq!(loaded.tried_table, man.tried_table);
+        let info = loaded.get(&addr(5, 5, 1, 1)).unwrap();
+        assert_eq!(info.attempts, 1);
+
+        // file hỏng thì báo lỗi, không âm thầm bỏ
+        fs::write(&path, b"garbage").unwrap();
+        assert!(AddrMan::load(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
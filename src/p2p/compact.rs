@@ -0,0 +1,205 @@
+//! Compact block.
+//!
+//! Block mới được gửi dưới dạng header + short id (48 bit) của từng tx;
+//! bên nhận dựng lại block từ tx đã có trong mempool / orphan pool và chỉ
+//! xin các tx còn thiếu (GetBlockTxn / BlockTxn). Short id dùng SipHash với
+//! key lấy từ header và nonce ngẫu nhiên của bên gửi, nên không ai chọn
+//! trước được tx trùng short id trên mọi kết nối.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::chain::block::{merkle_root, Block};
+use crate::chain::encode::serialize;
+use crate::chain::hash::hash_header;
+use crate::chain::header::BlockHeader;
+use crate::chain::siphash::{siphash24, siphash_key};
+use crate::chain::tx::Transaction;
+use crate::chain::txid::txid;
+
+/// Short id chỉ giữ 48 bit thấp
+pub const SHORT_ID_MASK: u64 = 0xffff_ffff_ffff;
+/// Số tx tối đa của 1 compact block, giới hạn bộ nhớ khi dựng lại
+pub const MAX_COMPACT_TXS: usize = 100_000;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PrefilledTx {
+    /// Vị trí trong block
+    pub index: u32,
+    pub tx: Transaction,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CompactBlock {
+    pub header: BlockHeader,
+    pub nonce: u64,
+    /// Short id của các tx không prefill, theo thứ tự trong block
+    pub short_ids: Vec<u64>,
+    /// Tx gửi kèm nguyên vẹn (luôn có coinbase), index tăng dần
+    pub prefilled: Vec<PrefilledTx>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CompactError {
+    TooManyTxs(usize),
+    /// Prefilled index vượt số tx hoặc không tăng dần
+    BadPrefilledIndex(u32),
+    /// 2 tx trong block trùng short id: phải tải cả block
+    DuplicateShortId,
+    /// BlockTxn không đúng số tx còn thiếu
+    BadTxCount { expected: usize, got: usize },
+    /// Tx dựng lại không khớp merkle root (short id trùng với tx khác)
+    MerkleMismatch,
+}
+
+impl fmt::Display for CompactError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompactError::TooManyTxs(n) => write!(f, "compact block with {} txs", n),
+            CompactError::BadPrefilledIndex(i) => write!(f, "bad prefilled index {}", i),
+            CompactError::DuplicateShortId => write!(f, "duplicate short id in block"),
+            CompactError::BadTxCount { expected, got } => {
+                write!(f, "expected {} missing txs, got {}", expected, got)
+            }
+            CompactError::MerkleMismatch => write!(f, "reconstructed block has wrong merkle root"),
+        }
+    }
+}
+
+impl std::error::Error for CompactError {}
+
+impl CompactError {
+    /// Message sai định dạng (khác với trùng short id do xui)
+    pub fn is_malformed(&self) -> bool {
+        matches!(self, CompactError::TooManyTxs(_) | CompactError::BadPrefilledIndex(_))
+    }
+}
+
+impl CompactBlock {
+    /// Compact block của `block`, prefill coinbase
+    pub fn from_block(block: &Block, nonce: u64) -> Self {
+        let key = short_id_key(&block.header, nonce);
+        let mut short_ids = Vec::new();
+        let mut prefilled = Vec::new();
+
+        for (i, tx) in block.transactions.iter().enumerate() {
+            if i == 0 {
+                prefilled.push(PrefilledTx { index: 0, tx: tx.clone() });
+            } else {
+                short_ids.push(short_id(key, &txid(tx)));
+            }
+        }
+
+        Self { header: block.header.clone(), nonce, short_ids, prefilled }
+    }
+
+    pub fn tx_count(&self) -> usize {
+        self.short_ids.len() + self.prefilled.len()
+    }
+}
+
+/// Key SipHash: 16 byte đầu của sha256(header || nonce)
+pub fn short_id_key(header: &BlockHeader, nonce: u64) -> (u64, u64) {
+    let mut hasher = Sha256::new();
+    hasher.update(serialize(header));
+    hasher.update(nonce.to_le_bytes());
+    let digest: [u8; 32] = hasher.finalize().into();
+    siphash_key(digest[..16].try_into().unwrap())
+}
+
+pub fn short_id(key: (u64, u64), txid: &[u8; 32]) -> u64 {
+    siphash24(key.0, key.1, txid) & SHORT_ID_MASK
+}
+
+/// Block đang dựng lại, chờ các tx còn thiếu
+pub struct PartialBlock {
+    pub hash: [u8; 32],
+    header: BlockHeader,
+    txs: Vec<Option<Transaction>>,
+}
+
+impl PartialBlock {
+    /// Điền tx từ `candidates` (mempool, orphan pool). Short id khớp nhiều
+    /// candidate thì coi như thiếu, xin lại từ peer.
+    pub fn new<'a>(
+        cmpct: &CompactBlock,
+        candidates: impl IntoIterator<Item = &'a Transaction>,
+    ) -> Result<Self, CompactError> {
+        let count = cmpct.tx_count();
+        if count > MAX_COMPACT_TXS {
+            return Err(CompactError::TooManyTxs(count));
+        }
+        let mut txs: Vec<Option<Transaction>> = vec![None; count];
+
+        let mut last: Option<u32> = None;
+        for p in &cmpct.prefilled {
+            if p.index as usize >= count || last.is_some_and(|l| p.index <= l) {
+                return Err(CompactError::BadPrefilledIndex(p.index));
+            }
+            txs[p.index as usize] = Some(p.tx.clone());
+            last = Some(p.index);
+        }
+
+        // short id -> vị trí trong block
+        let mut slots: HashMap<u64, usize> = HashMap::new();
+        let free = txs.iter().enumerate().filter(|(_, t)| t.is_none()).map(|(i, _)| i);
+        for (&id, i) in cmpct.short_ids.iter().zip(free) {
+            if slots.insert(id, i).is_some() {
+                return Err(CompactError::DuplicateShortId);
+            }
+        }
+
+        let key = short_id_key(&cmpct.header, cmpct.nonce);
+        let mut found: HashMap<usize, Option<&Transaction>> = HashMap::new();
+        for tx in candidates {
+            let id = txid(tx);
+            if let Some(&i) = slots.get(&short_id(key, &id)) {
+                match found.get(&i) {
+                    None => {
+                        found.insert(i, Some(tx));
+                    }
+                    // cùng tx (có cả ở mempool lẫn orphan pool) thì không sao
+                    Some(Some(prev)) if txid(prev) == id => {}
+                    Some(_) => {
+                        found.insert(i, None);
+                    }
+                }
+            }
+        }
+        for (i, tx) in found {
+            txs[i] = tx.cloned();
+        }
+
+        Ok(Self { hash: hash_header(&cmpct.header), header: cmpct.header.clone(), txs })
+    }
+
+    /// Vị trí các tx còn thiếu, tăng dần
+    pub fn missing(&self) -> Vec<u32> {
+        self.txs
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| t.is_none())
+            .map(|(i, _)| i as u32)
+            .collect()
+    }
+
+    /// Điền tx còn thiếu (theo thứ tự của `missing`), kiểm tra merkle root
+    pub fn fill(mut self, missing_txs: Vec<Transaction>) -> Result<Block, CompactError> {
+        let missing = self.missing();
+        if missing.len() != missing_txs.len() {
+            return Err(CompactError::BadTxCount { expected: missing.len(), got: missing_txs.len() });
+        }
+        for (i, tx) in missing.into_iter().zip(missing_txs) {
+            self.txs[i as usize] = Some(tx);
+        }
+
+        let transactions: Vec<Transaction> = self.txs.into_iter().map(|t| t.unwrap()).collect();
+        if merkle_root(&transactions) != self.header.merkle_root {
+            return Err(CompactError::MerkleMismatch);
+        }
+        Ok(Block { header: self.header, transactions })
+    }
+}
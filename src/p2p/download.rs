@@ -0,0 +1,80 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::chain::state::ChainState;
+
+/// Số block body tối đa đang chờ từ 1 peer
+pub const MAX_BLOCKS_IN_FLIGHT_PER_PEER: usize = 16;
+/// Peer không trả block trong thời gian này thì block được giao cho peer khác
+pub const BLOCK_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Block body đang được yêu cầu từ peer nào, dùng chung giữa các peer thread.
+/// Lock order: chain trước, download sau.
+#[derive(Default)]
+pub struct BlockDownload {
+    in_flight: HashMap<[u8; 32], (u64, Instant)>,
+    /// Peer có block quá hạn, chờ bị ngắt (xem p2p::keepalive)
+    stalled: HashSet<u64>,
+}
+
+impl BlockDownload {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Chọn block mới để `peer` tải, block quá hạn ở peer khác được giao lại
+    pub fn assign(&mut self, peer: u64, chain: &ChainState) -> Vec<[u8; 32]> {
+        let now = Instant::now();
+        self.expire(now);
+
+        let room = MAX_BLOCKS_IN_FLIGHT_PER_PEER.saturating_sub(self.in_flight_from(peer));
+
+        let picks = chain.blocks_to_download(room, |h| self.in_flight.contains_key(h));
+        for hash in &picks {
+            self.in_flight.insert(*hash, (peer, now));
+        }
+        picks
+    }
+
+    /// Block xin trực tiếp từ `peer` (compact block), không giao cho peer khác
+    pub fn request(&mut self, peer: u64, hash: [u8; 32]) {
+        self.in_flight.insert(hash, (peer, Instant::now()));
+    }
+
+    /// Block quá hạn được bỏ khỏi in_flight để giao lại, peer giữ nó bị ghi nhận
+    fn expire(&mut self, now: Instant) {
+        let stalled = &mut self.stalled;
+        self.in_flight.retain(|_, (peer, since)| {
+            let ok = now.duration_since(*since) < BLOCK_DOWNLOAD_TIMEOUT;
+            if !ok {
+                stalled.insert(*peer);
+            }
+            ok
+        });
+    }
+
+    /// Lấy danh sách peer làm tắc download từ lần gọi trước
+    pub fn take_stalled(&mut self, now: Instant) -> HashSet<u64> {
+        self.expire(now);
+        std::mem::take(&mut self.stalled)
+    }
+
+    /// Block đã tới; false nếu không ai yêu cầu nó
+    pub fn received(&mut self, hash: &[u8; 32]) -> bool {
+        self.in_flight.remove(hash).is_some()
+    }
+
+    /// Peer ngắt kết nối: block của nó được tải lại từ peer khác
+    pub fn peer_disconnected(&mut self, peer: u64) {
+        self.in_flight.retain(|_, (p, _)| *p != peer);
+        self.stalled.remove(&peer);
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    pub fn in_flight_from(&self, peer: u64) -> usize {
+        self.in_flight.values().filter(|(p, _)| *p == peer).count()
+    }
+}
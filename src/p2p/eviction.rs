@@ -0,0 +1,127 @@
+//! Giới hạn kết nối inbound và chọn peer để nhường chỗ.
+//!
+//! Inbound bị giới hạn tổng số (`PeerContext::max_inbound`), số kết nối
+//! mỗi IP và mỗi network group. Khi hết slot, kết nối mới chỉ được nhận
+//! nếu có peer inbound bị đuổi: trước hết giữ lại các peer khó giả mạo
+//! (network group đa dạng, ping thấp, vừa gửi block / tx mới, kết nối
+//! lâu), rồi đuổi peer mới nhất trong network group đông nhất. Kẻ tấn
+//! công mở nhiều kết nối từ ít subnet vì vậy chỉ đuổi được chính nó.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::chain::siphash::siphash24;
+use crate::p2p::addrman::network_group;
+use crate::p2p::peer::Direction;
+use crate::p2p::relay::{PeerHandle, PeerRegistry};
+
+/// Số kết nối inbound mặc định
+pub const DEFAULT_MAX_INBOUND: usize = 117;
+/// Số kết nối inbound tối đa từ 1 IP
+pub const MAX_INBOUND_PER_IP: usize = 3;
+/// Số kết nối inbound tối đa từ 1 network group
+pub const MAX_INBOUND_PER_GROUP: usize = 10;
+
+/// Số peer được giữ theo từng tiêu chí
+const PROTECT_BY_GROUP: usize = 4;
+const PROTECT_BY_PING: usize = 8;
+const PROTECT_BY_TX: usize = 4;
+const PROTECT_BY_BLOCK: usize = 4;
+
+/// Thông tin 1 peer inbound để chọn peer bị đuổi
+pub struct EvictionCandidate {
+    pub id: u64,
+    pub group: Vec<u8>,
+    /// Hash của group với key bí mật: peer không chọn trước được thứ tự
+    pub group_key: u64,
+    pub connected: Instant,
+    pub min_ping: Option<Duration>,
+    /// Lần cuối peer gửi block / tx mới
+    pub last_block: Option<Instant>,
+    pub last_tx: Option<Instant>,
+}
+
+/// Giữ lại `n` peer có `key` lớn nhất
+fn protect<K: Ord>(candidates: &mut Vec<EvictionCandidate>, n: usize, key: impl Fn(&EvictionCandidate) -> K) {
+    candidates.sort_by_key(|c| std::cmp::Reverse(key(c)));
+    let n = n.min(candidates.len());
+    candidates.drain(..n);
+}
+
+/// Id peer bị đuổi; None nếu mọi peer đều được giữ
+pub fn select_to_evict(mut candidates: Vec<EvictionCandidate>) -> Option<u64> {
+    protect(&mut candidates, PROTECT_BY_GROUP, |c| c.group_key);
+    // None (chưa đo được) xếp sau mọi ping
+    protect(&mut candidates, PROTECT_BY_PING, |c| std::cmp::Reverse(c.min_ping.unwrap_or(Duration::MAX)));
+    protect(&mut candidates, PROTECT_BY_TX, |c| c.last_tx);
+    protect(&mut candidates, PROTECT_BY_BLOCK, |c| c.last_block);
+    // nửa còn lại kết nối lâu nhất
+    let half = candidates.len() / 2;
+    protect(&mut candidates, half, |c| std::cmp::Reverse(c.connected));
+
+    // group đông nhất; bằng nhau thì group có kết nối mới nhất
+    let mut groups: HashMap<&[u8], Vec<&EvictionCandidate>> = HashMap::new();
+    for c in &candidates {
+        groups.entry(&c.group).or_default().push(c);
+    }
+    let newest = |g: &Vec<&EvictionCandidate>| g.iter().map(|c| c.connected).max();
+    let group = groups.values().max_by(|a, b| a.len().cmp(&b.len()).then(newest(a).cmp(&newest(b))))?;
+
+    group.iter().max_by_key(|c| c.connected).map(|c| c.id)
+}
+
+impl PeerRegistry {
+    /// Peer inbound nên đuổi để nhận kết nối mới
+    pub fn select_eviction(&self) -> Option<Arc<PeerHandle>> {
+        let peers = self.list();
+        let candidates = peers
+            .iter()
+            .filter(|p| p.direction == Direction::Inbound && !p.whitelisted && !p.is_closing())
+            .map(|p| {
+                let stats = p.stats.lock().unwrap();
+                let group = network_group(&p.addr.ip());
+                EvictionCandidate {
+                    id: p.id,
+                    group_key: siphash24(self.group_key.0, self.group_key.1, &group),
+                    group,
+                    connected: stats.connected,
+                    min_ping: stats.min_ping,
+                    last_block: stats.last_block,
+                    last_tx: stats.last_tx,
+                }
+            })
+            .collect();
+
+        let id = select_to_evict(candidates)?;
+        peers.into_iter().find(|p| p.id == id)
+    }
+}
+
+/// Đếm kết nối inbound theo IP và network group
+#[derive(Default)]
+pub struct InboundCounts {
+    pub total: usize,
+    by_ip: HashMap<IpAddr, usize>,
+    by_group: HashMap<Vec<u8>, usize>,
+}
+
+impl InboundCounts {
+    pub fn add(&mut self, ip: IpAddr) {
+        self.total += 1;
+        *self.by_ip.entry(ip).or_default() += 1;
+        *self.by_group.entry(network_group(&ip)).or_default() += 1;
+    }
+
+    /// Lý do từ chối kết nối mới từ `ip` (không tính giới hạn tổng)
+    pub fn check(&self, ip: &IpAddr) -> Option<&'static str> {
+        if self.by_ip.get(ip).copied().unwrap_or(0) >= MAX_INBOUND_PER_IP {
+            return Some("too many connections from address");
+        }
+        if self.by_group.get(&network_group(ip)).copied().unwrap_or(0) >= MAX_INBOUND_PER_GROUP {
+            return Some("too many connections from network group");
+        }
+        None
+    }
+}
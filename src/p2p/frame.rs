@@ -0,0 +1,197 @@
+//! Đóng gói Message thành frame trên TCP.
+//!
+//! ```text
+//! magic    4   theo network (ChainParams::magic)
+//! command 12   tên message ASCII, đệm 0
+//! length   4   u32 LE, độ dài payload, tối đa MAX_PAYLOAD_SIZE
+//! checksum 4   4 byte đầu sha256d(payload)
+//! payload      bincode(Message)
+//! ```
+//!
+//! Biết trước độ dài nên frame hỏng (checksum / payload sai) được bỏ qua mà
+//! stream vẫn đồng bộ; sai magic hoặc length quá lớn thì không đọc tiếp được.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use bincode::Options;
+use sha2::{Sha256, Digest};
+
+use crate::p2p::message::Message;
+
+pub const HEADER_SIZE: usize = 24;
+pub const COMMAND_SIZE: usize = 12;
+/// Payload lớn nhất được chấp nhận (đủ cho block lớn nhất)
+pub const MAX_PAYLOAD_SIZE: u32 = 32 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum FrameError {
+    Io(io::Error),
+    BadMagic([u8; 4]),
+    Oversized(u32),
+    BadChecksum,
+    /// Command trong header không khớp message decode được
+    CommandMismatch(String),
+    Decode(String),
+}
+
+impl FrameError {
+    /// Lỗi làm lệch stream: phải ngắt kết nối
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            FrameError::Io(_) | FrameError::BadMagic(_) | FrameError::Oversized(_)
+        )
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Io(e) => write!(f, "io error: {}", e),
+            FrameError::BadMagic(m) => write!(f, "bad magic {}", hex::encode(m)),
+            FrameError::Oversized(n) => write!(f, "payload of {} bytes too large", n),
+            FrameError::BadChecksum => write!(f, "checksum mismatch"),
+            FrameError::CommandMismatch(c) => write!(f, "command {:?} does not match payload", c),
+            FrameError::Decode(e) => write!(f, "invalid payload: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<io::Error> for FrameError {
+    fn from(e: io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
+
+fn checksum(payload: &[u8]) -> [u8; 4] {
+    let hash = Sha256::digest(Sha256::digest(payload));
+    hash[..4].try_into().unwrap()
+}
+
+fn command_bytes(command: &str) -> [u8; COMMAND_SIZE] {
+    let mut out = [0u8; COMMAND_SIZE];
+    out[..command.len()].copy_from_slice(command.as_bytes());
+    out
+}
+
+struct FrameHeader {
+    command: [u8; COMMAND_SIZE],
+    length: u32,
+    checksum: [u8; 4],
+}
+
+fn parse_header(magic: &[u8; 4], bytes: &[u8; HEADER_SIZE]) -> Result<FrameHeader, FrameError> {
+    let got: [u8; 4] = bytes[..4].try_into().unwrap();
+    if &got != magic {
+        return Err(FrameError::BadMagic(got));
+    }
+
+    let length = u32::from_le_bytes(bytes[16..20].try_into().unwrap());
+    if length > MAX_PAYLOAD_SIZE {
+        return Err(FrameError::Oversized(length));
+    }
+
+    Ok(FrameHeader {
+        command: bytes[4..16].try_into().unwrap(),
+        length,
+        checksum: bytes[20..24].try_into().unwrap(),
+    })
+}
+
+fn decode_payload(header: &FrameHeader, payload: &[u8]) -> Result<Message, FrameError> {
+    if checksum(payload) != header.checksum {
+        return Err(FrameError::BadChecksum);
+    }
+
+    // cùng format với bincode::serialize nhưng không cho byte thừa
+    let msg: Message = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_limit(MAX_PAYLOAD_SIZE as u64)
+        .deserialize(payload)
+        .map_err(|e| FrameError::Decode(e.to_string()))?;
+
+    if command_bytes(msg.command()) != header.command {
+        let name = header.command.iter().take_while(|b| **b != 0).map(|b| *b as char).collect();
+        return Err(FrameError::CommandMismatch(name));
+    }
+    Ok(msg)
+}
+
+/* =========================
+   ENCODE
+   ========================= */
+
+pub fn encode_frame(magic: &[u8; 4], msg: &Message) -> Vec<u8> {
+    let payload = bincode::serialize(msg).unwrap();
+
+    let mut out = Vec::with_capacity(HEADER_SIZE + payload.len());
+    out.extend_from_slice(magic);
+    out.extend_from_slice(&command_bytes(msg.command()));
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(&checksum(&payload));
+    out.extend_from_slice(&payload);
+    out
+}
+
+pub fn write_message<W: Write>(w: &mut W, magic: &[u8; 4], msg: &Message) -> io::Result<()> {
+    w.write_all(&encode_frame(magic, msg))
+}
+
+/* =========================
+   DECODE
+   ========================= */
+
+/// Đọc đủ 1 frame từ stream blocking (chờ qua nhiều TCP segment nếu cần)
+pub fn read_message<R: Read>(r: &mut R, magic: &[u8; 4]) -> Result<Message, FrameError> {
+    let mut head = [0u8; HEADER_SIZE];
+    r.read_exact(&mut head)?;
+    let header = parse_header(magic, &head)?;
+
+    let mut payload = vec![0u8; header.length as usize];
+    r.read_exact(&mut payload)?;
+    decode_payload(&header, &payload)
+}
+
+/// Tách frame từ byte nhận dần (socket non-blocking): đẩy byte vào bằng
+/// `push`, lấy message ra bằng `next_message` tới khi trả về None
+pub struct FrameDecoder {
+    magic: [u8; 4],
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new(magic: [u8; 4]) -> Self {
+        Self { magic, buf: Vec::new() }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// None = chưa đủ byte cho frame kế tiếp. Lỗi không fatal thì frame lỗi
+    /// đã được bỏ, gọi tiếp được.
+    pub fn next_message(&mut self) -> Result<Option<Message>, FrameError> {
+        if self.buf.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+        let head: [u8; HEADER_SIZE] = self.buf[..HEADER_SIZE].try_into().unwrap();
+        let header = parse_header(&self.magic, &head)?;
+
+        let end = HEADER_SIZE + header.length as usize;
+        if self.buf.len() < end {
+            return Ok(None);
+        }
+
+        let result = decode_payload(&header, &self.buf[HEADER_SIZE..end]);
+        self.buf.drain(..end);
+        result.map(Some)
+    }
+
+    /// Số byte đang chờ đủ frame
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+}
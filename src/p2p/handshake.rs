@@ -0,0 +1,269 @@
+//! Version handshake.
+//!
+//! Hai bên cùng gửi `Handshake` (version) ngay khi kết nối, nhận version
+//! của bên kia thì trả `Verack`. Kết nối chỉ dùng được khi đã nhận cả
+//! version lẫn verack; mọi message khác trước đó đều bị coi là vi phạm.
+//!
+//! Từ IDENTITY_VERSION, cùng verack mỗi bên gửi `Challenge` (nonce ngẫu
+//! nhiên); bên kia trả `Identity` ký nonce đó bằng identity key (gắn với
+//! session id của transport v2 nếu có). Handshake chỉ xong khi chữ ký đúng.
+
+use std::fmt;
+use std::io;
+use std::time::{Duration, Instant};
+
+use secp256k1::ecdsa::Signature;
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
+use sha2::{Digest, Sha256};
+
+use crate::p2p::frame::FrameError;
+use crate::p2p::message::Message;
+use crate::p2p::transport::TransportError;
+
+/// Protocol version của node này
+pub const PROTOCOL_VERSION: u32 = 4;
+/// Từ version này peer nhận được compact block (xem p2p::compact)
+pub const COMPACT_BLOCKS_VERSION: u32 = 3;
+/// Từ version này 2 bên trao đổi challenge / identity sau verack
+pub const IDENTITY_VERSION: u32 = 4;
+/// Peer cũ hơn version này bị từ chối
+pub const MIN_PEER_PROTOCOL_VERSION: u32 = 1;
+/// Peer phải hoàn tất handshake trong thời gian này
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub enum HandshakeError {
+    Frame(FrameError),
+    /// Lỗi của transport v2 (xem p2p::transport)
+    Transport(TransportError),
+    Timeout,
+    WrongGenesis([u8; 32]),
+    ObsoleteVersion(u32),
+    /// Kết nối tới chính mình (cùng node_id)
+    SelfConnection,
+    /// Gửi version 2 lần
+    DuplicateVersion,
+    /// Message khác version / verack trước khi handshake xong
+    Unexpected(&'static str),
+    /// Pubkey hoặc chữ ký identity không hợp lệ
+    BadIdentity,
+}
+
+impl HandshakeError {
+    /// Lỗi do peer vi phạm protocol (không phải do khác network / mạng chậm)
+    pub fn is_misbehavior(&self) -> bool {
+        matches!(
+            self,
+            HandshakeError::DuplicateVersion | HandshakeError::Unexpected(_) | HandshakeError::BadIdentity
+        )
+    }
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::Frame(e) => write!(f, "{}", e),
+            HandshakeError::Transport(e) => write!(f, "{}", e),
+            HandshakeError::Timeout => write!(f, "handshake timed out"),
+            HandshakeError::WrongGenesis(h) => write!(f, "peer on genesis {}", hex::encode(h)),
+            HandshakeError::ObsoleteVersion(v) => write!(f, "obsolete protocol version {}", v),
+            HandshakeError::SelfConnection => write!(f, "connected to self"),
+            HandshakeError::DuplicateVersion => write!(f, "duplicate version message"),
+            HandshakeError::Unexpected(c) => write!(f, "unexpected {} before handshake", c),
+            HandshakeError::BadIdentity => write!(f, "invalid node identity signature"),
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+impl From<FrameError> for HandshakeError {
+    fn from(e: FrameError) -> Self {
+        match e {
+            FrameError::Io(ref io)
+                if matches!(io.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) =>
+            {
+                HandshakeError::Timeout
+            }
+            e => HandshakeError::Frame(e),
+        }
+    }
+}
+
+/// Thông tin node này gửi trong version
+#[derive(Clone, Debug)]
+pub struct LocalVersion {
+    pub genesis_hash: [u8; 32],
+    pub node_id: [u8; 32],
+    pub services: u64,
+    pub best_height: u64,
+    /// Key ký challenge peer gửi (xem node::load_node_identity); None = ẩn danh
+    pub identity: Option<SecretKey>,
+}
+
+/// Thông tin peer sau handshake
+#[derive(Clone, Debug)]
+pub struct PeerVersion {
+    /// min(version của ta, version của peer)
+    pub protocol_version: u32,
+    pub node_id: [u8; 32],
+    pub services: u64,
+    /// Height peer báo lúc kết nối
+    pub best_height: u64,
+    /// Identity pubkey peer đã chứng minh; None với peer cũ / ẩn danh
+    pub identity: Option<PublicKey>,
+    /// Session id của transport v2, None khi kết nối v1
+    pub session_id: Option<[u8; 32]>,
+}
+
+pub struct HandshakeState {
+    local: LocalVersion,
+    peer: Option<PeerVersion>,
+    verack_received: bool,
+    /// Nonce ta gửi trong Challenge
+    challenge: [u8; 32],
+    /// Đã trả Challenge của peer
+    challenge_answered: bool,
+    /// Đã nhận Identity (hoặc peer cũ không có bước này)
+    identity_done: bool,
+    session_id: Option<[u8; 32]>,
+    started: Instant,
+}
+
+impl HandshakeState {
+    pub fn new(local: LocalVersion) -> Self {
+        Self {
+            local,
+            peer: None,
+            verack_received: false,
+            challenge: rand::random(),
+            challenge_answered: false,
+            identity_done: false,
+            session_id: None,
+            started: Instant::now(),
+        }
+    }
+
+    /// Gắn chữ ký identity với session transport v2; gọi trước khi nhận Challenge
+    pub fn bind_session(&mut self, session_id: Option<[u8; 32]>) {
+        self.session_id = session_id;
+    }
+
+    /// Message version gửi ngay khi kết nối
+    pub fn version_message(&self) -> Message {
+        Message::Handshake {
+            protocol_version: PROTOCOL_VERSION,
+            genesis_hash: self.local.genesis_hash,
+            node_id: self.local.node_id,
+            services: self.local.services,
+            best_height: self.local.best_height,
+        }
+    }
+
+    fn identity_negotiated(&self) -> bool {
+        self.peer.as_ref().is_some_and(|p| p.protocol_version >= IDENTITY_VERSION)
+    }
+
+    /// Xử lý 1 message nhận được trong lúc handshake; trả các message cần gửi lại
+    pub fn on_message(&mut self, msg: Message) -> Result<Vec<Message>, HandshakeError> {
+        match msg {
+            Message::Handshake { protocol_version, genesis_hash, node_id, services, best_height } => {
+                if self.peer.is_some() {
+                    return Err(HandshakeError::DuplicateVersion);
+                }
+                if genesis_hash != self.local.genesis_hash {
+                    return Err(HandshakeError::WrongGenesis(genesis_hash));
+                }
+                if node_id == self.local.node_id {
+                    return Err(HandshakeError::SelfConnection);
+                }
+                if protocol_version < MIN_PEER_PROTOCOL_VERSION {
+                    return Err(HandshakeError::ObsoleteVersion(protocol_version));
+                }
+
+                self.peer = Some(PeerVersion {
+                    protocol_version: protocol_version.min(PROTOCOL_VERSION),
+                    node_id,
+                    services,
+                    best_height,
+                    identity: None,
+                    session_id: self.session_id,
+                });
+                if !self.identity_negotiated() {
+                    self.identity_done = true;
+                    return Ok(vec![Message::Verack]);
+                }
+                Ok(vec![Message::Verack, Message::Challenge { nonce: self.challenge }])
+            }
+
+            // verack trước version: peer không theo thứ tự
+            Message::Verack if self.peer.is_some() && !self.verack_received => {
+                self.verack_received = true;
+                Ok(vec![])
+            }
+
+            Message::Challenge { nonce } if self.identity_negotiated() && !self.challenge_answered => {
+                self.challenge_answered = true;
+                let reply = match &self.local.identity {
+                    Some(sk) => {
+                        let digest = identity_digest(&self.local.genesis_hash, &nonce, &self.session_id);
+                        let msg = secp256k1::Message::from_digest_slice(&digest).unwrap();
+                        let secp = Secp256k1::new();
+                        Message::Identity {
+                            pubkey: PublicKey::from_secret_key(&secp, sk).serialize().to_vec(),
+                            signature: secp.sign_ecdsa(&msg, sk).serialize_der().to_vec(),
+                        }
+                    }
+                    None => Message::Identity { pubkey: vec![], signature: vec![] },
+                };
+                Ok(vec![reply])
+            }
+
+            Message::Identity { pubkey, signature } if self.identity_negotiated() && !self.identity_done => {
+                self.identity_done = true;
+                if pubkey.is_empty() {
+                    return Ok(vec![]);
+                }
+                let pk = PublicKey::from_slice(&pubkey).map_err(|_| HandshakeError::BadIdentity)?;
+                let sig = Signature::from_der(&signature).map_err(|_| HandshakeError::BadIdentity)?;
+                let digest = identity_digest(&self.local.genesis_hash, &self.challenge, &self.session_id);
+                let msg = secp256k1::Message::from_digest_slice(&digest).unwrap();
+                Secp256k1::verification_only()
+                    .verify_ecdsa(&msg, &sig, &pk)
+                    .map_err(|_| HandshakeError::BadIdentity)?;
+                self.peer.as_mut().unwrap().identity = Some(pk);
+                Ok(vec![])
+            }
+
+            other => Err(HandshakeError::Unexpected(other.command())),
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.peer.is_some() && self.verack_received && self.identity_done
+    }
+
+    pub fn timed_out(&self, now: Instant) -> bool {
+        now.duration_since(self.started) >= HANDSHAKE_TIMEOUT
+    }
+
+    /// Như `into_peer` nhưng không lấy state
+    pub fn peer(&self) -> Option<&PeerVersion> {
+        if self.is_complete() { self.peer.as_ref() } else { None }
+    }
+
+    /// Thông tin peer, chỉ có sau khi handshake xong
+    pub fn into_peer(self) -> Option<PeerVersion> {
+        if self.is_complete() { self.peer } else { None }
+    }
+}
+
+/// Digest identity key ký: nonce của bên hỏi, gắn với genesis và session v2
+fn identity_digest(genesis: &[u8; 32], nonce: &[u8; 32], session_id: &Option<[u8; 32]>) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"egg node identity");
+    hasher.update(genesis);
+    hasher.update(nonce);
+    hasher.update(session_id.unwrap_or([0; 32]));
+    hasher.finalize().into()
+}
@@ -0,0 +1,126 @@
+//! Ping / pong và phát hiện peer chết.
+//!
+//! Mỗi PING_INTERVAL gửi Ping với nonce ngẫu nhiên cho từng peer, Pong
+//! đúng nonce cho ra round-trip time. Peer không trả Pong, không gửi gì
+//! trong TIMEOUT_INTERVAL, hoặc giữ block download quá BLOCK_DOWNLOAD_TIMEOUT
+//! thì bị ngắt (event loop đóng socket).
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::p2p::download::BlockDownload;
+use crate::p2p::message::Message;
+use crate::p2p::relay::{PeerHandle, PeerRegistry};
+
+/// Khoảng giữa 2 lần ping 1 peer
+pub const PING_INTERVAL: Duration = Duration::from_secs(2 * 60);
+/// Không nhận Pong / message nào trong khoảng này thì ngắt
+pub const TIMEOUT_INTERVAL: Duration = Duration::from_secs(20 * 60);
+const TICK: Duration = Duration::from_secs(1);
+
+/// Thống kê của 1 kết nối, dùng chung giữa event loop, worker và watchdog
+pub struct PeerStats {
+    pub connected: Instant,
+    pub last_send: Option<Instant>,
+    pub last_recv: Option<Instant>,
+    /// Ping đang chờ Pong: (nonce, lúc gửi)
+    pub ping_pending: Option<(u64, Instant)>,
+    pub last_ping: Option<Instant>,
+    pub ping_time: Option<Duration>,
+    pub min_ping: Option<Duration>,
+    /// Lần cuối peer gửi block / tx mới cho ta (xem p2p::eviction)
+    pub last_block: Option<Instant>,
+    pub last_tx: Option<Instant>,
+}
+
+impl PeerStats {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            connected: now,
+            last_send: None,
+            last_recv: None,
+            ping_pending: None,
+            last_ping: None,
+            ping_time: None,
+            min_ping: None,
+            last_block: None,
+            last_tx: None,
+        }
+    }
+
+    /// Thời gian chờ Pong hiện tại
+    pub fn ping_wait(&self, now: Instant) -> Option<Duration> {
+        self.ping_pending.map(|(_, sent)| now.duration_since(sent))
+    }
+}
+
+impl PeerHandle {
+    /// Ghi nhận Pong; nonce không khớp ping đang chờ thì bỏ qua
+    pub fn on_pong(&self, nonce: u64, now: Instant) {
+        let mut stats = self.stats.lock().unwrap();
+        match stats.ping_pending {
+            Some((expected, sent)) if expected == nonce => {
+                let rtt = now.duration_since(sent);
+                stats.ping_pending = None;
+                stats.ping_time = Some(rtt);
+                stats.min_ping = Some(stats.min_ping.map_or(rtt, |m| m.min(rtt)));
+            }
+            Some(_) => log::debug!("pong from {} with wrong nonce", self.addr),
+            None => log::debug!("unsolicited pong from {}", self.addr),
+        }
+    }
+
+    /// Lý do cần ngắt peer (không trả lời), hoặc gửi Ping nếu đến hạn
+    fn keepalive(&self, now: Instant) -> Option<&'static str> {
+        let nonce = {
+            let mut stats = self.stats.lock().unwrap();
+            if stats.ping_wait(now).is_some_and(|w| w >= TIMEOUT_INTERVAL) {
+                return Some("ping timeout");
+            }
+            let idle_since = stats.last_recv.unwrap_or(stats.connected);
+            if now.duration_since(idle_since) >= TIMEOUT_INTERVAL {
+                return Some("receive timeout");
+            }
+            if stats.ping_pending.is_some()
+                || stats.last_ping.is_some_and(|t| now.duration_since(t) < PING_INTERVAL)
+            {
+                return None;
+            }
+            // nonce 0 không dùng, để peer cũ phân biệt được
+            let nonce = rand::random::<u64>().max(1);
+            stats.ping_pending = Some((nonce, now));
+            stats.last_ping = Some(now);
+            nonce
+        };
+        self.try_send(Message::Ping { nonce });
+        None
+    }
+}
+
+impl PeerRegistry {
+    /// Ping đến hạn, ngắt peer không trả lời hoặc làm tắc block download
+    pub fn check_peers(&self, now: Instant, download: &Mutex<BlockDownload>) {
+        let stalling = download.lock().unwrap().take_stalled(now);
+
+        for peer in self.list() {
+            let reason = if stalling.contains(&peer.id) {
+                Some("stalled block download")
+            } else {
+                peer.keepalive(now)
+            };
+            if let Some(reason) = reason {
+                log::info!("disconnecting peer {}: {}", peer.addr, reason);
+                peer.disconnect();
+            }
+        }
+    }
+}
+
+/// Thread kiểm tra định kỳ mọi peer
+pub fn start_keepalive(peers: Arc<PeerRegistry>, download: Arc<Mutex<BlockDownload>>) {
+    thread::spawn(move || loop {
+        thread::sleep(TICK);
+        peers.check_peers(Instant::now(), &download);
+    });
+}
@@ -3,41 +3,169 @@ use serde::{Serialize, Deserialize};
 use crate::chain::header::BlockHeader;
 use crate::chain::block::Block;
 use crate::chain::tx::Transaction;
+use crate::p2p::addrman::NetAddress;
+use crate::p2p::compact::CompactBlock;
+
+/// Phục vụ được toàn bộ lịch sử block
+pub const NODE_NETWORK: u64 = 1 << 0;
+/// Chỉ phục vụ được MIN_BLOCKS_TO_KEEP block gần tip (node prune)
+pub const NODE_NETWORK_LIMITED: u64 = 1 << 10;
+/// Phục vụ compact block filter (GetCFilters / GetCFHeaders)
+pub const NODE_COMPACT_FILTERS: u64 = 1 << 6;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum InvKind {
+    Tx,
+    Block,
+}
+
+/// 1 mục inventory: tx (theo txid) hoặc block (theo hash header)
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct InvItem {
+    pub kind: InvKind,
+    pub hash: [u8; 32],
+}
 
 #[derive(Serialize, Deserialize)]
 pub enum Message {
-    // ---- handshake ----
+    // ---- handshake (xem p2p::handshake) ----
     Handshake {
         protocol_version: u32,
         genesis_hash: [u8; 32],
         node_id: [u8; 32],
+        services: u64,
+        /// Height tip của bên gửi
+        best_height: u64,
+    },
+    /// Đã nhận và chấp nhận Handshake của bên kia
+    Verack,
+
+    // ---- keepalive (xem p2p::keepalive) ----
+    Ping {
+        nonce: u64,
+    },
+    /// Trả lời Ping, cùng nonce
+    Pong {
+        nonce: u64,
     },
 
     // ---- headers-first sync ----
+    /// Xin header sau block chung đầu tiên trong locator, tới `stop` (0 = tối đa)
     GetHeaders {
-        from: [u8; 32],
-        limit: u32,
+        locator: Vec<[u8; 32]>,
+        stop: [u8; 32],
     },
     Headers {
         headers: Vec<BlockHeader>,
     },
 
-    // ---- compact block ----
+    // ---- compact block (xem p2p::compact) ----
     CompactBlock {
-        header: BlockHeader,
-        txids: Vec<[u8; 32]>,
+        block: CompactBlock,
+    },
+    /// Xin các tx còn thiếu khi dựng lại compact block
+    GetBlockTxn {
+        block_hash: [u8; 32],
+        /// Vị trí tx trong block, tăng dần
+        indexes: Vec<u32>,
+    },
+    BlockTxn {
+        block_hash: [u8; 32],
+        txs: Vec<Transaction>,
     },
 
-    // ---- full block fallback ----
-    GetBlock {
-        hash: [u8; 32],
+    // ---- inventory relay (xem p2p::relay) ----
+    /// Báo có tx / block mới
+    Inv {
+        items: Vec<InvItem>,
     },
+    /// Xin tx / block theo inventory
+    GetData {
+        items: Vec<InvItem>,
+    },
+    /// Các mục trong GetData mà bên gửi không có
+    NotFound {
+        items: Vec<InvItem>,
+    },
+
+    // ---- full block ----
     Block {
         block: Block,
     },
 
+    // ---- compact block filters ----
+    GetCFilters {
+        filter_type: u8,
+        start_height: u64,
+        stop_hash: [u8; 32],
+    },
+    CFilters {
+        filter_type: u8,
+        /// (block hash, filter) theo thứ tự height
+        filters: Vec<([u8; 32], Vec<u8>)>,
+    },
+    GetCFHeaders {
+        filter_type: u8,
+        start_height: u64,
+        stop_hash: [u8; 32],
+    },
+    CFHeaders {
+        filter_type: u8,
+        stop_hash: [u8; 32],
+        /// Filter header của block ngay trước start_height
+        prev_header: [u8; 32],
+        filter_hashes: Vec<[u8; 32]>,
+    },
+
+    // ---- address gossip (xem p2p::addrman) ----
+    GetAddr,
+    Addr {
+        addrs: Vec<NetAddress>,
+    },
+
     // ---- transaction broadcast ----
     Tx {
         tx: Transaction,
     },
+
+    // ---- node identity (xem p2p::handshake) ----
+    /// Nonce ngẫu nhiên, peer phải ký bằng identity key
+    Challenge {
+        nonce: [u8; 32],
+    },
+    /// Chữ ký (DER) của challenge; pubkey rỗng = node không có identity
+    Identity {
+        pubkey: Vec<u8>,
+        signature: Vec<u8>,
+    },
+}
+
+impl Message {
+    /// Tên lệnh ghi trong frame header (xem p2p::frame)
+    pub fn command(&self) -> &'static str {
+        match self {
+            Message::Handshake { .. } => "version",
+            Message::Verack => "verack",
+            Message::Ping { .. } => "ping",
+            Message::Pong { .. } => "pong",
+            Message::GetHeaders { .. } => "getheaders",
+            Message::Headers { .. } => "headers",
+            Message::CompactBlock { .. } => "cmpctblock",
+            Message::GetBlockTxn { .. } => "getblocktxn",
+            Message::BlockTxn { .. } => "blocktxn",
+            Message::Inv { .. } => "inv",
+            Message::GetData { .. } => "getdata",
+            Message::NotFound { .. } => "notfound",
+            Message::Block { .. } => "block",
+            Message::GetCFilters { .. } => "getcfilters",
+            Message::CFilters { .. } => "cfilters",
+            Message::GetCFHeaders { .. } => "getcfheaders",
+            Message::CFHeaders { .. } => "cfheaders",
+            Message::GetAddr => "getaddr",
+            Message::Addr { .. } => "addr",
+            Message::Tx { .. } => "tx",
+            Message::Challenge { .. } => "challenge",
+            Message::Identity { .. } => "identity",
+        }
+    }
 }
@@ -1,4 +1,16 @@
 pub mod peer;
 pub mod message;
+pub mod frame;
+pub mod handshake;
+pub mod network;
+pub mod addrman;
+pub mod download;
+pub mod relay;
+pub mod compact;
+pub mod keepalive;
+pub mod reactor;
+pub mod eviction;
 
 
+pub mod sim;
+pub mod transport;
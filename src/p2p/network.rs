@@ -1,14 +1,228 @@
-use std::net::TcpStream;
+//! Outbound connection manager.
+//!
+//! Giữ tối đa `target` kết nối outbound: trước hết tới các address trong
+//! `NodeConfig::peers`, chỗ còn lại lấy từ address manager (mỗi network
+//! group tối đa 1 kết nối). Address tĩnh kết nối hỏng hoặc handshake thất
+//! bại được thử lại với backoff tăng gấp đôi; kết nối bị ngắt bình thường
+//! thì thử lại sau INITIAL_BACKOFF. Address từ address manager tự bị bỏ
+//! khi thử mãi không được (xem AddrInfo::is_terrible).
+
+use std::collections::HashSet;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::p2p::addrman::network_group;
+use crate::p2p::handshake::HandshakeError;
+use crate::p2p::peer::{handle_peer, Direction, PeerContext};
+
+/// Số kết nối outbound mặc định
+pub const DEFAULT_MAX_OUTBOUND: usize = 8;
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
+const TICK: Duration = Duration::from_millis(500);
+
+struct OutboundPeer {
+    addr: String,
+    failures: u32,
+    next_attempt: Instant,
+    /// Đang kết nối hoặc đang chạy peer loop
+    active: bool,
+    /// Address trỏ về chính node này: không thử lại
+    is_self: bool,
+}
+
+impl OutboundPeer {
+    fn schedule_retry(&mut self, failed: bool) {
+        let delay = if failed {
+            self.failures += 1;
+            INITIAL_BACKOFF
+                .saturating_mul(1 << self.failures.min(16))
+                .min(MAX_BACKOFF)
+        } else {
+            self.failures = 0;
+            INITIAL_BACKOFF
+        };
+        self.next_attempt = Instant::now() + delay;
+        self.active = false;
+    }
+}
+
+#[derive(Clone)]
+pub struct OutboundManager {
+    /// Address cấu hình tĩnh
+    peers: Arc<Mutex<Vec<OutboundPeer>>>,
+    /// Kết nối đang mở tới address lấy từ address manager
+    dynamic: Arc<Mutex<HashSet<SocketAddr>>>,
+    target: usize,
+}
 
-use crate::chain::state::ChainState;
-use crate::p2p::peer::{handle_peer, perform_handshake};
+impl OutboundManager {
+    pub fn new(addrs: &[String], target: usize) -> Self {
+        let now = Instant::now();
+        let mut seen = HashSet::new();
+        let peers = addrs
+            .iter()
+            .filter(|a| seen.insert(a.as_str()))
+            .map(|a| OutboundPeer {
+                addr: a.clone(),
+                failures: 0,
+                next_attempt: now,
+                active: false,
+                is_self: false,
+            })
+            .collect();
 
-pub fn connect_to_peer(addr: &str, chain: Arc<Mutex<ChainState>>) {
-    if let Ok(stream) = TcpStream::connect(addr) {
-        if perform_handshake(&stream).is_ok() {
-            let mut chain = chain.lock().unwrap();
-            handle_peer(stream, &mut chain);
+        Self {
+            peers: Arc::new(Mutex::new(peers)),
+            dynamic: Arc::new(Mutex::new(HashSet::new())),
+            target,
         }
     }
+
+    /// Số kết nối outbound đang mở (kể cả đang connect / handshake)
+    pub fn active_count(&self) -> usize {
+        let fixed = self.peers.lock().unwrap().iter().filter(|p| p.active).count();
+        fixed + self.dynamic.lock().unwrap().len()
+    }
+
+    /// Thread chạy mãi: mở kết nối mới mỗi khi thiếu so với target
+    pub fn start(self, ctx: PeerContext) {
+        thread::spawn(move || loop {
+            for addr in self.pick(&ctx) {
+                let manager = self.clone();
+                let ctx = ctx.clone();
+                thread::spawn(move || manager.run_connection(&addr, &ctx));
+            }
+            for addr in self.pick_dynamic(&ctx) {
+                let manager = self.clone();
+                let ctx = ctx.clone();
+                thread::spawn(move || manager.run_dynamic(addr, &ctx));
+            }
+            thread::sleep(TICK);
+        });
+    }
+
+    /// Chọn address để kết nối, đánh dấu active luôn để không bị chọn 2 lần
+    fn pick(&self, ctx: &PeerContext) -> Vec<String> {
+        let mut peers = self.peers.lock().unwrap();
+        let now = Instant::now();
+        let active = peers.iter().filter(|p| p.active).count() + self.dynamic.lock().unwrap().len();
+        let mut room = self.target.saturating_sub(active);
+        let mut picked = Vec::new();
+
+        for peer in peers.iter_mut() {
+            if room == 0 {
+                break;
+            }
+            if peer.active || peer.is_self || peer.next_attempt > now {
+                continue;
+            }
+            if is_banned(ctx, &peer.addr) {
+                continue;
+            }
+            peer.active = true;
+            picked.push(peer.addr.clone());
+            room -= 1;
+        }
+        picked
+    }
+
+    /// Chỗ còn trống sau address tĩnh: lấy từ address manager, khác network group
+    fn pick_dynamic(&self, ctx: &PeerContext) -> Vec<SocketAddr> {
+        let (fixed, active_fixed) = {
+            let peers = self.peers.lock().unwrap();
+            let addrs: Vec<String> = peers.iter().map(|p| p.addr.clone()).collect();
+            (addrs, peers.iter().filter(|p| p.active).count())
+        };
+        let mut dynamic = self.dynamic.lock().unwrap();
+        let mut room = self.target.saturating_sub(active_fixed + dynamic.len());
+        let mut groups: Vec<Vec<u8>> = dynamic.iter().map(|a| network_group(&a.ip())).collect();
+
+        let mut addrman = ctx.addrman.lock().unwrap();
+        let mut picked = Vec::new();
+        let mut tries = 0;
+
+        while room > 0 && tries < 30 {
+            tries += 1;
+            let addr = match addrman.select(&groups) {
+                Some(a) => a,
+                None => break,
+            };
+            if dynamic.contains(&addr)
+                || fixed.contains(&addr.to_string())
+                || ctx.ban.lock().unwrap().is_banned(&addr.ip())
+            {
+                continue;
+            }
+
+            addrman.attempt(&addr);
+            dynamic.insert(addr);
+            groups.push(network_group(&addr.ip()));
+            picked.push(addr);
+            room -= 1;
+        }
+        picked
+    }
+
+    fn run_dynamic(&self, addr: SocketAddr, ctx: &PeerContext) {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                if let Err(e) = handle_peer(stream, addr, Direction::Outbound, ctx) {
+                    log::debug!("outbound {} failed: {}", addr, e);
+                }
+            }
+            Err(e) => log::debug!("cannot connect to {}: {}", addr, e),
+        }
+        self.dynamic.lock().unwrap().remove(&addr);
+    }
+
+    fn run_connection(&self, addr: &str, ctx: &PeerContext) {
+        let result = connect(addr).map(|(stream, sock)| {
+            log::debug!("connected to {}", sock);
+            handle_peer(stream, sock, Direction::Outbound, ctx)
+        });
+
+        let mut peers = self.peers.lock().unwrap();
+        let peer = match peers.iter_mut().find(|p| p.addr == addr) {
+            Some(p) => p,
+            None => return,
+        };
+
+        match result {
+            Ok(Ok(())) => peer.schedule_retry(false),
+            Ok(Err(HandshakeError::SelfConnection)) => {
+                log::info!("{} is this node, not connecting again", addr);
+                peer.is_self = true;
+                peer.active = false;
+            }
+            Ok(Err(_)) => peer.schedule_retry(true),
+            Err(e) => {
+                log::debug!("cannot connect to {}: {}", addr, e);
+                peer.schedule_retry(true);
+            }
+        }
+    }
+}
+
+fn connect(addr: &str) -> std::io::Result<(TcpStream, SocketAddr)> {
+    let mut last_err = None;
+    for sock in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&sock, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok((stream, sock)),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::NotFound, "address did not resolve")
+    }))
+}
+
+fn is_banned(ctx: &PeerContext, addr: &str) -> bool {
+    match addr.to_socket_addrs().ok().and_then(|mut a| a.next()) {
+        Some(sock) => ctx.ban.lock().unwrap().is_banned(&sock.ip()),
+        None => false,
+    }
 }
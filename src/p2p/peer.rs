@@ -1,68 +1,801 @@
-use std::net::TcpStream;
-use std::io::Read;
+//! Xử lý message của peer.
+//!
+//! Socket do event loop (p2p::reactor) quản lý; message đã decode được
+//! chuyển qua 1 queue tới validation worker duy nhất ở đây, nên chain /
+//! mempool chỉ bị 1 thread mạng lock. Trạng thái riêng của từng peer nằm
+//! trong worker, không cần lock.
+
+use std::collections::{HashMap, HashSet};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use secp256k1::{PublicKey, SecretKey};
 
-use crate::p2p::message::Message;
+use crate::p2p::addrman::{AddrMan, NetAddress, MAX_ADDR_TO_SEND};
+use crate::p2p::compact::PartialBlock;
+use crate::p2p::download::BlockDownload;
+use crate::p2p::eviction::DEFAULT_MAX_INBOUND;
+use crate::p2p::handshake::{HandshakeError, LocalVersion, PeerVersion};
+use crate::p2p::message::NODE_NETWORK;
+use crate::p2p::message::{InvItem, InvKind, Message};
+use crate::p2p::reactor::{self, NetHandle, NetParts};
+use crate::p2p::network::CONNECT_TIMEOUT;
+use crate::p2p::relay::{PeerHandle, PeerRegistry, Wake, MAX_INV_SIZE};
+use crate::p2p::transport::TransportError;
+use crate::chain::block::Block;
+use crate::chain::filter::BASIC_FILTER;
+use crate::chain::index::BlockStatus;
+use crate::chain::hash::hash_header;
+use crate::chain::header_chain::{HeaderError, MAX_HEADERS_RESULTS};
 use crate::chain::state::ChainState;
+use crate::chain::tx::Transaction;
+use crate::chain::txid::txid;
 use crate::mempool::Mempool;
 use crate::orphan::tx::OrphanTxPool;
 use crate::orphan::block::OrphanBlockPool;
-use crate::net::ban::BanManager;
-use crate::net::rate::RateLimiter;
+use crate::net::ban::{BanManager, Misbehavior};
+use crate::net::rate::Bandwidth;
+
+/// Block cũ hơn tip chừng này không được phục vụ khi hết maxuploadtarget
+pub const HISTORICAL_BLOCK_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Quảng bá lại address của mình cho mỗi peer sau khoảng này
+pub const ADVERTISE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Cứ chừng này Headers liên tiếp không nối được thì phạt peer 1 lần
+pub const MAX_UNCONNECTING_HEADERS: u32 = 10;
+/// Số compact block chờ BlockTxn cùng lúc từ 1 peer; quá thì tải cả block
+pub const MAX_PENDING_COMPACT: usize = 3;
+
+/// Trạng thái dùng chung cho mọi kết nối, inbound lẫn outbound
+#[derive(Clone)]
+pub struct PeerContext {
+    pub chain: Arc<Mutex<ChainState>>,
+    pub mempool: Arc<Mutex<Mempool>>,
+    pub orphan_tx: Arc<Mutex<OrphanTxPool>>,
+    pub orphan_block: Arc<Mutex<OrphanBlockPool>>,
+    pub download: Arc<Mutex<BlockDownload>>,
+    pub ban: Arc<Mutex<BanManager>>,
+    /// Giới hạn băng thông toàn node
+    pub bandwidth: Arc<Mutex<Bandwidth>>,
+    pub addrman: Arc<Mutex<AddrMan>>,
+    /// Peer đã handshake, dùng để relay
+    pub peers: Arc<PeerRegistry>,
+    /// Giao kết nối / listener cho event loop
+    pub net: NetHandle,
+    /// Address của node này quảng bá cho peer (None = không quảng bá)
+    pub local_addr: Option<SocketAddr>,
+    /// Id ngẫu nhiên mỗi lần chạy, để nhận ra kết nối tới chính mình
+    pub node_id: [u8; 32],
+    pub services: u64,
+    /// Số kết nối inbound tối đa (xem p2p::eviction)
+    pub max_inbound: usize,
+    /// Kết nối outbound bằng transport mã hoá (xem p2p::transport)
+    pub v2_transport: bool,
+    /// Key ký challenge lúc handshake, dẫn xuất từ seed dưới Role::Node
+    pub identity: Option<SecretKey>,
+    /// Identity pubkey của node tin cậy: không ban, không evict, không
+    /// giới hạn theo peer, relay trước (chỉ khi kết nối qua transport v2)
+    pub trusted: Arc<HashSet<PublicKey>>,
+    pub(crate) next_peer_id: Arc<AtomicU64>,
+    /// Event loop chưa chạy (xem `start`)
+    net_parts: Arc<Mutex<Option<NetParts>>>,
+}
+
+impl PeerContext {
+    pub fn new(chain: ChainState, addrman: AddrMan, node_id: [u8; 32], services: u64) -> Self {
+        let mempool = Mempool::with_events(chain.events.clone());
+        let (net, net_parts) = reactor::channel().expect("cannot create event loop");
+        PeerContext {
+            chain: Arc::new(Mutex::new(chain)),
+            mempool: Arc::new(Mutex::new(mempool)),
+            orphan_tx: Arc::new(Mutex::new(OrphanTxPool::new())),
+            orphan_block: Arc::new(Mutex::new(OrphanBlockPool::new())),
+            download: Arc::new(Mutex::new(BlockDownload::new())),
+            ban: Arc::new(Mutex::new(BanManager::new())),
+            bandwidth: Arc::new(Mutex::new(Bandwidth::unlimited())),
+            addrman: Arc::new(Mutex::new(addrman)),
+            peers: Arc::new(PeerRegistry::new()),
+            net,
+            local_addr: None,
+            node_id,
+            services,
+            max_inbound: DEFAULT_MAX_INBOUND,
+            v2_transport: true,
+            identity: None,
+            trusted: Arc::new(HashSet::new()),
+            next_peer_id: Arc::new(AtomicU64::new(0)),
+            net_parts: Arc::new(Mutex::new(Some(net_parts))),
+        }
+    }
+
+    /// Chạy event loop và validation worker với cấu hình hiện tại của
+    /// context; gọi 1 lần sau khi đã đặt xong các field
+    pub fn start(&self) {
+        if let Some(parts) = self.net_parts.lock().unwrap().take() {
+            reactor::start(parts, self.clone());
+        }
+    }
+
+    /// Version gửi cho peer lúc handshake
+    pub(crate) fn local_version(&self) -> LocalVersion {
+        let chain = self.chain.lock().unwrap();
+        LocalVersion {
+            genesis_hash: chain.block_hash_at(0).unwrap(),
+            node_id: self.node_id,
+            services: self.services,
+            best_height: chain.tip_height(),
+            identity: self.identity,
+        }
+    }
+
+    /// Đăng ký peer vừa handshake xong; transport lấy message cần gửi từ
+    /// outbox trả về và báo worker bằng WorkItem::Connected
+    pub(crate) fn register_peer(
+        &self,
+        id: u64,
+        addr: SocketAddr,
+        direction: Direction,
+        peer: PeerVersion,
+        waker: Arc<dyn Wake>,
+    ) -> (Arc<PeerHandle>, Receiver<Message>) {
+        // trên v1 chữ ký không gắn với kết nối: kẻ đứng giữa có thể chuyển
+        // challenge cho node tin cậy rồi trả lại Identity của nó
+        let whitelisted =
+            peer.session_id.is_some() && peer.identity.is_some_and(|pk| self.trusted.contains(&pk));
+        log::info!(
+            "{:?} peer {} connected: version {} height {} services {:x} transport {}{}",
+            direction, addr, peer.protocol_version, peer.best_height, peer.services,
+            if peer.session_id.is_some() { "v2" } else { "v1" },
+            match peer.identity {
+                Some(pk) if whitelisted => format!(" identity {} (trusted)", pk),
+                Some(pk) => format!(" identity {}", pk),
+                None => String::new(),
+            }
+        );
+        // chỉ address tự mình kết nối tới mới chắc chắn đúng
+        if direction == Direction::Outbound {
+            self.addrman.lock().unwrap().good(&addr, peer.services);
+        }
+        self.peers.register(id, addr, direction, peer, whitelisted, waker)
+    }
+
+    /// Cộng điểm vi phạm cho peer; true nếu peer bị ban (người gọi nên ngắt).
+    /// Node tin cậy không bị chấm điểm.
+    pub(crate) fn misbehaving(&self, handle: &PeerHandle, what: Misbehavior) -> bool {
+        if handle.whitelisted {
+            log::debug!("trusted peer {} misbehaving ({:?}), not scoring", handle.addr, what);
+            return false;
+        }
+        self.ban.lock().unwrap().misbehaving(handle.addr.ip(), what)
+    }
+}
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// Chạy 1 kết nối đã mở tới khi ngắt. Err nếu handshake không thành.
+/// Outbound thử transport v2 trước, peer không hiểu thì kết nối lại bằng v1.
 pub fn handle_peer(
-    mut stream: TcpStream,
-    ip: String,
-    chain: Arc<Mutex<ChainState>>,
-    mempool: Arc<Mutex<Mempool>>,
-    orphan_tx: Arc<Mutex<OrphanTxPool>>,
-    orphan_block: Arc<Mutex<OrphanBlockPool>>,
-    ban: Arc<Mutex<BanManager>>,
-    rate: Arc<Mutex<RateLimiter>>,
-) {
-    let mut buf = [0u8; 8192];
+    stream: TcpStream,
+    addr: SocketAddr,
+    direction: Direction,
+    ctx: &PeerContext,
+) -> Result<(), HandshakeError> {
+    let v2 = direction == Direction::Outbound && ctx.v2_transport;
+    match run_connection(stream, addr, direction, v2, ctx) {
+        Err(HandshakeError::Transport(TransportError::Rejected)) => {
+            log::debug!("{} does not support v2 transport, reconnecting with v1", addr);
+            let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)
+                .map_err(|e| HandshakeError::Frame(e.into()))?;
+            run_connection(stream, addr, direction, false, ctx)
+        }
+        result => result,
+    }
+}
 
-    loop {
-        if !rate.lock().unwrap().allow(&ip) {
-            if ban.lock().unwrap().add_score(&ip, 20) {
-                return;
+fn run_connection(
+    stream: TcpStream,
+    addr: SocketAddr,
+    direction: Direction,
+    v2: bool,
+    ctx: &PeerContext,
+) -> Result<(), HandshakeError> {
+    match ctx.net.connect(stream, addr, direction, v2) {
+        Ok(done) => done.recv().unwrap_or(Ok(())),
+        Err(e) => Err(HandshakeError::Frame(e.into())),
+    }
+}
+
+/// Transport (event loop / simulator) gửi cho validation worker
+pub(crate) enum WorkItem {
+    /// Handshake xong
+    Connected(Arc<PeerHandle>),
+    Message(u64, Message),
+    Disconnected(u64),
+}
+
+/// Trạng thái riêng của 1 peer, chỉ worker dùng
+struct PeerState {
+    handle: Arc<PeerHandle>,
+    /// Peer không phục vụ toàn bộ lịch sử: chỉ lấy header, body tải từ peer khác
+    serves_blocks: bool,
+    answered_getaddr: bool,
+    /// Compact block đang chờ BlockTxn, theo block hash
+    pending_compact: HashMap<[u8; 32], PartialBlock>,
+    last_advertised: Option<Instant>,
+    /// Số Headers liên tiếp có header đầu không nối được
+    unconnecting_headers: u32,
+}
+
+/// Validation worker: xử lý tuần tự message của mọi peer
+pub(crate) struct Worker {
+    ctx: PeerContext,
+    states: HashMap<u64, PeerState>,
+}
+
+impl Worker {
+    pub(crate) fn new(ctx: PeerContext) -> Self {
+        Self { ctx, states: HashMap::new() }
+    }
+
+    pub(crate) fn handle(&mut self, item: WorkItem) {
+        let ctx = &self.ctx;
+        match item {
+            WorkItem::Connected(handle) => {
+                let mut state = PeerState {
+                    serves_blocks: handle.remote.services & NODE_NETWORK != 0,
+                    handle,
+                    answered_getaddr: false,
+                    pending_compact: HashMap::new(),
+                    last_advertised: None,
+                    unconnecting_headers: 0,
+                };
+                if !on_connected(&mut state, ctx) {
+                    state.handle.disconnect();
+                }
+                self.states.insert(state.handle.id, state);
+            }
+
+            WorkItem::Message(id, msg) => {
+                let state = match self.states.get_mut(&id) {
+                    Some(s) if !s.handle.is_closing() => s,
+                    _ => return,
+                };
+                if !advertise(state, ctx) || !process_message(state, msg, ctx) {
+                    state.handle.disconnect();
+                }
+            }
+
+            WorkItem::Disconnected(id) => {
+                if let Some(state) = self.states.remove(&id) {
+                    // block đang chờ từ peer này được giao lại cho peer khác
+                    ctx.download.lock().unwrap().peer_disconnected(id);
+                    log::info!("peer {} disconnected", state.handle.addr);
+                }
+            }
+        }
+    }
+}
+
+/// Thread của worker. `queued` đếm message đang chờ trong queue, event
+/// loop dựa vào đó để ngừng đọc socket khi worker không theo kịp.
+pub(crate) fn run_worker(items: Receiver<WorkItem>, queued: Arc<AtomicUsize>, ctx: PeerContext) {
+    let mut worker = Worker::new(ctx);
+    for item in items {
+        if let WorkItem::Message(..) = item {
+            queued.fetch_sub(1, Ordering::Relaxed);
+        }
+        worker.handle(item);
+    }
+}
+
+/// Message đầu tiên sau handshake; false nếu phải ngắt kết nối
+fn on_connected(state: &mut PeerState, ctx: &PeerContext) -> bool {
+    let handle = &state.handle;
+
+    // headers-first: hỏi header trước, body được tải sau theo best header
+    let locator = {
+        let chain = ctx.chain.lock().unwrap();
+        chain.block_locator(&chain.best_header().hash)
+    };
+    if !handle.send(Message::GetHeaders { locator, stop: [0u8; 32] }) {
+        return false;
+    }
+
+    // inbound có thể là spy dò bảng address: chỉ hỏi peer mình chọn
+    if handle.direction == Direction::Outbound && !handle.send(Message::GetAddr) {
+        return false;
+    }
+    advertise(state, ctx)
+}
+
+/// Quảng bá address của mình nếu đến hạn
+fn advertise(state: &mut PeerState, ctx: &PeerContext) -> bool {
+    let local = match ctx.local_addr {
+        Some(a) => a,
+        None => return true,
+    };
+    if state.last_advertised.is_some_and(|t| t.elapsed() < ADVERTISE_INTERVAL) {
+        return true;
+    }
+    state.last_advertised = Some(Instant::now());
+    let addrs = vec![NetAddress { addr: local, services: ctx.services, time: unix_now() }];
+    state.handle.send(Message::Addr { addrs })
+}
+
+/// Xử lý 1 message; false nếu phải ngắt kết nối
+fn process_message(state: &mut PeerState, msg: Message, ctx: &PeerContext) -> bool {
+    let PeerContext { chain, mempool, orphan_tx, download, bandwidth, addrman, .. } = ctx;
+    let handle = state.handle.clone();
+    let handle = &*handle;
+    let serves_blocks = state.serves_blocks;
+    let addr = handle.addr;
+    let ip = &addr.ip().to_string();
+
+    match msg {
+        Message::Ping { nonce } => return handle.send(Message::Pong { nonce }),
+
+        Message::Pong { nonce } => handle.on_pong(nonce, Instant::now()),
+
+        Message::Tx { tx } => {
+            // peer đã có tx này: không báo ngược lại
+            handle.mark_known(InvItem { kind: InvKind::Tx, hash: txid(&tx) });
+            let chain = chain.lock().unwrap();
+            let mut mem = mempool.lock().unwrap();
+
+            if mem.add(tx.clone(), &chain.utxos) {
+                handle.stats.lock().unwrap().last_tx = Some(Instant::now());
+            } else {
+                orphan_tx.lock().unwrap().add(tx);
             }
-            continue;
         }
 
-        let size = match stream.read(&mut buf) {
-            Ok(0) => return,
-            Ok(n) => n,
-            Err(_) => return,
-        };
+        Message::GetHeaders { locator, stop } => {
+            let headers = chain.lock().unwrap().headers_after_locator(&locator, &stop);
+            if !handle.send(Message::Headers { headers }) {
+                return false;
+            }
+        }
 
-        let msg: Message = match bincode::deserialize(&buf[..size]) {
-            Ok(m) => m,
-            Err(_) => {
-                ban.lock().unwrap().add_score(&ip, 10);
-                continue;
+        Message::Headers { headers } => {
+            if headers.len() > MAX_HEADERS_RESULTS {
+                if ctx.misbehaving(handle, Misbehavior::OversizedMessage) {
+                    return false;
+                }
+                return true;
             }
-        };
 
-        match msg {
-            Message::Tx { tx } => {
-                let chain = chain.lock().unwrap();
-                let mut mem = mempool.lock().unwrap();
+            let mut chain = chain.lock().unwrap();
+            let mut last = None;
+            let mut request_more = headers.len() == MAX_HEADERS_RESULTS;
 
-                if !mem.add(tx.clone(), &chain.utxos) {
-                    orphan_tx.lock().unwrap().add(tx);
+            for (i, header) in headers.iter().enumerate() {
+                match chain.process_header(header) {
+                    Ok(hash) => last = Some(hash),
+                    // header đầu không nối được: peer ở nhánh ta chưa biết, hỏi lại từ
+                    // locator; lặp lại mãi thì peer đang làm ta tốn GetHeaders
+                    Err(HeaderError::UnknownParent(_)) if i == 0 => {
+                        state.unconnecting_headers += 1;
+                        if state.unconnecting_headers.is_multiple_of(MAX_UNCONNECTING_HEADERS)
+                            && ctx.misbehaving(handle, Misbehavior::UnconnectingHeaders)
+                        {
+                            return false;
+                        }
+                        request_more = true;
+                        break;
+                    }
+                    // đồng hồ lệch không phải lỗi của peer
+                    Err(HeaderError::TimeTooNew) => break,
+                    Err(e) => {
+                        log::warn!("invalid header from {}: {}", ip, e);
+                        if ctx.misbehaving(handle, Misbehavior::InvalidHeader) {
+                            return false;
+                        }
+                        request_more = false;
+                        break;
+                    }
                 }
             }
 
-            Message::Block { block } => {
-                let mut chain = chain.lock().unwrap();
-                if !chain.add_block(block.clone()) {
-                    orphan_block.lock().unwrap().add(block);
-                    ban.lock().unwrap().add_score(&ip, 5);
+            if last.is_some() {
+                state.unconnecting_headers = 0;
+            }
+            if request_more {
+                let from = last.unwrap_or(chain.best_header().hash);
+                let locator = chain.block_locator(&from);
+                if !handle.send(Message::GetHeaders { locator, stop: [0u8; 32] }) {
+                    return false;
                 }
             }
 
-            _ => {}
+            if serves_blocks && !request_blocks(handle, &chain, download) {
+                return false;
+            }
         }
+
+        Message::Inv { items } => {
+            if items.len() > MAX_INV_SIZE {
+                if ctx.misbehaving(handle, Misbehavior::OversizedMessage) {
+                    return false;
+                }
+                return true;
+            }
+
+            let mut wanted = Vec::new();
+            let mut unknown_block = false;
+            for item in items {
+                handle.mark_known(item);
+                match item.kind {
+                    InvKind::Tx => {
+                        if !mempool.lock().unwrap().txs.contains_key(&item.hash) {
+                            wanted.push(item);
+                        }
+                    }
+                    InvKind::Block => {
+                        if !chain.lock().unwrap().index.contains(&item.hash) {
+                            unknown_block = true;
+                        }
+                    }
+                }
+            }
+
+            // block mới đi đường headers-first, body tải qua BlockDownload
+            if unknown_block {
+                let locator = {
+                    let chain = chain.lock().unwrap();
+                    chain.block_locator(&chain.best_header().hash)
+                };
+                if !handle.send(Message::GetHeaders { locator, stop: [0u8; 32] }) {
+                    return false;
+                }
+            }
+            if !wanted.is_empty() && !handle.send(Message::GetData { items: wanted }) {
+                return false;
+            }
+        }
+
+        Message::GetData { items } => {
+            if items.len() > MAX_INV_SIZE {
+                if ctx.misbehaving(handle, Misbehavior::OversizedMessage) {
+                    return false;
+                }
+                return true;
+            }
+
+            let mut not_found = Vec::new();
+            for item in items {
+                let reply = match item.kind {
+                    InvKind::Tx => mempool
+                        .lock()
+                        .unwrap()
+                        .txs
+                        .get(&item.hash)
+                        .map(|m| Message::Tx { tx: m.tx.clone() }),
+                    // hết maxuploadtarget: chỉ phục vụ block gần tip
+                    InvKind::Block => {
+                        let chain = chain.lock().unwrap();
+                        let limited = bandwidth.lock().unwrap().upload_target_reached(Instant::now());
+                        if limited && !handle.whitelisted && is_historical(&chain, &item.hash) {
+                            log::debug!("upload target reached, not serving {} to {}", hex::encode(item.hash), ip);
+                            None
+                        } else {
+                            chain.get_servable_block(&item.hash).map(|block| Message::Block { block })
+                        }
+                    }
+                };
+                match reply {
+                    Some(msg) => {
+                        handle.mark_known(item);
+                        if !handle.send(msg) {
+                            return false;
+                        }
+                    }
+                    None => not_found.push(item),
+                }
+            }
+            if !not_found.is_empty() && !handle.send(Message::NotFound { items: not_found }) {
+                return false;
+            }
+        }
+
+        // block peer không có được giao lại cho peer khác
+        Message::NotFound { items } => {
+            let mut download = download.lock().unwrap();
+            for item in items.iter().filter(|i| i.kind == InvKind::Block) {
+                download.received(&item.hash);
+                state.pending_compact.remove(&item.hash);
+            }
+        }
+
+        Message::Block { block } => {
+            state.pending_compact.remove(&hash_header(&block.header));
+            let keep = process_block(block, handle, ctx, serves_blocks);
+            if !keep {
+                return false;
+            }
+        }
+
+        Message::CompactBlock { block: cmpct } => {
+            let hash = hash_header(&cmpct.header);
+            handle.mark_known(InvItem { kind: InvKind::Block, hash });
+            let mut chain = chain.lock().unwrap();
+
+            // chưa biết parent: đi đường headers-first như Inv
+            if !chain.index.contains(&cmpct.header.prev_hash) {
+                let locator = chain.block_locator(&chain.best_header().hash);
+                if !handle.send(Message::GetHeaders { locator, stop: [0u8; 32] }) {
+                    return false;
+                }
+                return true;
+            }
+            match chain.process_header(&cmpct.header) {
+                Ok(_) => {}
+                Err(HeaderError::TimeTooNew) => return true,
+                Err(e) => {
+                    log::warn!("invalid compact block header from {}: {}", ip, e);
+                    if ctx.misbehaving(handle, Misbehavior::InvalidHeader) {
+                        return false;
+                    }
+                    return true;
+                }
+            }
+            let have_data = |h: &[u8; 32]| chain.index.get(h).is_some_and(|e| e.status.has(BlockStatus::HAVE_DATA));
+            if have_data(&hash) || !chain.is_potential_tip(&cmpct.header) {
+                return true;
+            }
+            // block đã tới bằng đường khác thì không chờ BlockTxn nữa
+            state.pending_compact.retain(|h, _| !have_data(h));
+
+            let partial = {
+                let mem = mempool.lock().unwrap();
+                let orphans = orphan_tx.lock().unwrap();
+                let candidates = mem.txs.values().map(|m| &m.tx);
+                PartialBlock::new(&cmpct, candidates.chain(orphans.txs.values().map(|o| &o.tx)))
+            };
+            drop(chain);
+
+            match partial {
+                Ok(partial) if partial.missing().is_empty() => {
+                    if !complete_compact(partial, vec![], handle, ctx, serves_blocks) {
+                        return false;
+                    }
+                }
+                Ok(partial)
+                    if state.pending_compact.len() < MAX_PENDING_COMPACT
+                        || state.pending_compact.contains_key(&hash) =>
+                {
+                    download.lock().unwrap().request(handle.id, hash);
+                    let indexes = partial.missing();
+                    log::debug!("compact block {} from {}: {} txs missing", hex::encode(hash), ip, indexes.len());
+                    state.pending_compact.insert(hash, partial);
+                    if !handle.send(Message::GetBlockTxn { block_hash: hash, indexes }) {
+                        return false;
+                    }
+                }
+                Err(e) if e.is_malformed() => {
+                    log::debug!("bad compact block from {}: {}", ip, e);
+                    if ctx.misbehaving(handle, Misbehavior::InvalidCompactBlock) {
+                        return false;
+                    }
+                }
+                // trùng short id hoặc đã chờ quá nhiều BlockTxn: tải cả block
+                Ok(_) | Err(_) => {
+                    download.lock().unwrap().request(handle.id, hash);
+                    let items = vec![InvItem { kind: InvKind::Block, hash }];
+                    if !handle.send(Message::GetData { items }) {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        Message::GetBlockTxn { block_hash, indexes } => {
+            let block = chain.lock().unwrap().get_servable_block(&block_hash);
+            let block = match block {
+                Some(b) => b,
+                None => {
+                    let items = vec![InvItem { kind: InvKind::Block, hash: block_hash }];
+                    if !handle.send(Message::NotFound { items }) {
+                        return false;
+                    }
+                    return true;
+                }
+            };
+
+            let txs: Option<Vec<Transaction>> = indexes
+                .iter()
+                .map(|&i| block.transactions.get(i as usize).cloned())
+                .collect();
+            match txs {
+                Some(txs) => {
+                    if !handle.send(Message::BlockTxn { block_hash, txs }) {
+                        return false;
+                    }
+                }
+                None => {
+                    if ctx.misbehaving(handle, Misbehavior::BadBlockTxnRequest) {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        Message::BlockTxn { block_hash, txs } => {
+            let partial = match state.pending_compact.remove(&block_hash) {
+                Some(p) => p,
+                // không xin: bỏ qua
+                None => return true,
+            };
+            if !complete_compact(partial, txs, handle, ctx, serves_blocks) {
+                return false;
+            }
+        }
+
+        Message::GetCFilters { filter_type, start_height, stop_hash } => {
+            if filter_type != BASIC_FILTER {
+                return !ctx.misbehaving(handle, Misbehavior::BadFilterRequest);
+            }
+
+            // thiếu filter là lỗi của mình: trả danh sách rỗng, không phạt
+            let filters = match chain.lock().unwrap().get_cfilters(start_height, &stop_hash) {
+                Ok(filters) => filters,
+                Err(e) if e.is_malformed() => {
+                    log::debug!("bad getcfilters from {}: {}", ip, e);
+                    return !ctx.misbehaving(handle, Misbehavior::BadFilterRequest);
+                }
+                Err(e) => {
+                    log::debug!("getcfilters from {}: {}", ip, e);
+                    vec![]
+                }
+            };
+            if !handle.send(Message::CFilters { filter_type, filters }) {
+                return false;
+            }
+        }
+
+        Message::GetCFHeaders { filter_type, start_height, stop_hash } => {
+            if filter_type != BASIC_FILTER {
+                return !ctx.misbehaving(handle, Misbehavior::BadFilterRequest);
+            }
+
+            let (prev_header, filter_hashes) = match chain.lock().unwrap().get_cfheaders(start_height, &stop_hash) {
+                Ok(headers) => headers,
+                Err(e) if e.is_malformed() => {
+                    log::debug!("bad getcfheaders from {}: {}", ip, e);
+                    return !ctx.misbehaving(handle, Misbehavior::BadFilterRequest);
+                }
+                Err(e) => {
+                    log::debug!("getcfheaders from {}: {}", ip, e);
+                    ([0u8; 32], vec![])
+                }
+            };
+            let reply = Message::CFHeaders {
+                filter_type,
+                stop_hash,
+                prev_header,
+                filter_hashes,
+            };
+            if !handle.send(reply) {
+                return false;
+            }
+        }
+
+        // trả lời 1 lần mỗi kết nối, tránh bị dò toàn bộ bảng
+        Message::GetAddr => {
+            if state.answered_getaddr {
+                return true;
+            }
+            state.answered_getaddr = true;
+            let addrs = addrman.lock().unwrap().get_addr();
+            return handle.send(Message::Addr { addrs });
+        }
+
+        Message::Addr { addrs } => {
+            if addrs.len() > MAX_ADDR_TO_SEND {
+                if ctx.misbehaving(handle, Misbehavior::OversizedMessage) {
+                    return false;
+                }
+                return true;
+            }
+            addrman.lock().unwrap().add(&addrs, &addr.ip());
+        }
+
+        // handshake chỉ có 1 lần
+        Message::Handshake { .. } | Message::Verack | Message::Challenge { .. } | Message::Identity { .. } => {
+            return !ctx.misbehaving(handle, Misbehavior::HandshakeViolation);
+        }
+
+        _ => {}
+    }
+    true
+}
+
+
+/// Xử lý block body nhận từ peer; false nếu phải ngắt kết nối
+fn process_block(block: Block, handle: &PeerHandle, ctx: &PeerContext, serves_blocks: bool) -> bool {
+    let hash = hash_header(&block.header);
+    handle.mark_known(InvItem { kind: InvKind::Block, hash });
+    let mut chain = ctx.chain.lock().unwrap();
+    let requested = ctx.download.lock().unwrap().received(&hash);
+
+    if !requested && !chain.is_potential_tip(&block.header) && !chain.needs_snapshot_block(&hash) {
+        // chưa biết parent: giữ lại và hỏi header để nối vào
+        if !chain.index.contains(&block.header.prev_hash) {
+            ctx.orphan_block.lock().unwrap().add(block);
+            let locator = chain.block_locator(&chain.best_header().hash);
+            return handle.send(Message::GetHeaders { locator, stop: [0u8; 32] });
+        }
+        // block không yêu cầu và không thể thành tip: bỏ qua
+        return true;
     }
+
+    let new = !chain.index.get(&hash).is_some_and(|e| e.status.has(BlockStatus::HAVE_DATA));
+    match chain.accept_block(block, None) {
+        Ok(()) => {
+            if new {
+                handle.stats.lock().unwrap().last_block = Some(Instant::now());
+            }
+        }
+        Err(e) if e.is_consensus_failure() => {
+            log::warn!("invalid block {} from {}: {}", hex::encode(hash), handle.addr, e);
+            if ctx.misbehaving(handle, Misbehavior::InvalidBlock) {
+                return false;
+            }
+        }
+        Err(e) => log::debug!("block {} from {} not accepted: {}", hex::encode(hash), handle.addr, e),
+    }
+
+    !serves_blocks || request_blocks(handle, &chain, &ctx.download)
+}
+
+/// Điền tx còn thiếu vào compact block; dựng không được thì tải cả block
+fn complete_compact(
+    partial: PartialBlock,
+    txs: Vec<Transaction>,
+    handle: &PeerHandle,
+    ctx: &PeerContext,
+    serves_blocks: bool,
+) -> bool {
+    let hash = partial.hash;
+    match partial.fill(txs) {
+        Ok(block) => process_block(block, handle, ctx, serves_blocks),
+        Err(e) => {
+            log::debug!("cannot reconstruct {} from {}: {}", hex::encode(hash), handle.addr, e);
+            let items = vec![InvItem { kind: InvKind::Block, hash }];
+            handle.send(Message::GetData { items })
+        }
+    }
+}
+
+/// Block cũ hơn tip quá HISTORICAL_BLOCK_AGE
+fn is_historical(chain: &ChainState, hash: &[u8; 32]) -> bool {
+    let tip_time = chain.tip_entry().header.timestamp;
+    chain
+        .index
+        .get(hash)
+        .is_some_and(|e| e.header.timestamp + HISTORICAL_BLOCK_AGE.as_secs() < tip_time)
+}
+
+/// Xin body của các block tiếp theo trên nhánh best header
+fn request_blocks(handle: &PeerHandle, chain: &ChainState, download: &Mutex<BlockDownload>) -> bool {
+    let items: Vec<InvItem> = download
+        .lock()
+        .unwrap()
+        .assign(handle.id, chain)
+        .into_iter()
+        .map(|hash| InvItem { kind: InvKind::Block, hash })
+        .collect();
+    items.is_empty() || handle.send(Message::GetData { items })
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
 }
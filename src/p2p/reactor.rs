@@ -0,0 +1,652 @@
+//! Event loop mạng.
+//!
+//! 1 thread (mio) quản lý mọi socket: nhận kết nối inbound, đọc byte, giải
+//! mã (p2p::transport), tách frame (FrameDecoder), làm handshake, rồi chuyển message cho validation
+//! worker (peer::run_worker). Message gửi cho peer nằm trong outbox của
+//! PeerHandle, event loop ghi dần ra socket khi socket ghi được.
+//!
+//! Backpressure: mỗi peer giữ tối đa MAX_PEER_QUEUE message chưa chuyển
+//! cho worker, worker nhận tối đa MAX_WORKER_QUEUE message đang chờ; quá
+//! ngưỡng thì ngừng đọc socket của peer (TCP tự làm chậm bên gửi).
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{self as stdnet, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mio::net::{TcpListener, TcpStream};
+use mio::{Events, Interest, Poll, Token, Waker};
+
+use crate::net::ban::Misbehavior;
+use crate::net::rate::PeerRateLimits;
+use crate::p2p::eviction::InboundCounts;
+use crate::p2p::frame::{encode_frame, FrameDecoder, FrameError};
+use crate::p2p::handshake::{HandshakeError, HandshakeState, PeerVersion};
+use crate::p2p::message::Message;
+use crate::p2p::peer::{run_worker, Direction, PeerContext, WorkItem};
+use crate::p2p::relay::PeerHandle;
+use crate::p2p::transport::{Transport, TransportError};
+
+/// Tổng số message chờ worker tối đa
+pub const MAX_WORKER_QUEUE: usize = 1000;
+/// Số message đã decode chờ chuyển cho worker, mỗi peer
+pub const MAX_PEER_QUEUE: usize = 100;
+/// Có byte chờ ghi mà không ghi được gì trong khoảng này thì ngắt
+pub const WRITE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Ngừng lấy message từ outbox khi đã có chừng này byte chờ ghi
+const SEND_BUFFER_SIZE: usize = 1024 * 1024;
+const READ_CHUNK: usize = 64 * 1024;
+const WAKER: Token = Token(usize::MAX);
+const TICK: Duration = Duration::from_millis(500);
+/// Chờ ngắn khi còn message đợi chỗ trong queue của worker
+const BUSY_TICK: Duration = Duration::from_millis(10);
+
+type Done = Sender<Result<(), HandshakeError>>;
+
+enum Command {
+    Listen(stdnet::TcpListener),
+    Connect {
+        stream: stdnet::TcpStream,
+        addr: SocketAddr,
+        direction: Direction,
+        v2: bool,
+        done: Done,
+    },
+}
+
+/// Giao listener / kết nối cho event loop từ thread khác
+#[derive(Clone)]
+pub struct NetHandle {
+    commands: Sender<Command>,
+    waker: Arc<Waker>,
+}
+
+/// Event loop đã tạo nhưng chưa chạy (xem PeerContext::start)
+pub struct NetParts {
+    poll: Poll,
+    commands: Receiver<Command>,
+    waker: Arc<Waker>,
+}
+
+pub fn channel() -> io::Result<(NetHandle, NetParts)> {
+    let poll = Poll::new()?;
+    let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+    let (commands, rx) = mpsc::channel();
+    let handle = NetHandle { commands, waker: waker.clone() };
+    Ok((handle, NetParts { poll, commands: rx, waker }))
+}
+
+impl NetHandle {
+    /// Nhận kết nối inbound từ `listener`
+    pub fn listen(&self, listener: stdnet::TcpListener) -> io::Result<()> {
+        listener.set_nonblocking(true)?;
+        self.command(Command::Listen(listener))
+    }
+
+    /// Giao kết nối đã mở; receiver nhận kết quả khi kết nối đóng.
+    /// `v2`: kết nối outbound mở đầu bằng key exchange của transport v2.
+    pub fn connect(
+        &self,
+        stream: stdnet::TcpStream,
+        addr: SocketAddr,
+        direction: Direction,
+        v2: bool,
+    ) -> io::Result<Receiver<Result<(), HandshakeError>>> {
+        stream.set_nonblocking(true)?;
+        let (done, rx) = mpsc::channel();
+        self.command(Command::Connect { stream, addr, direction, v2, done })?;
+        Ok(rx)
+    }
+
+    fn command(&self, cmd: Command) -> io::Result<()> {
+        self.commands
+            .send(cmd)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "event loop stopped"))?;
+        self.waker.wake()
+    }
+}
+
+/// Chạy event loop và validation worker
+pub fn start(parts: NetParts, ctx: PeerContext) {
+    let (worker, items) = mpsc::channel();
+    let queued = Arc::new(AtomicUsize::new(0));
+    {
+        let ctx = ctx.clone();
+        let queued = queued.clone();
+        thread::spawn(move || run_worker(items, queued, ctx));
+    }
+
+    let magic = ctx.chain.lock().unwrap().params.magic;
+    let mut reactor = Reactor {
+        poll: parts.poll,
+        commands: parts.commands,
+        waker: parts.waker,
+        ctx,
+        magic,
+        listeners: HashMap::new(),
+        conns: HashMap::new(),
+        worker,
+        queued,
+        read_paused_until: None,
+        write_paused_until: None,
+    };
+    thread::spawn(move || reactor.run());
+}
+
+enum Phase {
+    Handshake(Box<HandshakeState>),
+    Ready {
+        handle: Arc<PeerHandle>,
+        outbox: Receiver<Message>,
+    },
+}
+
+struct Conn {
+    id: u64,
+    addr: SocketAddr,
+    direction: Direction,
+    stream: TcpStream,
+    magic: [u8; 4],
+    /// Mã hoá (v2) hoặc không (v1), nằm giữa socket và frame
+    transport: Transport,
+    phase: Phase,
+    decoder: FrameDecoder,
+    /// Message đã decode, chờ chuyển cho worker
+    inbound: VecDeque<Message>,
+    /// Ngừng đọc / decode vì `inbound` đầy
+    paused: bool,
+    /// Token bucket theo loại message
+    limits: PeerRateLimits,
+    /// Hết token: không đọc / decode tới lúc này
+    throttled_until: Option<Instant>,
+    send_buf: Vec<u8>,
+    /// Số byte đầu send_buf đã ghi
+    sent: usize,
+    last_write: Instant,
+    /// Edge-triggered: true tới khi gặp WouldBlock
+    readable: bool,
+    writable: bool,
+    closing: bool,
+    /// Lý do handshake thất bại
+    error: Option<HandshakeError>,
+    done: Option<Done>,
+}
+
+impl Conn {
+    fn queue(&mut self, msg: &Message, now: Instant) {
+        if self.sent == self.send_buf.len() {
+            self.last_write = now;
+        }
+        self.transport.send(encode_frame(&self.magic, msg), &mut self.send_buf);
+    }
+
+    /// Byte đọc từ socket qua transport vào decoder
+    fn receive(&mut self, bytes: &[u8], now: Instant) {
+        if self.sent == self.send_buf.len() {
+            self.last_write = now;
+        }
+        let mut plain = Vec::new();
+        match self.transport.receive(bytes, &mut plain, &mut self.send_buf) {
+            Ok(()) => self.decoder.push(&plain),
+            Err(e) => {
+                log::debug!("disconnecting {}: {}", self.addr, e);
+                self.close(Some(HandshakeError::Transport(e)));
+            }
+        }
+    }
+
+    fn close(&mut self, error: Option<HandshakeError>) {
+        self.closing = true;
+        if self.error.is_none() {
+            self.error = error;
+        }
+    }
+
+    /// Lỗi socket / EOF
+    fn fail(&mut self, e: io::Error) {
+        // peer cũ ngắt ngay khi nhận key v2: báo để kết nối lại bằng v1
+        let error = if self.transport.awaiting_key() {
+            HandshakeError::Transport(TransportError::Rejected)
+        } else {
+            HandshakeError::Frame(FrameError::Io(e))
+        };
+        self.close(Some(error));
+    }
+
+    fn pending_write(&self) -> usize {
+        self.send_buf.len() - self.sent
+    }
+}
+
+struct Reactor {
+    poll: Poll,
+    commands: Receiver<Command>,
+    waker: Arc<Waker>,
+    ctx: PeerContext,
+    magic: [u8; 4],
+    listeners: HashMap<Token, TcpListener>,
+    conns: HashMap<Token, Conn>,
+    worker: Sender<WorkItem>,
+    queued: Arc<AtomicUsize>,
+    /// Vượt giới hạn download / upload toàn node: ngừng đọc / ghi tới lúc này
+    read_paused_until: Option<Instant>,
+    write_paused_until: Option<Instant>,
+}
+
+impl Reactor {
+    fn run(&mut self) {
+        let mut events = Events::with_capacity(1024);
+        loop {
+            let busy = self.conns.values().any(|c| c.paused || !c.inbound.is_empty());
+            let mut timeout = if busy { BUSY_TICK } else { TICK };
+            if let Some(deadline) = self.next_deadline() {
+                timeout = timeout.min(deadline.saturating_duration_since(Instant::now()));
+            }
+            if let Err(e) = self.poll.poll(&mut events, Some(timeout)) {
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                log::error!("event loop stopped: {}", e);
+                return;
+            }
+
+            for event in events.iter() {
+                let token = event.token();
+                if token == WAKER {
+                    continue;
+                }
+                if self.listeners.contains_key(&token) {
+                    self.accept(token);
+                    continue;
+                }
+                if let Some(conn) = self.conns.get_mut(&token) {
+                    if event.is_readable() || event.is_read_closed() || event.is_error() {
+                        conn.readable = true;
+                    }
+                    if event.is_writable() || event.is_write_closed() {
+                        conn.writable = true;
+                    }
+                }
+            }
+
+            while let Ok(cmd) = self.commands.try_recv() {
+                self.command(cmd);
+            }
+
+            let now = Instant::now();
+            if self.read_paused_until.is_some_and(|t| t <= now) {
+                self.read_paused_until = None;
+            }
+            if self.write_paused_until.is_some_and(|t| t <= now) {
+                self.write_paused_until = None;
+            }
+            let tokens: Vec<Token> = self.conns.keys().copied().collect();
+            for token in tokens {
+                let mut conn = self.conns.remove(&token).unwrap();
+                self.service(&mut conn, now);
+                if conn.closing {
+                    self.finish(conn);
+                } else {
+                    self.conns.insert(token, conn);
+                }
+            }
+
+            self.forward();
+        }
+    }
+
+    /// Lúc sớm nhất 1 giới hạn tốc độ hết hiệu lực
+    fn next_deadline(&self) -> Option<Instant> {
+        self.conns
+            .values()
+            .filter_map(|c| c.throttled_until)
+            .chain(self.read_paused_until)
+            .chain(self.write_paused_until)
+            .min()
+    }
+
+    fn command(&mut self, cmd: Command) {
+        match cmd {
+            Command::Listen(listener) => {
+                let mut listener = TcpListener::from_std(listener);
+                let token = self.next_token();
+                match self.poll.registry().register(&mut listener, token, Interest::READABLE) {
+                    Ok(()) => {
+                        self.listeners.insert(token, listener);
+                    }
+                    Err(e) => log::error!("cannot listen: {}", e),
+                }
+            }
+            Command::Connect { stream, addr, direction, v2, done } => {
+                self.add_conn(TcpStream::from_std(stream), addr, direction, v2, Some(done));
+            }
+        }
+    }
+
+    fn next_token(&self) -> Token {
+        Token(self.ctx.next_peer_id.fetch_add(1, Ordering::Relaxed) as usize)
+    }
+
+    fn accept(&mut self, token: Token) {
+        loop {
+            let (stream, addr) = match self.listeners[&token].accept() {
+                Ok(s) => s,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    log::debug!("accept failed: {}", e);
+                    return;
+                }
+            };
+            if self.ctx.ban.lock().unwrap().is_banned(&addr.ip()) {
+                continue;
+            }
+            if let Err(reason) = self.make_room(&addr) {
+                log::debug!("rejecting inbound {}: {}", addr, reason);
+                continue;
+            }
+            self.add_conn(stream, addr, Direction::Inbound, false, None);
+        }
+    }
+
+    /// Kiểm tra giới hạn inbound; hết slot thì đuổi 1 peer inbound
+    fn make_room(&self, addr: &SocketAddr) -> Result<(), &'static str> {
+        let mut counts = InboundCounts::default();
+        for conn in self.conns.values() {
+            let closing = match &conn.phase {
+                Phase::Ready { handle, .. } => handle.is_closing(),
+                Phase::Handshake(_) => false,
+            };
+            if conn.direction == Direction::Inbound && !conn.closing && !closing {
+                counts.add(conn.addr.ip());
+            }
+        }
+        if let Some(reason) = counts.check(&addr.ip()) {
+            return Err(reason);
+        }
+        if counts.total < self.ctx.max_inbound {
+            return Ok(());
+        }
+
+        let peer = self.ctx.peers.select_eviction().ok_or("inbound slots full")?;
+        log::info!("evicting peer {} for inbound {}", peer.addr, addr);
+        peer.disconnect();
+        Ok(())
+    }
+
+    fn add_conn(
+        &mut self,
+        mut stream: TcpStream,
+        addr: SocketAddr,
+        direction: Direction,
+        v2: bool,
+        done: Option<Done>,
+    ) {
+        let token = self.next_token();
+        let interest = Interest::READABLE | Interest::WRITABLE;
+        if let Err(e) = self.poll.registry().register(&mut stream, token, interest) {
+            if let Some(done) = done {
+                let _ = done.send(Err(HandshakeError::Frame(FrameError::Io(e))));
+            }
+            return;
+        }
+
+        let state = HandshakeState::new(self.ctx.local_version());
+        let now = Instant::now();
+        let (transport, hello) = match direction {
+            Direction::Outbound if v2 => Transport::outbound_v2(self.magic),
+            Direction::Outbound => (Transport::v1(self.magic), Vec::new()),
+            Direction::Inbound => (Transport::inbound(self.magic), Vec::new()),
+        };
+
+        let mut conn = Conn {
+            id: token.0 as u64,
+            addr,
+            direction,
+            stream,
+            magic: self.magic,
+            transport,
+            phase: Phase::Handshake(Box::new(state)),
+            decoder: FrameDecoder::new(self.magic),
+            inbound: VecDeque::new(),
+            paused: false,
+            limits: PeerRateLimits::new(now),
+            throttled_until: None,
+            send_buf: hello,
+            sent: 0,
+            last_write: now,
+            readable: false,
+            writable: false,
+            closing: false,
+            error: None,
+            done,
+        };
+        if let Phase::Handshake(state) = &conn.phase {
+            let version = state.version_message();
+            conn.queue(&version, now);
+        }
+        self.conns.insert(token, conn);
+    }
+
+    /// Đọc, decode, ghi và kiểm tra timeout cho 1 kết nối
+    fn service(&mut self, conn: &mut Conn, now: Instant) {
+        match &conn.phase {
+            Phase::Handshake(state) if state.timed_out(now) => {
+                conn.close(Some(HandshakeError::Timeout));
+                return;
+            }
+            Phase::Ready { handle, .. } if handle.is_closing() => {
+                conn.close(None);
+                return;
+            }
+            _ => {}
+        }
+
+        if conn.throttled_until.is_some_and(|t| t <= now) {
+            conn.throttled_until = None;
+        }
+
+        self.decode(conn, now);
+        let mut buf = [0u8; READ_CHUNK];
+        while conn.readable
+            && !conn.closing
+            && !conn.paused
+            && conn.throttled_until.is_none()
+            && self.read_paused_until.is_none()
+        {
+            match conn.stream.read(&mut buf) {
+                Ok(0) => conn.fail(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                Ok(n) => {
+                    if let Some(wait) = self.ctx.bandwidth.lock().unwrap().on_receive(n, now) {
+                        self.read_paused_until = Some(now + wait);
+                    }
+                    conn.receive(&buf[..n], now);
+                    self.decode(conn, now);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => conn.readable = false,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => conn.fail(e),
+            }
+        }
+
+        self.flush(conn, now);
+        // đang bị giới hạn upload thì không tính là peer không đọc
+        if self.write_paused_until.is_some() {
+            conn.last_write = now;
+        }
+        if conn.pending_write() > 0 && now.duration_since(conn.last_write) >= WRITE_TIMEOUT {
+            log::debug!("write to {} timed out", conn.addr);
+            conn.close(Some(HandshakeError::Timeout));
+        }
+    }
+
+    /// Tách message từ byte đã nhận tới khi hết hoặc `inbound` đầy
+    fn decode(&mut self, conn: &mut Conn, now: Instant) {
+        conn.paused = conn.inbound.len() >= MAX_PEER_QUEUE;
+        while !conn.closing && !conn.paused && conn.throttled_until.is_none() {
+            let msg = match conn.decoder.next_message() {
+                Ok(Some(m)) => m,
+                Ok(None) => return,
+                // sai magic / quá lớn: stream lệch hoặc khác network, không đọc tiếp được
+                Err(e) if e.is_fatal() => {
+                    log::debug!("disconnecting {}: {}", conn.addr, e);
+                    conn.close(Some(HandshakeError::Frame(e)));
+                    return;
+                }
+                // frame đã được bỏ trọn, stream vẫn đồng bộ
+                Err(e) => {
+                    log::debug!("bad message from {}: {}", conn.addr, e);
+                    let banned = match &conn.phase {
+                        Phase::Ready { handle, .. } => self.ctx.misbehaving(handle, Misbehavior::MalformedMessage),
+                        Phase::Handshake(_) => {
+                            self.ctx.ban.lock().unwrap().misbehaving(conn.addr.ip(), Misbehavior::MalformedMessage)
+                        }
+                    };
+                    if banned {
+                        conn.close(Some(HandshakeError::Frame(e)));
+                    }
+                    continue;
+                }
+            };
+
+            match &mut conn.phase {
+                Phase::Handshake(state) => {
+                    // chữ ký identity gắn với session v2, đã có khi nhận được message
+                    state.bind_session(conn.transport.session_id());
+                    match state.on_message(msg) {
+                        Ok(replies) => {
+                            for reply in &replies {
+                                conn.queue(reply, now);
+                            }
+                        }
+                        Err(e) => {
+                            if e.is_misbehavior() {
+                                self.ctx.ban.lock().unwrap().misbehaving(conn.addr.ip(), Misbehavior::HandshakeViolation);
+                            }
+                            conn.close(Some(e));
+                            return;
+                        }
+                    }
+                    if let Phase::Handshake(state) = &conn.phase {
+                        if let Some(peer) = state.peer().cloned() {
+                            self.ready(conn, peer, now);
+                        }
+                    }
+                }
+                Phase::Ready { handle, .. } => {
+                    handle.on_receive(now);
+                    // node tin cậy không bị giới hạn theo peer
+                    let mut msg = msg;
+                    if !handle.whitelisted {
+                        if let Some(wait) = conn.limits.charge(&mut msg, now) {
+                            conn.throttled_until = Some(now + wait);
+                        }
+                    }
+                    conn.inbound.push_back(msg);
+                    conn.paused = conn.inbound.len() >= MAX_PEER_QUEUE;
+                }
+            }
+        }
+    }
+
+    /// Handshake xong: đăng ký peer và báo worker
+    fn ready(&mut self, conn: &mut Conn, peer: PeerVersion, now: Instant) {
+        let (handle, outbox) = self.ctx.register_peer(conn.id, conn.addr, conn.direction, peer, self.waker.clone());
+        handle.on_receive(now);
+        let _ = self.worker.send(WorkItem::Connected(handle.clone()));
+        conn.phase = Phase::Ready { handle, outbox };
+    }
+
+    /// Lấy message từ outbox vào send buffer rồi ghi tới khi socket đầy
+    fn flush(&mut self, conn: &mut Conn, now: Instant) {
+        while conn.pending_write() < SEND_BUFFER_SIZE {
+            let msg = match &conn.phase {
+                Phase::Ready { outbox, .. } => match outbox.try_recv() {
+                    Ok(m) => m,
+                    Err(_) => break,
+                },
+                Phase::Handshake(_) => break,
+            };
+            conn.queue(&msg, now);
+        }
+
+        while conn.writable && !conn.closing && conn.pending_write() > 0 && self.write_paused_until.is_none() {
+            let allowed = self.ctx.bandwidth.lock().unwrap().upload_allowance(conn.pending_write(), now);
+            if allowed == 0 {
+                let wait = self.ctx.bandwidth.lock().unwrap().upload_wait().unwrap_or(BUSY_TICK);
+                self.write_paused_until = Some(now + wait);
+                break;
+            }
+            match conn.stream.write(&conn.send_buf[conn.sent..conn.sent + allowed]) {
+                Ok(0) => conn.close(None),
+                Ok(n) => {
+                    self.ctx.bandwidth.lock().unwrap().on_send(n, now);
+                    conn.sent += n;
+                    conn.last_write = now;
+                    if let Phase::Ready { handle, .. } = &conn.phase {
+                        handle.stats.lock().unwrap().last_send = Some(now);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => conn.writable = false,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => conn.fail(e),
+            }
+        }
+
+        if conn.sent == conn.send_buf.len() {
+            conn.send_buf.clear();
+            conn.sent = 0;
+        } else if conn.sent >= SEND_BUFFER_SIZE {
+            conn.send_buf.drain(..conn.sent);
+            conn.sent = 0;
+        }
+    }
+
+    /// Chuyển message cho worker, lần lượt mỗi peer 1 message cho công bằng
+    fn forward(&mut self) {
+        let Reactor { conns, worker, queued, .. } = self;
+        loop {
+            let mut moved = false;
+            for conn in conns.values_mut() {
+                if queued.load(Ordering::Relaxed) >= MAX_WORKER_QUEUE {
+                    return;
+                }
+                let id = match &conn.phase {
+                    Phase::Ready { handle, .. } => handle.id,
+                    Phase::Handshake(_) => continue,
+                };
+                if let Some(msg) = conn.inbound.pop_front() {
+                    queued.fetch_add(1, Ordering::Relaxed);
+                    let _ = worker.send(WorkItem::Message(id, msg));
+                    moved = true;
+                }
+            }
+            if !moved {
+                return;
+            }
+        }
+    }
+
+    fn finish(&mut self, mut conn: Conn) {
+        let _ = self.poll.registry().deregister(&mut conn.stream);
+
+        let result = match conn.phase {
+            Phase::Ready { handle, .. } => {
+                self.ctx.peers.unregister(handle.id);
+                let _ = self.worker.send(WorkItem::Disconnected(handle.id));
+                Ok(())
+            }
+            Phase::Handshake(_) => {
+                let e = conn.error.take().unwrap_or(HandshakeError::Timeout);
+                log::debug!("handshake with {} failed: {}", conn.addr, e);
+                Err(e)
+            }
+        };
+        if let Some(done) = conn.done {
+            let _ = done.send(result);
+        }
+    }
+}
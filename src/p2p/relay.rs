@@ -0,0 +1,334 @@
+//! Relay tx / block giữa các peer.
+//!
+//! Mọi peer đã handshake được ghi trong `PeerRegistry`. Mỗi peer có 1
+//! outbox, event loop (p2p::reactor) lấy message từ đó ghi ra socket, nên
+//! thread nào cũng gửi được cho peer mà không cần đụng tới socket. Block
+//! mới được báo (Inv) ngay khi thành tip; tx được gom lại và gửi theo chu
+//! kỳ ngẫu nhiên cho từng peer (trickle) để khó đoán tx xuất phát từ node
+//! nào. Peer hỗ trợ compact block nhận luôn compact block thay cho Inv của
+//! block. Inventory peer đã biết (nó gửi cho ta hoặc ta đã báo cho nó)
+//! không bị báo lại.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::chain::block::Block;
+use crate::chain::tx::Transaction;
+use crate::chain::txid::txid;
+use crate::events::{ChainListener, EventBus};
+use crate::p2p::compact::CompactBlock;
+use crate::p2p::handshake::{PeerVersion, COMPACT_BLOCKS_VERSION};
+use crate::p2p::keepalive::PeerStats;
+use crate::p2p::message::{InvItem, InvKind, Message};
+use crate::p2p::peer::Direction;
+
+/// Số message chờ ghi tối đa cho 1 peer; đầy nghĩa là peer không đọc kịp
+pub const OUTBOX_CAPACITY: usize = 1024;
+/// Số inventory nhớ cho mỗi peer
+pub const MAX_KNOWN_INVENTORY: usize = 50_000;
+/// Số item tối đa trong 1 message Inv / GetData / NotFound
+pub const MAX_INV_SIZE: usize = 50_000;
+/// Số tx tối đa trong 1 lần trickle
+pub const MAX_TX_ANNOUNCE: usize = 1000;
+/// Chu kỳ trickle trung bình: inbound chậm hơn để spy khó đo
+pub const INBOUND_TRICKLE: Duration = Duration::from_secs(5);
+pub const OUTBOUND_TRICKLE: Duration = Duration::from_secs(2);
+
+/// Tập inventory giới hạn, bỏ phần cũ nhất khi đầy
+#[derive(Default)]
+pub struct KnownInventory {
+    set: HashSet<InvItem>,
+    order: VecDeque<InvItem>,
+}
+
+impl KnownInventory {
+    /// false nếu đã có
+    pub fn insert(&mut self, item: InvItem) -> bool {
+        if !self.set.insert(item) {
+            return false;
+        }
+        self.order.push_back(item);
+        if self.order.len() > MAX_KNOWN_INVENTORY {
+            let old = self.order.pop_front().unwrap();
+            self.set.remove(&old);
+        }
+        true
+    }
+
+    pub fn contains(&self, item: &InvItem) -> bool {
+        self.set.contains(item)
+    }
+}
+
+struct TrickleState {
+    /// txid chờ báo cho peer
+    pending: Vec<[u8; 32]>,
+    next_flush: Instant,
+}
+
+/// Báo transport có việc mới cho 1 peer (event loop: mio::Waker)
+pub trait Wake: Send + Sync {
+    fn wake(&self);
+}
+
+impl Wake for mio::Waker {
+    fn wake(&self) {
+        let _ = mio::Waker::wake(self);
+    }
+}
+
+/// Peer đã handshake, dùng chung giữa event loop, worker và relay
+pub struct PeerHandle {
+    pub id: u64,
+    pub addr: SocketAddr,
+    pub direction: Direction,
+    /// Version peer gửi lúc handshake
+    pub remote: PeerVersion,
+    /// Identity của peer nằm trong danh sách node tin cậy
+    pub whitelisted: bool,
+    pub(crate) stats: Arc<Mutex<PeerStats>>,
+    outbox: SyncSender<Message>,
+    /// Báo event loop có message mới / cần ngắt kết nối
+    waker: Arc<dyn Wake>,
+    closing: AtomicBool,
+    known: Mutex<KnownInventory>,
+    trickle: Mutex<TrickleState>,
+}
+
+impl PeerHandle {
+    /// Gửi message trả lời peer; false nếu peer đã ngắt hoặc outbox đầy
+    /// (người gọi nên ngắt kết nối)
+    pub fn send(&self, msg: Message) -> bool {
+        let ok = self.try_send(msg);
+        if !ok {
+            log::debug!("cannot queue message for peer {}", self.addr);
+        }
+        ok
+    }
+
+    /// Gửi không bắt buộc (relay); bỏ message nếu outbox đầy
+    pub fn try_send(&self, msg: Message) -> bool {
+        match self.outbox.try_send(msg) {
+            Ok(()) => {
+                self.waker.wake();
+                true
+            }
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    /// Yêu cầu event loop đóng kết nối
+    pub fn disconnect(&self) {
+        self.closing.store(true, Ordering::Relaxed);
+        self.waker.wake();
+    }
+
+    pub fn is_closing(&self) -> bool {
+        self.closing.load(Ordering::Relaxed)
+    }
+
+    pub fn on_receive(&self, now: Instant) {
+        self.stats.lock().unwrap().last_recv = Some(now);
+    }
+
+    /// Đánh dấu peer đã biết item; false nếu đã biết từ trước
+    pub fn mark_known(&self, item: InvItem) -> bool {
+        self.known.lock().unwrap().insert(item)
+    }
+
+    pub fn knows(&self, item: &InvItem) -> bool {
+        self.known.lock().unwrap().contains(item)
+    }
+
+    /// Node tin cậy nhận tx ngay, không trickle
+    fn trickle_interval(&self) -> Duration {
+        match self.direction {
+            _ if self.whitelisted => Duration::ZERO,
+            Direction::Inbound => INBOUND_TRICKLE,
+            Direction::Outbound => OUTBOUND_TRICKLE,
+        }
+    }
+}
+
+/// Thời gian chờ phân phối mũ với trung bình `mean`
+fn poisson_delay(mean: Duration) -> Duration {
+    let u: f64 = rand::thread_rng().gen_range(f64::EPSILON..1.0);
+    mean.mul_f64(-u.ln())
+}
+
+pub struct PeerRegistry {
+    peers: Mutex<HashMap<u64, Arc<PeerHandle>>>,
+    /// Key ngẫu nhiên để xếp network group khi chọn peer bị đuổi
+    pub(crate) group_key: (u64, u64),
+}
+
+impl Default for PeerRegistry {
+    fn default() -> Self {
+        Self { peers: Mutex::default(), group_key: rand::random() }
+    }
+}
+
+impl PeerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Đăng ký peer vừa handshake xong; trả về outbox để event loop ghi ra socket
+    pub fn register(
+        &self,
+        id: u64,
+        addr: SocketAddr,
+        direction: Direction,
+        remote: PeerVersion,
+        whitelisted: bool,
+        waker: Arc<dyn Wake>,
+    ) -> (Arc<PeerHandle>, Receiver<Message>) {
+        let stats = Arc::new(Mutex::new(PeerStats::new(Instant::now())));
+        let (outbox, rx) = sync_channel(OUTBOX_CAPACITY);
+
+        let handle = Arc::new(PeerHandle {
+            id,
+            addr,
+            direction,
+            remote,
+            whitelisted,
+            stats,
+            outbox,
+            waker,
+            closing: AtomicBool::new(false),
+            known: Mutex::new(KnownInventory::default()),
+            trickle: Mutex::new(TrickleState { pending: Vec::new(), next_flush: Instant::now() }),
+        });
+        let first_flush = Instant::now() + poisson_delay(handle.trickle_interval());
+        handle.trickle.lock().unwrap().next_flush = first_flush;
+
+        self.peers.lock().unwrap().insert(id, handle.clone());
+        (handle, rx)
+    }
+
+    pub fn unregister(&self, id: u64) {
+        self.peers.lock().unwrap().remove(&id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Theo thứ tự id (thứ tự kết nối)
+    pub fn list(&self) -> Vec<Arc<PeerHandle>> {
+        let mut peers: Vec<_> = self.peers.lock().unwrap().values().cloned().collect();
+        peers.sort_by_key(|p| p.id);
+        peers
+    }
+
+    /// Báo block mới ngay cho mọi peer chưa biết, node tin cậy trước;
+    /// có body thì gửi compact block
+    pub fn announce_block(&self, hash: [u8; 32], block: Option<&Block>) {
+        let item = InvItem { kind: InvKind::Block, hash };
+        let compact = block.map(|b| CompactBlock::from_block(b, rand::random()));
+
+        let mut peers = self.list();
+        peers.sort_by_key(|p| !p.whitelisted);
+        for peer in peers {
+            if !peer.mark_known(item) {
+                continue;
+            }
+            match &compact {
+                Some(c) if peer.remote.protocol_version >= COMPACT_BLOCKS_VERSION => {
+                    peer.try_send(Message::CompactBlock { block: c.clone() })
+                }
+                _ => peer.try_send(Message::Inv { items: vec![item] }),
+            };
+        }
+    }
+
+    /// Xếp tx vào hàng chờ trickle của mọi peer chưa biết
+    pub fn queue_tx(&self, txid: [u8; 32]) {
+        let item = InvItem { kind: InvKind::Tx, hash: txid };
+        for peer in self.list() {
+            if !peer.knows(&item) {
+                peer.trickle.lock().unwrap().pending.push(txid);
+            }
+        }
+    }
+
+    /// Gửi tx đang chờ cho các peer đến lượt
+    pub fn flush_trickle(&self, now: Instant) {
+        let mut rng = rand::thread_rng();
+
+        for peer in self.list() {
+            let mut pending = {
+                let mut t = peer.trickle.lock().unwrap();
+                if t.next_flush > now {
+                    continue;
+                }
+                t.next_flush = now + poisson_delay(peer.trickle_interval());
+                std::mem::take(&mut t.pending)
+            };
+
+            // thứ tự ngẫu nhiên, không lộ thứ tự nhận
+            pending.shuffle(&mut rng);
+            let items: Vec<InvItem> = pending
+                .into_iter()
+                .map(|hash| InvItem { kind: InvKind::Tx, hash })
+                .filter(|item| peer.mark_known(*item))
+                .collect();
+
+            for chunk in items.chunks(MAX_TX_ANNOUNCE) {
+                peer.try_send(Message::Inv { items: chunk.to_vec() });
+            }
+        }
+    }
+}
+
+/// Nghe chain / mempool event để relay
+struct RelayListener {
+    peers: Arc<PeerRegistry>,
+    /// Block connect gần nhất, để gửi compact block khi nó thành tip
+    last_connected: Option<([u8; 32], Block)>,
+}
+
+impl ChainListener for RelayListener {
+    fn on_tip_changed(&mut self, tip: &[u8; 32], _height: u64) {
+        let block = match self.last_connected.take() {
+            Some((hash, block)) if hash == *tip => Some(block),
+            _ => None,
+        };
+        self.peers.announce_block(*tip, block.as_ref());
+    }
+
+    fn on_tx_accepted(&mut self, tx: &Transaction, _fee: u64) {
+        self.peers.queue_tx(txid(tx));
+    }
+
+    fn on_block_connected(&mut self, block: &Block, hash: &[u8; 32], _height: u64) {
+        self.last_connected = Some((*hash, block.clone()));
+    }
+}
+
+/// Relay block / tx mới theo chain event; trickle do người gọi tự flush
+pub fn subscribe_relay(peers: Arc<PeerRegistry>, events: &EventBus) {
+    events.subscribe(RelayListener { peers, last_connected: None }, 10_000);
+}
+
+/// Bắt đầu relay: đăng ký listener và thread trickle
+pub fn start_relay(peers: Arc<PeerRegistry>, events: &EventBus) {
+    subscribe_relay(peers.clone(), events);
+
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_millis(200));
+        peers.flush_trickle(Instant::now());
+    });
+}
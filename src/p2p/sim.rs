@@ -0,0 +1,379 @@
+//! Mạng giả lập trong bộ nhớ cho test nhiều node.
+//!
+//! Mỗi node có PeerContext và validation worker thật, chain nằm trong
+//! `ChainDB::open_temporary` (chỉ trong bộ nhớ). Thay cho socket, mỗi link
+//! giữ 2 đầu như kết nối của reactor: đang handshake thì message đi qua
+//! HandshakeState, xong thì qua outbox của peer. `run` lần lượt lấy message
+//! từ từng đầu, encode / decode qua frame như TCP rồi giao sang đầu kia,
+//! theo thứ tự cố định (link theo thứ tự tạo, mỗi lượt 1 message mỗi chiều)
+//! và chờ relay xử lý xong event trước khi giao message tiếp. Trickle chạy
+//! theo đồng hồ ảo nên kết quả không phụ thuộc thời gian thật.
+
+use std::collections::VecDeque;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::chain::block::{merkle_root, Block};
+use crate::chain::hash::hash_header;
+use crate::chain::header::BlockHeader;
+use crate::chain::params::{ChainParams, Network};
+use crate::chain::reward::BLOCK_REWARD;
+use crate::chain::state::ChainState;
+use crate::chain::tx::Transaction;
+use crate::mempool::subscribe_mempool;
+use crate::p2p::addrman::AddrMan;
+use crate::p2p::frame::{encode_frame, FrameDecoder};
+use crate::p2p::handshake::{HandshakeState, PeerVersion};
+use crate::p2p::message::{Message, NODE_NETWORK};
+use crate::p2p::peer::{Direction, PeerContext, WorkItem, Worker};
+use crate::p2p::relay::{subscribe_relay, PeerHandle, Wake};
+use crate::pow::verify::verify_pow;
+use crate::storage::sleddb::ChainDB;
+
+/// Mỗi lần flush trickle, đồng hồ ảo tiến chừng này (lớn hơn hẳn chu kỳ trickle)
+const TRICKLE_STEP: Duration = Duration::from_secs(60 * 60);
+/// `run` dừng sau chừng này lượt dù mạng chưa yên
+const MAX_ROUNDS: usize = 100_000;
+
+/// Không có event loop để đánh thức
+struct NoWake;
+
+impl Wake for NoWake {
+    fn wake(&self) {}
+}
+
+pub struct SimNode {
+    pub ctx: PeerContext,
+    worker: Worker,
+    pub addr: SocketAddr,
+}
+
+enum Phase {
+    Handshake(Box<HandshakeState>),
+    /// Handle trong registry của node, outbox là message node gửi đi
+    Ready {
+        handle: Arc<PeerHandle>,
+        outbox: Receiver<Message>,
+    },
+}
+
+/// 1 đầu của link
+struct End {
+    node: usize,
+    id: u64,
+    direction: Direction,
+    phase: Phase,
+    /// Reply của handshake, gửi trước message trong outbox
+    sendq: VecDeque<Message>,
+    decoder: FrameDecoder,
+}
+
+impl End {
+    fn handle(&self) -> Option<&Arc<PeerHandle>> {
+        match &self.phase {
+            Phase::Ready { handle, .. } => Some(handle),
+            Phase::Handshake(_) => None,
+        }
+    }
+
+    fn next_outgoing(&mut self) -> Option<Message> {
+        if let Some(msg) = self.sendq.pop_front() {
+            return Some(msg);
+        }
+        match &self.phase {
+            Phase::Ready { outbox, .. } => outbox.try_recv().ok(),
+            Phase::Handshake(_) => None,
+        }
+    }
+}
+
+struct Link {
+    ends: [End; 2],
+    /// Handshake lỗi hoặc frame không decode được trước khi xong handshake
+    failed: bool,
+}
+
+pub struct SimNetwork {
+    nodes: Vec<SimNode>,
+    links: Vec<Link>,
+    /// Link bị cắt bởi `partition`, nối lại khi `heal`
+    cut: Vec<(usize, usize)>,
+    magic: [u8; 4],
+    clock: Instant,
+}
+
+impl SimNetwork {
+    /// `n` node regtest, chưa kết nối với nhau
+    pub fn new(n: usize) -> Self {
+        let nodes = (0..n).map(Self::make_node).collect();
+        Self {
+            nodes,
+            links: Vec::new(),
+            cut: Vec::new(),
+            magic: ChainParams::new(Network::Regtest).magic,
+            clock: Instant::now(),
+        }
+    }
+
+    fn make_node(i: usize) -> SimNode {
+        let chain = ChainState::load_or_init(ChainParams::new(Network::Regtest), ChainDB::open_temporary());
+        let node_id = [i as u8 + 1; 32];
+        let ctx = PeerContext::new(chain, AddrMan::new(), node_id, NODE_NETWORK);
+        let events = ctx.chain.lock().unwrap().events.clone();
+        subscribe_relay(ctx.peers.clone(), &events);
+        subscribe_mempool(ctx.chain.clone(), ctx.mempool.clone(), &events);
+
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, i as u8 / 250, i as u8 % 250 + 1)), 8333);
+        SimNode { worker: Worker::new(ctx.clone()), ctx, addr }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn node(&self, i: usize) -> &SimNode {
+        &self.nodes[i]
+    }
+
+    pub fn tip(&self, i: usize) -> ([u8; 32], u64) {
+        let chain = self.nodes[i].ctx.chain.lock().unwrap();
+        (chain.tip, chain.tip_height())
+    }
+
+    /// Mọi node cùng tip
+    pub fn converged(&self) -> bool {
+        let first = self.tip(0);
+        (1..self.nodes.len()).all(|i| self.tip(i) == first)
+    }
+
+    fn is_linked(&self, a: usize, b: usize) -> bool {
+        self.links.iter().any(|l| {
+            let (x, y) = (l.ends[0].node, l.ends[1].node);
+            (x, y) == (a, b) || (x, y) == (b, a)
+        })
+    }
+
+    /// Có link giữa `a` và `b` đã xong handshake ở cả 2 đầu
+    pub fn is_connected(&self, a: usize, b: usize) -> bool {
+        self.links.iter().any(|l| {
+            let (x, y) = (l.ends[0].node, l.ends[1].node);
+            ((x, y) == (a, b) || (x, y) == (b, a)) && l.ends.iter().all(|e| e.handle().is_some())
+        })
+    }
+
+    /// `a` mở kết nối outbound tới `b`. Mỗi bên gửi version ngay; handshake
+    /// chạy qua frame khi `run` giao message, lỗi thì link bị bỏ
+    pub fn connect(&mut self, a: usize, b: usize) {
+        let ends = [self.open_end(a, Direction::Outbound), self.open_end(b, Direction::Inbound)];
+        self.links.push(Link { ends, failed: false });
+    }
+
+    fn open_end(&self, node: usize, direction: Direction) -> End {
+        let ctx = &self.nodes[node].ctx;
+        let id = ctx.next_peer_id.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let state = HandshakeState::new(ctx.local_version());
+        let version = state.version_message();
+        End {
+            node,
+            id,
+            direction,
+            phase: Phase::Handshake(Box::new(state)),
+            sendq: VecDeque::from([version]),
+            decoder: FrameDecoder::new(self.magic),
+        }
+    }
+
+    /// Handshake của `end` xong: đăng ký peer và báo worker
+    fn ready(node: &mut SimNode, end: &mut End, remote: SocketAddr, peer: PeerVersion) {
+        let (handle, outbox) = node.ctx.register_peer(end.id, remote, end.direction, peer, Arc::new(NoWake));
+        handle.on_receive(Instant::now());
+        node.worker.handle(WorkItem::Connected(handle.clone()));
+        end.phase = Phase::Ready { handle, outbox };
+    }
+
+    /// Ngắt link giữa `a` và `b` (nếu có)
+    pub fn disconnect(&mut self, a: usize, b: usize) {
+        let pos = self.links.iter().position(|l| {
+            let (x, y) = (l.ends[0].node, l.ends[1].node);
+            (x, y) == (a, b) || (x, y) == (b, a)
+        });
+        if let Some(pos) = pos {
+            let link = self.links.remove(pos);
+            self.close(link);
+        }
+    }
+
+    fn close(&mut self, link: Link) {
+        for end in &link.ends {
+            if let Some(handle) = end.handle() {
+                let node = &mut self.nodes[end.node];
+                node.ctx.peers.unregister(handle.id);
+                node.worker.handle(WorkItem::Disconnected(handle.id));
+            }
+        }
+    }
+
+    /// Cắt mọi link giữa các nhóm khác nhau; node không thuộc nhóm nào giữ nguyên
+    pub fn partition(&mut self, groups: &[&[usize]]) {
+        let group_of = |n: usize| groups.iter().position(|g| g.contains(&n));
+        let crossing: Vec<(usize, usize)> = self
+            .links
+            .iter()
+            .map(|l| (l.ends[0].node, l.ends[1].node))
+            .filter(|&(a, b)| matches!((group_of(a), group_of(b)), (Some(x), Some(y)) if x != y))
+            .collect();
+        for &(a, b) in &crossing {
+            self.disconnect(a, b);
+        }
+        self.cut.extend(crossing);
+    }
+
+    /// Nối lại các link bị `partition` cắt
+    pub fn heal(&mut self) {
+        for (a, b) in std::mem::take(&mut self.cut) {
+            if !self.is_linked(a, b) {
+                self.connect(a, b);
+            }
+        }
+    }
+
+    /// Mine 1 block trên tip của node `i` (timestamp = parent + 1); trả hash
+    pub fn mine(&mut self, i: usize) -> [u8; 32] {
+        let ctx = &self.nodes[i].ctx;
+        let block = {
+            let chain = ctx.chain.lock().unwrap();
+            let parent = chain.tip_entry();
+            let height = chain.tip_height() + 1;
+            let coinbase = Transaction::coinbase(
+                format!("sim-{}", i).into_bytes(),
+                BLOCK_REWARD,
+                &format!("sim {} height {}", i, height),
+            );
+            let txs = vec![coinbase];
+            let mut header = BlockHeader {
+                version: 1,
+                prev_hash: chain.tip,
+                merkle_root: merkle_root(&txs),
+                timestamp: parent.header.timestamp + 1,
+                bits: parent.header.bits,
+                nonce: 0,
+            };
+            while !verify_pow(&header) {
+                header.nonce += 1;
+            }
+            Block { header, transactions: txs }
+        };
+        let hash = hash_header(&block.header);
+        let events = {
+            let mut chain = ctx.chain.lock().unwrap();
+            assert!(chain.add_block(block), "mined block rejected");
+            chain.events.clone()
+        };
+        events.sync();
+        hash
+    }
+
+    /// Giao message tới khi mạng yên; trả số message đã giao
+    pub fn run(&mut self) -> usize {
+        let mut delivered = 0;
+        for _ in 0..MAX_ROUNDS {
+            self.clock += TRICKLE_STEP;
+            for node in &self.nodes {
+                node.ctx.peers.flush_trickle(self.clock);
+            }
+
+            let mut progress = false;
+            for l in 0..self.links.len() {
+                for side in 0..2 {
+                    if self.deliver(l, side) {
+                        progress = true;
+                        delivered += 1;
+                    }
+                }
+            }
+            self.drop_closed();
+
+            if !progress {
+                return delivered;
+            }
+        }
+        log::warn!("simulated network still busy after {} rounds", MAX_ROUNDS);
+        delivered
+    }
+
+    /// Giao 1 message từ đầu `side` của link `l` sang đầu kia
+    fn deliver(&mut self, l: usize, side: usize) -> bool {
+        let link = &mut self.links[l];
+        if link.failed {
+            return false;
+        }
+        let msg = match link.ends[side].next_outgoing() {
+            Some(m) => m,
+            None => return false,
+        };
+        let from = link.ends[side].node;
+        let from_addr = self.nodes[from].addr;
+
+        let to = &mut link.ends[1 - side];
+        to.decoder.push(&encode_frame(&self.magic, &msg));
+        let msg = match to.decoder.next_message() {
+            Ok(Some(m)) => m,
+            Ok(None) => unreachable!("whole frame pushed"),
+            Err(e) => {
+                log::warn!("sim: cannot decode {}: {}", msg.command(), e);
+                match to.handle() {
+                    Some(handle) => handle.disconnect(),
+                    None => link.failed = true,
+                }
+                return true;
+            }
+        };
+
+        let node = &mut self.nodes[to.node];
+        let id = match &mut to.phase {
+            Phase::Handshake(state) => {
+                match state.on_message(msg) {
+                    Ok(replies) => to.sendq.extend(replies),
+                    Err(e) => {
+                        log::info!("sim: handshake {} -> {} failed: {}", from, to.node, e);
+                        link.failed = true;
+                        return true;
+                    }
+                }
+                if let Some(peer) = state.peer().cloned() {
+                    Self::ready(node, to, from_addr, peer);
+                }
+                return true;
+            }
+            Phase::Ready { handle, .. } => {
+                handle.on_receive(Instant::now());
+                handle.id
+            }
+        };
+
+        node.worker.handle(WorkItem::Message(id, msg));
+        // relay chạy trên thread của event bus: chờ nó xếp xong message.
+        // Không giữ lock chain khi chờ: listener của mempool cũng cần nó
+        let events = node.ctx.chain.lock().unwrap().events.clone();
+        events.sync();
+        true
+    }
+
+    fn drop_closed(&mut self) {
+        let (closed, open) = std::mem::take(&mut self.links)
+            .into_iter()
+            .partition(|l| l.failed || l.ends.iter().any(|e| e.handle().is_some_and(|h| h.is_closing())));
+        self.links = open;
+        for link in closed {
+            let (a, b) = (link.ends[0].node, link.ends[1].node);
+            log::info!("sim: link {} <-> {} closed", a, b);
+            self.close(link);
+        }
+    }
+}
@@ -0,0 +1,586 @@
+//! Transport v2: mã hoá kết nối P2P.
+//!
+//! ```text
+//! mỗi bên   key      64  secp256k1 pubkey mã hoá ElligatorSwift
+//!           garbage  0..MAX_GARBAGE byte ngẫu nhiên
+//!           (nhận đủ key của peer, tính được key phiên)
+//!           terminator 16  do key phiên quyết định
+//!           packet*
+//! packet    length    4  độ dài nội dung, XOR keystream ChaCha20 (length key)
+//!           content      1 frame v1, ChaCha20-Poly1305 (packet key)
+//!           tag      16
+//! ```
+//!
+//! Key phiên lấy từ ECDH (HKDF-SHA256, salt có magic của network), mỗi
+//! chiều 1 cặp key riêng, đổi key sau mỗi REKEY_INTERVAL packet. Packet
+//! đầu tiên mỗi chiều xác thực luôn garbage đã gửi (AAD). Mọi byte trên
+//! dây trông như ngẫu nhiên, kể cả lúc trao đổi key.
+//!
+//! Bên nhận kết nối nhìn 4 byte đầu: trùng magic là peer v1 (frame không
+//! mã hoá), khác thì là key v2. Bên kết nối tới peer cũ nhận lại version
+//! v1 hoặc bị ngắt ngay sau khi gửi key (`TransportError::Rejected`) thì
+//! kết nối lại bằng v1.
+
+use std::fmt;
+
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::ChaCha20;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
+use hkdf::Hkdf;
+use rand::Rng;
+use secp256k1::ellswift::{ElligatorSwift, ElligatorSwiftParty};
+use secp256k1::{Secp256k1, SecretKey};
+use sha2::Sha256;
+
+use crate::p2p::frame::{HEADER_SIZE, MAX_PAYLOAD_SIZE};
+
+pub const ELLSWIFT_SIZE: usize = 64;
+/// Garbage tối đa sau key
+pub const MAX_GARBAGE: usize = 4095;
+pub const TERMINATOR_SIZE: usize = 16;
+const LENGTH_SIZE: usize = 4;
+const TAG_SIZE: usize = 16;
+/// Đổi key sau chừng này packet
+pub const REKEY_INTERVAL: u64 = 224;
+/// Nội dung packet lớn nhất: 1 frame lớn nhất
+const MAX_CONTENT: usize = HEADER_SIZE + MAX_PAYLOAD_SIZE as usize;
+
+#[derive(Debug)]
+pub enum TransportError {
+    /// Peer ngắt trước khi gửi key: có thể chỉ hiểu v1
+    Rejected,
+    /// Không thấy garbage terminator trong MAX_GARBAGE byte
+    NoTerminator,
+    Oversized(usize),
+    /// Tag sai: dữ liệu bị sửa hoặc key không khớp
+    Decrypt,
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Rejected => write!(f, "peer does not support v2 transport"),
+            TransportError::NoTerminator => write!(f, "garbage terminator not found"),
+            TransportError::Oversized(n) => write!(f, "packet of {} bytes too large", n),
+            TransportError::Decrypt => write!(f, "packet authentication failed"),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+/// Key của 1 chiều
+struct PacketCipher {
+    length_key: [u8; 32],
+    key: [u8; 32],
+    /// Số packet đã xử lý
+    counter: u64,
+}
+
+impl PacketCipher {
+    fn new(length_key: [u8; 32], key: [u8; 32]) -> Self {
+        Self { length_key, key, counter: 0 }
+    }
+
+    /// 4 byte thứ tự packet trong epoch + 8 byte epoch
+    fn nonce(&self) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[..4].copy_from_slice(&((self.counter % REKEY_INTERVAL) as u32).to_le_bytes());
+        nonce[4..].copy_from_slice(&(self.counter / REKEY_INTERVAL).to_le_bytes());
+        nonce
+    }
+
+    fn length_mask(&self) -> [u8; LENGTH_SIZE] {
+        let mut mask = [0u8; LENGTH_SIZE];
+        ChaCha20::new(&self.length_key.into(), &self.nonce().into()).apply_keystream(&mut mask);
+        mask
+    }
+
+    fn encrypt(&mut self, content: &[u8], aad: &[u8], out: &mut Vec<u8>) {
+        let mut length = (content.len() as u32).to_le_bytes();
+        for (b, m) in length.iter_mut().zip(self.length_mask()) {
+            *b ^= m;
+        }
+        out.extend_from_slice(&length);
+
+        let aead = ChaCha20Poly1305::new(&self.key.into());
+        let sealed = aead.encrypt(&self.nonce().into(), Payload { msg: content, aad }).unwrap();
+        out.extend_from_slice(&sealed);
+        self.advance();
+    }
+
+    fn decrypt_length(&self, bytes: &[u8]) -> usize {
+        let mut length: [u8; LENGTH_SIZE] = bytes[..LENGTH_SIZE].try_into().unwrap();
+        for (b, m) in length.iter_mut().zip(self.length_mask()) {
+            *b ^= m;
+        }
+        u32::from_le_bytes(length) as usize
+    }
+
+    /// `sealed` = content + tag
+    fn decrypt(&mut self, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, TransportError> {
+        let aead = ChaCha20Poly1305::new(&self.key.into());
+        let content = aead
+            .decrypt(&self.nonce().into(), Payload { msg: sealed, aad })
+            .map_err(|_| TransportError::Decrypt)?;
+        self.advance();
+        Ok(content)
+    }
+
+    fn advance(&mut self) {
+        self.counter += 1;
+        if !self.counter.is_multiple_of(REKEY_INTERVAL) {
+            return;
+        }
+        // key mới = keystream của key cũ với nonce không bao giờ dùng cho packet
+        let mut nonce = [0xffu8; 12];
+        nonce[4..].copy_from_slice(&(self.counter / REKEY_INTERVAL).to_le_bytes());
+        for key in [&mut self.length_key, &mut self.key] {
+            let mut next = [0u8; 32];
+            ChaCha20::new(&(*key).into(), &nonce.into()).apply_keystream(&mut next);
+            *key = next;
+        }
+    }
+}
+
+/// Key phiên sau ECDH
+struct Session {
+    send: PacketCipher,
+    recv: PacketCipher,
+    id: [u8; 32],
+    recv_terminator: [u8; TERMINATOR_SIZE],
+    /// AAD cho packet đầu tiên mỗi chiều (garbage), rỗng sau đó
+    send_aad: Vec<u8>,
+    recv_aad: Vec<u8>,
+}
+
+impl Session {
+    fn derive(shared: &[u8; 32], magic: &[u8; 4], initiator: bool, garbage: Vec<u8>) -> (Self, [u8; TERMINATOR_SIZE]) {
+        let mut salt = b"egg_v2_shared_secret".to_vec();
+        salt.extend_from_slice(magic);
+        let hk = Hkdf::<Sha256>::new(Some(&salt), shared);
+        let expand = |info: &str| {
+            let mut okm = [0u8; 32];
+            hk.expand(info.as_bytes(), &mut okm).unwrap();
+            okm
+        };
+
+        let initiator_cipher = PacketCipher::new(expand("initiator_L"), expand("initiator_P"));
+        let responder_cipher = PacketCipher::new(expand("responder_L"), expand("responder_P"));
+        let terminators = expand("garbage_terminators");
+        let initiator_term: [u8; TERMINATOR_SIZE] = terminators[..TERMINATOR_SIZE].try_into().unwrap();
+        let responder_term: [u8; TERMINATOR_SIZE] = terminators[TERMINATOR_SIZE..].try_into().unwrap();
+
+        let (send, recv, send_term, recv_term) = if initiator {
+            (initiator_cipher, responder_cipher, initiator_term, responder_term)
+        } else {
+            (responder_cipher, initiator_cipher, responder_term, initiator_term)
+        };
+        let session = Session {
+            send,
+            recv,
+            id: expand("session_id"),
+            recv_terminator: recv_term,
+            send_aad: garbage,
+            recv_aad: Vec::new(),
+        };
+        (session, send_term)
+    }
+
+    fn encrypt(&mut self, content: &[u8], out: &mut Vec<u8>) {
+        let aad = std::mem::take(&mut self.send_aad);
+        self.send.encrypt(content, &aad, out);
+    }
+}
+
+/// Trạng thái v2 từ lúc gửi key tới hết kết nối
+struct V2 {
+    initiator: bool,
+    secret: SecretKey,
+    ours: ElligatorSwift,
+    /// Garbage đã gửi, chờ làm AAD của packet đầu
+    garbage: Vec<u8>,
+    /// Byte nhận chưa xử lý
+    buf: Vec<u8>,
+    /// Có sau khi nhận key của peer
+    session: Option<Session>,
+    /// Đã qua garbage terminator của peer
+    synced: bool,
+}
+
+impl V2 {
+    /// Key mới; bên kết nối tránh key bắt đầu bằng magic (bị nhầm là v1)
+    fn new(initiator: bool, magic: &[u8; 4]) -> Self {
+        let secp = Secp256k1::new();
+        let mut rng = rand::thread_rng();
+        loop {
+            let secret = match SecretKey::from_slice(&rng.gen::<[u8; 32]>()) {
+                Ok(k) => k,
+                Err(_) => continue,
+            };
+            let ours = ElligatorSwift::from_seckey(&secp, secret, Some(rng.gen()));
+            if initiator && ours.to_array()[..4] == magic[..] {
+                continue;
+            }
+            let garbage_len = rng.gen_range(0..=MAX_GARBAGE);
+            let garbage = (0..garbage_len).map(|_| rng.gen()).collect();
+            return Self { initiator, secret, ours, garbage, buf: Vec::new(), session: None, synced: false };
+        }
+    }
+
+    /// Key + garbage gửi đầu tiên
+    fn hello(&self) -> Vec<u8> {
+        let mut out = self.ours.to_array().to_vec();
+        out.extend_from_slice(&self.garbage);
+        out
+    }
+
+    fn receive(
+        &mut self,
+        magic: &[u8; 4],
+        held: &mut Vec<Vec<u8>>,
+        plain: &mut Vec<u8>,
+        send: &mut Vec<u8>,
+    ) -> Result<(), TransportError> {
+        if self.session.is_none() {
+            // peer v1 trả version (bắt đầu bằng magic) thay cho key
+            if self.initiator && self.buf.len() >= magic.len() && self.buf[..magic.len()] == magic[..] {
+                return Err(TransportError::Rejected);
+            }
+            if self.buf.len() < ELLSWIFT_SIZE {
+                return Ok(());
+            }
+            let theirs = ElligatorSwift::from_array(self.buf[..ELLSWIFT_SIZE].try_into().unwrap());
+            let (a, b, party) = if self.initiator {
+                (self.ours, theirs, ElligatorSwiftParty::A)
+            } else {
+                (theirs, self.ours, ElligatorSwiftParty::B)
+            };
+            let shared = ElligatorSwift::shared_secret(a, b, self.secret, party, None);
+            let garbage = std::mem::take(&mut self.garbage);
+            let (mut session, terminator) = Session::derive(shared.as_secret_bytes(), magic, self.initiator, garbage);
+
+            send.extend_from_slice(&terminator);
+            for frame in held.drain(..) {
+                session.encrypt(&frame, send);
+            }
+            self.session = Some(session);
+            self.buf.drain(..ELLSWIFT_SIZE);
+        }
+        let session = self.session.as_mut().unwrap();
+
+        if !self.synced {
+            let window = &self.buf[..self.buf.len().min(MAX_GARBAGE + TERMINATOR_SIZE)];
+            let found = window.windows(TERMINATOR_SIZE).position(|w| w == session.recv_terminator);
+            match found {
+                Some(pos) => {
+                    session.recv_aad = self.buf[..pos].to_vec();
+                    self.buf.drain(..pos + TERMINATOR_SIZE);
+                    self.synced = true;
+                }
+                None if self.buf.len() >= MAX_GARBAGE + TERMINATOR_SIZE => {
+                    return Err(TransportError::NoTerminator);
+                }
+                None => return Ok(()),
+            }
+        }
+
+        let mut start = 0;
+        while self.buf.len() - start >= LENGTH_SIZE {
+            let len = session.recv.decrypt_length(&self.buf[start..]);
+            if len > MAX_CONTENT {
+                return Err(TransportError::Oversized(len));
+            }
+            let end = start + LENGTH_SIZE + len + TAG_SIZE;
+            if self.buf.len() < end {
+                break;
+            }
+            let aad = std::mem::take(&mut session.recv_aad);
+            plain.extend_from_slice(&session.recv.decrypt(&self.buf[start + LENGTH_SIZE..end], &aad)?);
+            start = end;
+        }
+        self.buf.drain(..start);
+        Ok(())
+    }
+}
+
+enum State {
+    /// Inbound: chờ 4 byte đầu để biết peer dùng v1 hay v2
+    Detect(Vec<u8>),
+    V1,
+    V2(Box<V2>),
+}
+
+/// Lớp giữa socket và frame: nhận byte từ socket ra byte frame v1, và ngược lại
+pub struct Transport {
+    magic: [u8; 4],
+    state: State,
+    /// Frame chờ key phiên (hoặc chờ biết peer dùng v1 / v2)
+    held: Vec<Vec<u8>>,
+}
+
+impl Transport {
+    /// Frame không mã hoá
+    pub fn v1(magic: [u8; 4]) -> Self {
+        Self { magic, state: State::V1, held: Vec::new() }
+    }
+
+    /// Kết nối outbound v2; trả kèm byte phải gửi ngay
+    pub fn outbound_v2(magic: [u8; 4]) -> (Self, Vec<u8>) {
+        let v2 = V2::new(true, &magic);
+        let hello = v2.hello();
+        (Self { magic, state: State::V2(Box::new(v2)), held: Vec::new() }, hello)
+    }
+
+    /// Kết nối inbound, peer v1 hay v2 đều được
+    pub fn inbound(magic: [u8; 4]) -> Self {
+        Self { magic, state: State::Detect(Vec::new()), held: Vec::new() }
+    }
+
+    /// Mã hoá 1 frame vào `out` (giữ lại nếu chưa có key)
+    pub fn send(&mut self, frame: Vec<u8>, out: &mut Vec<u8>) {
+        match &mut self.state {
+            State::V1 => out.extend_from_slice(&frame),
+            State::V2(v2) if v2.session.is_some() => v2.session.as_mut().unwrap().encrypt(&frame, out),
+            _ => self.held.push(frame),
+        }
+    }
+
+    /// Xử lý byte nhận từ socket: byte frame ra `plain`, byte cần gửi thêm
+    /// (key, terminator, frame đang giữ) ra `send`
+    pub fn receive(&mut self, data: &[u8], plain: &mut Vec<u8>, send: &mut Vec<u8>) -> Result<(), TransportError> {
+        if let State::Detect(buf) = &mut self.state {
+            buf.extend_from_slice(data);
+            let n = buf.len().min(self.magic.len());
+            if buf[..n] == self.magic[..n] {
+                if n < self.magic.len() {
+                    return Ok(());
+                }
+                plain.extend_from_slice(buf);
+                for frame in self.held.drain(..) {
+                    send.extend_from_slice(&frame);
+                }
+                self.state = State::V1;
+                return Ok(());
+            }
+            let mut v2 = V2::new(false, &self.magic);
+            send.extend_from_slice(&v2.hello());
+            v2.buf = std::mem::take(buf);
+            self.state = State::V2(Box::new(v2));
+            return self.receive(&[], plain, send);
+        }
+
+        match &mut self.state {
+            State::V1 => {
+                plain.extend_from_slice(data);
+                Ok(())
+            }
+            State::V2(v2) => {
+                v2.buf.extend_from_slice(data);
+                v2.receive(&self.magic, &mut self.held, plain, send)
+            }
+            State::Detect(_) => unreachable!(),
+        }
+    }
+
+    /// Đã gửi key v2, chưa nhận được gì của peer ngoài phần đầu của magic
+    pub fn awaiting_key(&self) -> bool {
+        match &self.state {
+            State::V2(v2) => {
+                let n = v2.buf.len().min(self.magic.len());
+                v2.initiator && v2.session.is_none() && v2.buf[..n] == self.magic[..n]
+            }
+            _ => false,
+        }
+    }
+
+    /// Id phiên v2 (giống nhau ở 2 bên); None nếu v1 hoặc chưa có key
+    pub fn session_id(&self) -> Option<[u8; 32]> {
+        match &self.state {
+            State::V2(v2) => v2.session.as_ref().map(|s| s.id),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAGIC: [u8; 4] = [0xe9, 0x67, 0x67, 0xfa];
+
+    /// Đưa byte vào transport (cả cục hoặc từng byte), trả (plain, send)
+    fn feed(t: &mut Transport, data: &[u8], byte_by_byte: bool) -> Result<(Vec<u8>, Vec<u8>), TransportError> {
+        let (mut plain, mut send) = (Vec::new(), Vec::new());
+        if byte_by_byte {
+            for b in data {
+                t.receive(std::slice::from_ref(b), &mut plain, &mut send)?;
+            }
+        } else {
+            t.receive(data, &mut plain, &mut send)?;
+        }
+        Ok((plain, send))
+    }
+
+    /// Outbound v2 với garbage cho trước
+    fn outbound_with_garbage(garbage: Vec<u8>) -> (Transport, Vec<u8>) {
+        let mut v2 = V2::new(true, &MAGIC);
+        v2.garbage = garbage;
+        let hello = v2.hello();
+        (Transport { magic: MAGIC, state: State::V2(Box::new(v2)), held: Vec::new() }, hello)
+    }
+
+    fn send_cipher(t: &Transport) -> &PacketCipher {
+        match &t.state {
+            State::V2(v2) => &v2.session.as_ref().unwrap().send,
+            _ => panic!("not v2"),
+        }
+    }
+
+    /// Handshake đủ 2 chiều, mỗi bên gửi 1 frame trước khi có key
+    fn handshake(byte_by_byte: bool) -> (Transport, Transport) {
+        let (mut a, hello) = outbound_with_garbage(vec![0x5a; 100]);
+        let mut b = Transport::inbound(MAGIC);
+        let mut out = Vec::new();
+        a.send(b"from initiator".to_vec(), &mut out);
+        b.send(b"from responder".to_vec(), &mut out);
+        assert!(out.is_empty(), "frames must be held until keys are known");
+        assert!(a.awaiting_key());
+
+        let (plain, b_hello) = feed(&mut b, &hello, byte_by_byte).unwrap();
+        assert!(plain.is_empty());
+        let (plain, a_rest) = feed(&mut a, &b_hello, byte_by_byte).unwrap();
+        assert_eq!(plain, b"from responder");
+        let (plain, rest) = feed(&mut b, &a_rest, byte_by_byte).unwrap();
+        assert_eq!(plain, b"from initiator");
+        assert!(rest.is_empty());
+
+        assert!(a.session_id().is_some());
+        assert_eq!(a.session_id(), b.session_id());
+        (a, b)
+    }
+
+    #[test]
+    fn handshake_with_garbage_and_terminator() {
+        let (mut a, mut b) = handshake(false);
+
+        let mut out = Vec::new();
+        b.send(b"reply".to_vec(), &mut out);
+        assert_eq!(feed(&mut a, &out, false).unwrap().0, b"reply");
+    }
+
+    #[test]
+    fn handshake_byte_by_byte() {
+        handshake(true);
+    }
+
+    #[test]
+    fn no_terminator_rejected() {
+        let (mut a, _) = Transport::outbound_v2(MAGIC);
+        let responder = V2::new(false, &MAGIC);
+        let mut junk = responder.ours.to_array().to_vec();
+        junk.extend(std::iter::repeat_n(0u8, MAX_GARBAGE + TERMINATOR_SIZE));
+        assert!(matches!(feed(&mut a, &junk, false), Err(TransportError::NoTerminator)));
+    }
+
+    #[test]
+    fn rekey_boundary() {
+        let (mut a, mut b) = handshake(false);
+        let key0 = send_cipher(&a).key;
+
+        // đi qua 2 lần đổi key, từng packet một
+        for i in 0..2 * REKEY_INTERVAL + 3 {
+            let frame = format!("packet {}", i).into_bytes();
+            let mut out = Vec::new();
+            a.send(frame.clone(), &mut out);
+            assert_eq!(feed(&mut b, &out, false).unwrap().0, frame, "packet {}", i);
+
+            let counter = send_cipher(&a).counter;
+            if counter < REKEY_INTERVAL {
+                assert_eq!(send_cipher(&a).key, key0);
+            } else {
+                assert_ne!(send_cipher(&a).key, key0);
+            }
+        }
+
+        // nhiều packet qua boundary trong 1 lần nhận
+        let start = send_cipher(&b).counter;
+        let target = (start / REKEY_INTERVAL + 1) * REKEY_INTERVAL;
+        let mut out = Vec::new();
+        let mut expected = Vec::new();
+        for i in start..target + 5 {
+            let frame = format!("batch {}", i).into_bytes();
+            expected.extend_from_slice(&frame);
+            b.send(frame, &mut out);
+        }
+        assert_eq!(feed(&mut a, &out, false).unwrap().0, expected);
+    }
+
+    #[test]
+    fn tampered_ciphertext_rejected() {
+        // byte LENGTH_SIZE thuộc nội dung, byte cuối thuộc tag
+        for pos in [Some(LENGTH_SIZE), None] {
+            let (mut a, mut b) = handshake(false);
+            let mut out = Vec::new();
+            a.send(b"hello".to_vec(), &mut out);
+            let pos = pos.unwrap_or(out.len() - 1);
+            out[pos] ^= 1;
+            assert!(matches!(feed(&mut b, &out, false), Err(TransportError::Decrypt)));
+        }
+
+        // packet của phiên khác
+        let (_, mut b) = handshake(false);
+        let (mut other, _) = handshake(false);
+        let mut out = Vec::new();
+        other.send(b"not for b".to_vec(), &mut out);
+        assert!(feed(&mut b, &out, false).is_err());
+    }
+
+    #[test]
+    fn tampered_garbage_rejected() {
+        // garbage là AAD của packet đầu: sửa garbage thì packet đầu hỏng
+        let (mut a, mut hello) = outbound_with_garbage(vec![0x33; 40]);
+        let mut out = Vec::new();
+        a.send(b"first".to_vec(), &mut out);
+        hello[ELLSWIFT_SIZE + 7] ^= 0x80;
+
+        let mut b = Transport::inbound(MAGIC);
+        let (_, b_hello) = feed(&mut b, &hello, false).unwrap();
+        let (_, a_rest) = feed(&mut a, &b_hello, false).unwrap();
+        assert!(matches!(feed(&mut b, &a_rest, false), Err(TransportError::Decrypt)));
+    }
+
+    #[test]
+    fn detects_v1_by_magic() {
+        let mut b = Transport::inbound(MAGIC);
+        let mut out = Vec::new();
+        b.send(b"v1 version".to_vec(), &mut out);
+        assert!(out.is_empty());
+
+        let mut frame = MAGIC.to_vec();
+        frame.extend_from_slice(b"rest of a v1 frame");
+
+        // 2 byte đầu chưa đủ để quyết định
+        let (plain, send) = feed(&mut b, &frame[..2], false).unwrap();
+        assert!(plain.is_empty() && send.is_empty());
+
+        let (plain, send) = feed(&mut b, &frame[2..], false).unwrap();
+        assert_eq!(plain, frame);
+        assert_eq!(send, b"v1 version");
+        assert!(b.session_id().is_none());
+
+        // sau đó là v1 thuần
+        let mut out = Vec::new();
+        b.send(b"plain".to_vec(), &mut out);
+        assert_eq!(out, b"plain");
+    }
+
+    #[test]
+    fn initiator_sees_v1_reply() {
+        let (mut a, _) = Transport::outbound_v2(MAGIC);
+        let mut reply = MAGIC.to_vec();
+        reply.extend_from_slice(b"version");
+        assert!(matches!(feed(&mut a, &reply, false), Err(TransportError::Rejected)));
+    }
+}
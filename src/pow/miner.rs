@@ -5,15 +5,20 @@ use crate::chain::block::merkle_root;
 use crate::chain::reward::BLOCK_REWARD;
 use crate::chain::hash::hash_header;
 use crate::pow::target::bits_to_target;
+use crate::pow::retarget::next_bits;
+use crate::chain::state::ChainState;
 use crate::mempool::Mempool;
 
+/// Mine block nối vào tip hiện tại, bits theo luật retarget
 pub fn mine_block_with_fees(
-    prev_hash: [u8; 32],
-    height: u64,
+    chain: &ChainState,
     miner_address: Vec<u8>,
     mempool: &Mempool,
     max_txs: usize,
 ) -> Block {
+    let parent = chain.tip_entry();
+    let height = parent.height + 1;
+
     // --- coinbase ---
     let coinbase = Transaction {
         inputs: vec![],
@@ -36,10 +41,10 @@ pub fn mine_block_with_fees(
 
     let mut header = BlockHeader {
         version: 1,
-        prev_hash,
+        prev_hash: parent.hash,
         merkle_root: merkle,
-        timestamp: now(),
-        bits: 0x1f00ffff,
+        timestamp: now().max(parent.header.timestamp + 1),
+        bits: next_bits(&chain.params.pow, &chain.index, parent),
         nonce: 0,
     };
 
@@ -2,3 +2,4 @@ pub mod miner;
 pub mod target;
 pub mod verify;
 pub mod work;
+pub mod retarget;
@@ -0,0 +1,48 @@
+use crate::chain::index::{BlockIndex, BlockIndexEntry};
+use crate::chain::params::PowParams;
+use crate::pow::target::bits_to_target;
+
+/// Bits bắt buộc của block con của `parent`
+pub fn next_bits(pow: &PowParams, index: &BlockIndex, parent: &BlockIndexEntry) -> u32 {
+    let height = parent.height + 1;
+    if pow.no_retargeting || !height.is_multiple_of(pow.retarget_interval) {
+        return parent.header.bits;
+    }
+
+    let first = index
+        .get_ancestor(&parent.hash, height - pow.retarget_interval)
+        .expect("retarget period start missing");
+
+    // giới hạn mỗi lần chỉnh tối đa x4 / /4
+    let timespan = pow.target_timespan();
+    let actual = parent
+        .header
+        .timestamp
+        .saturating_sub(first.header.timestamp)
+        .clamp(timespan / 4, timespan * 4);
+
+    retarget_bits(parent.header.bits, actual, timespan, pow.pow_limit_bits)
+}
+
+/// target mới = target cũ * actual / expected, không dễ hơn pow limit
+pub fn retarget_bits(bits: u32, actual: u64, expected: u64, limit_bits: u32) -> u32 {
+    let mut exponent = bits >> 24;
+    let mut mantissa = (bits & 0x007f_ffff) as u128 * actual as u128 / expected as u128;
+
+    while mantissa > 0x007f_ffff {
+        mantissa >>= 8;
+        exponent += 1;
+    }
+    while mantissa != 0 && mantissa < 0x8000 && exponent > 3 {
+        mantissa <<= 8;
+        exponent -= 1;
+    }
+
+    let new_bits = (exponent << 24) | mantissa as u32;
+    // bits_to_target chỉ xử lý được exponent <= 31
+    if exponent > 31 || bits_to_target(new_bits) > bits_to_target(limit_bits) {
+        limit_bits
+    } else {
+        new_bits
+    }
+}
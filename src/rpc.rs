@@ -0,0 +1,236 @@
+//! Control RPC cho node đang chạy.
+//!
+//! Node nghe trên `NodeConfig::rpc_bind`, chỉ nhận địa chỉ loopback vì
+//! RPC không xác thực (ai kết nối được đều bỏ ban / xem peer / invalidate
+//! block được). Mỗi kết
+//! nối gửi đúng 1 `RpcRequest` và nhận 1 `RpcResponse`, mã hoá bincode.
+//! Lệnh CLI dùng `call` thay vì mở chain database (đang bị node giữ).
+
+use std::io;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+use crate::chain::sethash::UtxoStats;
+use crate::chain::tx::Transaction;
+use crate::net::ban::{BanEntry, BanReason, Subnet, BAN_TIME};
+use crate::p2p::peer::{Direction, PeerContext};
+use crate::storage::sleddb::{AddressOutput, TxLocation};
+
+pub const DEFAULT_RPC_BIND: &str = "127.0.0.1:8332";
+const MAX_RPC_SIZE: u64 = 32 * 1024 * 1024;
+const RPC_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Deserialize)]
+pub enum RpcRequest {
+    GetPeerInfo,
+    /// Thêm / bỏ ban; thêm thì ngắt luôn peer trong subnet.
+    /// `duration` None = BAN_TIME
+    SetBan { subnet: Subnet, add: bool, duration: Option<Duration> },
+    ListBans,
+    ClearBanned,
+    /// Đánh dấu block (và con cháu) invalid, reorg sang chain khác
+    InvalidateBlock { hash: [u8; 32] },
+    /// Gỡ trạng thái invalid đã đặt bởi InvalidateBlock
+    ReconsiderBlock { hash: [u8; 32] },
+    /// Tx theo txid (cần txindex)
+    GetRawTransaction { txid: [u8; 32] },
+    /// Output nhận và tx đã tiêu của 1 address (cần addressindex)
+    GetAddressHistory { address: Vec<u8> },
+    /// Thống kê UTXO set tại block (None = tip)
+    GetTxOutSetInfo { block: Option<[u8; 32]> },
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum RpcResponse {
+    PeerInfo(Vec<PeerInfo>),
+    Error(String),
+    Bans(Vec<(Subnet, BanEntry)>),
+    /// Lệnh không có kết quả đã chạy xong
+    Done,
+    /// Active tip sau lệnh
+    Tip { hash: [u8; 32], height: u64 },
+    RawTransaction { loc: TxLocation, tx: Transaction },
+    AddressHistory(Vec<AddressOutput>),
+    TxOutSetInfo { hash: [u8; 32], height: u64, stats: UtxoStats },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PeerInfo {
+    pub id: u64,
+    pub addr: SocketAddr,
+    pub inbound: bool,
+    pub version: u32,
+    pub services: u64,
+    /// Height peer báo lúc handshake
+    pub start_height: u64,
+    /// Thời gian đã kết nối
+    pub conn_time: Duration,
+    /// Bao lâu từ lần gửi / nhận gần nhất
+    pub last_send: Option<Duration>,
+    pub last_recv: Option<Duration>,
+    pub ping_time: Option<Duration>,
+    pub min_ping: Option<Duration>,
+    /// Ping đang chờ Pong được bao lâu
+    pub ping_wait: Option<Duration>,
+    pub blocks_in_flight: usize,
+    /// Id phiên transport v2; None = v1
+    pub session_id: Option<[u8; 32]>,
+    /// Identity pubkey (33 byte nén) peer đã chứng minh lúc handshake
+    pub identity: Option<Vec<u8>>,
+    /// Identity nằm trong danh sách node tin cậy
+    pub whitelisted: bool,
+}
+
+pub fn peer_info(ctx: &PeerContext) -> Vec<PeerInfo> {
+    let now = Instant::now();
+    let download = ctx.download.lock().unwrap();
+
+    ctx.peers
+        .list()
+        .iter()
+        .map(|peer| {
+            let stats = peer.stats.lock().unwrap();
+            PeerInfo {
+                id: peer.id,
+                addr: peer.addr,
+                inbound: peer.direction == Direction::Inbound,
+                version: peer.remote.protocol_version,
+                services: peer.remote.services,
+                start_height: peer.remote.best_height,
+                conn_time: now.duration_since(stats.connected),
+                last_send: stats.last_send.map(|t| now.duration_since(t)),
+                last_recv: stats.last_recv.map(|t| now.duration_since(t)),
+                ping_time: stats.ping_time,
+                min_ping: stats.min_ping,
+                ping_wait: stats.ping_wait(now),
+                blocks_in_flight: download.in_flight_from(peer.id),
+                session_id: peer.remote.session_id,
+                identity: peer.remote.identity.map(|pk| pk.serialize().to_vec()),
+                whitelisted: peer.whitelisted,
+            }
+        })
+        .collect()
+}
+
+fn handle(req: RpcRequest, ctx: &PeerContext) -> RpcResponse {
+    match req {
+        RpcRequest::GetPeerInfo => RpcResponse::PeerInfo(peer_info(ctx)),
+        RpcRequest::SetBan { subnet, add: true, duration } => {
+            let duration = duration.unwrap_or(BAN_TIME);
+            ctx.ban.lock().unwrap().ban(subnet, duration, BanReason::Manual);
+            for peer in ctx.peers.list() {
+                if subnet.contains(&peer.addr.ip()) {
+                    log::info!("disconnecting banned peer {}", peer.addr);
+                    peer.disconnect();
+                }
+            }
+            RpcResponse::Done
+        }
+        RpcRequest::SetBan { subnet, add: false, .. } => {
+            if ctx.ban.lock().unwrap().unban(&subnet) {
+                RpcResponse::Done
+            } else {
+                RpcResponse::Error(format!("{} is not banned", subnet))
+            }
+        }
+        RpcRequest::ListBans => RpcResponse::Bans(ctx.ban.lock().unwrap().list()),
+        RpcRequest::ClearBanned => {
+            ctx.ban.lock().unwrap().clear();
+            RpcResponse::Done
+        }
+        RpcRequest::InvalidateBlock { hash } => {
+            let mut chain = ctx.chain.lock().unwrap();
+            match chain.invalidate_block(&hash) {
+                Ok(()) => RpcResponse::Tip { hash: chain.tip, height: chain.tip_height() },
+                Err(e) => RpcResponse::Error(e.to_string()),
+            }
+        }
+        RpcRequest::ReconsiderBlock { hash } => {
+            let mut chain = ctx.chain.lock().unwrap();
+            match chain.reconsider_block(&hash) {
+                Ok(()) => RpcResponse::Tip { hash: chain.tip, height: chain.tip_height() },
+                Err(e) => RpcResponse::Error(e.to_string()),
+            }
+        }
+        RpcRequest::GetRawTransaction { txid } => {
+            let chain = ctx.chain.lock().unwrap();
+            if !chain.db.has_txindex() {
+                return RpcResponse::Error("txindex not enabled, restart with: run --txindex".into());
+            }
+            match chain.get_transaction(&txid) {
+                Some((loc, tx)) => RpcResponse::RawTransaction { loc, tx },
+                None => RpcResponse::Error(format!("transaction {} not found", hex::encode(txid))),
+            }
+        }
+        RpcRequest::GetAddressHistory { address } => {
+            let chain = ctx.chain.lock().unwrap();
+            if !chain.db.has_addressindex() {
+                return RpcResponse::Error("addressindex not enabled, restart with: run --addressindex".into());
+            }
+            RpcResponse::AddressHistory(chain.get_address_history(&address))
+        }
+        RpcRequest::GetTxOutSetInfo { block } => {
+            let chain = ctx.chain.lock().unwrap();
+            let hash = block.unwrap_or(chain.tip);
+            match (chain.index.get(&hash), chain.db.get_utxo_stats(&hash)) {
+                (Some(entry), Some(stats)) => RpcResponse::TxOutSetInfo { hash, height: entry.height, stats },
+                _ => RpcResponse::Error(format!("no utxo set info for block {}", hex::encode(hash))),
+            }
+        }
+    }
+}
+
+fn decode<T: for<'de> Deserialize<'de>>(stream: &mut TcpStream) -> io::Result<T> {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_limit(MAX_RPC_SIZE)
+        .deserialize_from(stream)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn serve(mut stream: TcpStream, ctx: &PeerContext) -> io::Result<()> {
+    stream.set_read_timeout(Some(RPC_TIMEOUT))?;
+    let req: RpcRequest = decode(&mut stream)?;
+    bincode::serialize_into(&mut stream, &handle(req, ctx)).map_err(io::Error::other)
+}
+
+/// Nghe RPC trên `bind` (phải là loopback), mỗi kết nối 1 thread ngắn
+pub fn start_rpc(bind: &str, ctx: PeerContext) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(bind)?;
+    let local = listener.local_addr()?;
+    if !local.ip().is_loopback() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "rpc has no authentication, refusing to bind a non-loopback address",
+        ));
+    }
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let ctx = ctx.clone();
+            thread::spawn(move || {
+                if let Err(e) = serve(stream, &ctx) {
+                    log::debug!("rpc request failed: {}", e);
+                }
+            });
+        }
+    });
+    Ok(local)
+}
+
+/// Gửi 1 request tới node đang chạy
+pub fn call(addr: &str, req: &RpcRequest) -> io::Result<RpcResponse> {
+    let sock = addr.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "address did not resolve")
+    })?;
+    let mut stream = TcpStream::connect_timeout(&sock, RPC_TIMEOUT)?;
+    stream.set_read_timeout(Some(RPC_TIMEOUT))?;
+
+    bincode::serialize_into(&mut stream, req).map_err(io::Error::other)?;
+    stream.shutdown(Shutdown::Write)?;
+    decode(&mut stream)
+}
@@ -1,78 +1,712 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use serde::{Serialize, Deserialize};
 use sled::Db;
 use bincode;
 
 use crate::chain::block::Block;
+use crate::chain::encode::{serialize, deserialize};
+use crate::chain::index::BlockIndexEntry;
+use crate::chain::sethash::UtxoStats;
+use crate::chain::snapshot::SnapshotMetadata;
+use crate::chain::txid::txid;
+use crate::chain::undo::BlockUndo;
 use crate::chain::utxo::UTXO;
 
+/// txindex: vị trí của tx trong chain
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TxLocation {
+    pub block_hash: [u8; 32],
+    pub height: u64,
+    /// Thứ tự tx trong block
+    pub position: u32,
+}
+
+/// addressindex: 1 output trả cho address, kèm tx đã tiêu nó (nếu có)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AddressOutput {
+    pub txid: [u8; 32],
+    pub vout: u32,
+    pub value: u64,
+    pub height: u64,
+    /// (txid, height) của tx tiêu output này
+    pub spent_by: Option<([u8; 32], u64)>,
+}
+
+/// Giá trị trên disk không decode được (disk hỏng / ghi dở)
+#[derive(Debug, PartialEq, Eq)]
+pub struct CorruptRecord {
+    pub kind: &'static str,
+    /// Key của record, bỏ prefix
+    pub key: Vec<u8>,
+}
+
+impl fmt::Display for CorruptRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "corrupt {} record {}", self.kind, hex::encode(&self.key))
+    }
+}
+
+impl std::error::Error for CorruptRecord {}
+
+/// Ghi đang gom: key -> giá trị mới (None = xoá)
+type PendingWrites = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+/// Nơi giữ key-value: sled trên disk, hoặc BTreeMap trong bộ nhớ (test / simulator)
+enum Backend {
+    Sled(Db),
+    Memory {
+        map: Mutex<BTreeMap<Vec<u8>, Vec<u8>>>,
+        next_id: AtomicU64,
+    },
+}
+
+impl Backend {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Backend::Sled(db) => db.get(key).unwrap().map(|v| v.to_vec()),
+            Backend::Memory { map, .. } => map.lock().unwrap().get(key).cloned(),
+        }
+    }
+
+    /// Ghi (None = xoá), trả về độ dài giá trị cũ
+    fn put(&self, key: &[u8], val: Option<&[u8]>) -> Option<usize> {
+        match self {
+            Backend::Sled(db) => match val {
+                Some(val) => db.insert(key, val).unwrap(),
+                None => db.remove(key).unwrap(),
+            }
+            .map(|v| v.len()),
+            Backend::Memory { map, .. } => {
+                let mut map = map.lock().unwrap();
+                match val {
+                    Some(val) => map.insert(key.to_vec(), val.to_vec()),
+                    None => map.remove(key),
+                }
+                .map(|v| v.len())
+            }
+        }
+    }
+
+    fn scan(&self, prefix: &[u8]) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        match self {
+            Backend::Sled(db) => db
+                .scan_prefix(prefix)
+                .map(|item| {
+                    let (k, v) = item.unwrap();
+                    (k.to_vec(), v.to_vec())
+                })
+                .collect(),
+            Backend::Memory { map, .. } => map
+                .lock()
+                .unwrap()
+                .range(prefix.to_vec()..)
+                .take_while(|(k, _)| k.starts_with(prefix))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+        }
+    }
+
+    /// Ghi cả batch, atomic
+    fn apply(&self, writes: PendingWrites) {
+        match self {
+            Backend::Sled(db) => {
+                let mut batch = sled::Batch::default();
+                for (key, val) in writes {
+                    match val {
+                        Some(val) => batch.insert(key, val),
+                        None => batch.remove(key),
+                    }
+                }
+                db.apply_batch(batch).unwrap();
+            }
+            Backend::Memory { map, .. } => {
+                let mut map = map.lock().unwrap();
+                for (key, val) in writes {
+                    match val {
+                        Some(val) => map.insert(key, val),
+                        None => map.remove(&key),
+                    };
+                }
+            }
+        }
+    }
+
+    fn generate_id(&self) -> u64 {
+        match self {
+            Backend::Sled(db) => db.generate_id().unwrap(),
+            Backend::Memory { next_id, .. } => next_id.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    fn flush(&self) {
+        if let Backend::Sled(db) = self {
+            db.flush().unwrap();
+        }
+    }
+}
+
 pub struct ChainDB {
-    db: Db,
+    db: Backend,
+    /// Tổng số byte block body + undo data đang lưu (dùng cho prune)
+    stored_bytes: AtomicU64,
+    /// Batch đang mở (xem `begin_batch`)
+    pending: Mutex<Option<PendingWrites>>,
 }
 
 impl ChainDB {
     pub fn open(path: &str) -> Self {
-        let db = sled::open(path).expect("cannot open db");
-        ChainDB { db }
+        Self::try_open(path).expect("cannot open db")
+    }
+
+    /// Như `open` nhưng trả lỗi (vd. DB đang bị process khác giữ lock)
+    pub fn try_open(path: &str) -> Result<Self, sled::Error> {
+        sled::open(path).map(|db| Self::from_backend(Backend::Sled(db)))
+    }
+
+    /// DB chỉ nằm trong bộ nhớ, không đụng tới disk, mất khi drop; dùng cho test / simulator
+    pub fn open_temporary() -> Self {
+        Self::from_backend(Backend::Memory {
+            map: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(0),
+        })
+    }
+
+    fn from_backend(db: Backend) -> Self {
+        let stored = [&b"block:"[..], b"undo:"]
+            .into_iter()
+            .flat_map(|prefix| db.scan(prefix).into_values())
+            .map(|v| v.len() as u64)
+            .sum();
+
+        ChainDB {
+            db,
+            stored_bytes: AtomicU64::new(stored),
+            pending: Mutex::new(None),
+        }
+    }
+
+    // ---------- RAW ACCESS ----------
+
+    /// Mọi ghi sau lệnh này được gom lại tới `commit_batch` rồi ghi 1 lần
+    /// (atomic). Đọc trong lúc đó thấy cả giá trị đang gom.
+    pub fn begin_batch(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        assert!(pending.is_none(), "batch already open");
+        *pending = Some(BTreeMap::new());
+    }
+
+    pub fn commit_batch(&self) {
+        let writes = self.pending.lock().unwrap().take().expect("no open batch");
+        self.db.apply(writes);
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if let Some(writes) = self.pending.lock().unwrap().as_ref() {
+            if let Some(val) = writes.get(key) {
+                return val.clone();
+            }
+        }
+        self.db.get(key)
+    }
+
+    /// Ghi (None = xoá), trả về độ dài giá trị cũ
+    fn replace(&self, key: &[u8], val: Option<&[u8]>) -> Option<usize> {
+        let mut pending = self.pending.lock().unwrap();
+        match pending.as_mut() {
+            Some(writes) => {
+                let old = match writes.get(key) {
+                    Some(old) => old.as_ref().map(|v| v.len()),
+                    None => self.db.get(key).map(|v| v.len()),
+                };
+                writes.insert(key.to_vec(), val.map(|v| v.to_vec()));
+                old
+            }
+            None => self.db.put(key, val),
+        }
+    }
+
+    fn insert(&self, key: &[u8], val: &[u8]) {
+        self.replace(key, Some(val));
+    }
+
+    fn remove(&self, key: &[u8]) {
+        self.replace(key, None);
+    }
+
+    /// Mọi (key, value) có prefix, theo thứ tự key, gồm cả ghi đang gom
+    fn scan(&self, prefix: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut found: BTreeMap<Vec<u8>, Option<Vec<u8>>> =
+            self.db.scan(prefix).into_iter().map(|(k, v)| (k, Some(v))).collect();
+        if let Some(writes) = self.pending.lock().unwrap().as_ref() {
+            let within = writes
+                .range(prefix.to_vec()..)
+                .take_while(|(k, _)| k.starts_with(prefix));
+            for (k, v) in within {
+                found.insert(k.clone(), v.clone());
+            }
+        }
+        found.into_iter().filter_map(|(k, v)| Some((k, v?))).collect()
+    }
+
+    pub fn stored_bytes(&self) -> u64 {
+        self.stored_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Cập nhật bộ đếm sau khi ghi/xoá 1 giá trị block/undo
+    fn account(&self, added: usize, removed: Option<usize>) {
+        let removed = removed.unwrap_or(0) as u64;
+        self.stored_bytes.fetch_add(added as u64, Ordering::Relaxed);
+        self.stored_bytes.fetch_sub(removed, Ordering::Relaxed);
+    }
+
+    pub fn flush(&self) {
+        self.db.flush();
     }
 
     // ---------- BLOCK ----------
 
-    pub fn put_block(&self, hash: &[u8; 32], block: &Block) {
+    /// Ghi block body, trả về vị trí (tăng dần theo thứ tự ghi)
+    pub fn put_block(&self, block: &Block) -> u64 {
+        let pos = self.db.generate_id();
+
         let mut key = b"block:".to_vec();
-        key.extend_from_slice(hash);
+        key.extend_from_slice(&pos.to_be_bytes());
+
+        let val = serialize(block);
+        let old = self.replace(&key, Some(&val));
+        self.account(val.len(), old);
+        pos
+    }
+
+    pub fn get_block(&self, pos: u64) -> Result<Option<Block>, CorruptRecord> {
+        let mut key = b"block:".to_vec();
+        key.extend_from_slice(&pos.to_be_bytes());
 
-        let val = bincode::serialize(block).unwrap();
-        self.db.insert(key, val).unwrap();
+        self.get(&key)
+            .map(|v| deserialize(&v).map_err(|_| CorruptRecord { kind: "block", key: pos.to_be_bytes().to_vec() }))
+            .transpose()
     }
 
-    pub fn get_block(&self, hash: &[u8; 32]) -> Option<Block> {
+    pub fn delete_block(&self, pos: u64) {
         let mut key = b"block:".to_vec();
+        key.extend_from_slice(&pos.to_be_bytes());
+        let old = self.replace(&key, None);
+        self.account(0, old);
+    }
+
+    /// Vị trí của mọi block body đã lưu, theo thứ tự ghi
+    pub fn block_positions(&self) -> Vec<u64> {
+        self.scan(b"block:")
+            .into_iter()
+            .filter_map(|(k, _)| k[6..].try_into().ok().map(u64::from_be_bytes))
+            .collect()
+    }
+
+    /// Xoá index, undo, UTXO set, tx/address index và tip; giữ lại block body (dùng cho reindex)
+    pub fn clear_chainstate(&self) {
+        let prefixes = [
+            &b"index:"[..], b"undo:", b"utxo:", b"meta:", b"txidx:", b"addr:",
+            b"utxostats:", b"cfilter:", b"cfheader:", b"bgutxo:",
+        ];
+        for prefix in prefixes {
+            for (key, _) in self.scan(prefix) {
+                let old = self.replace(&key, None);
+                if prefix == b"undo:" {
+                    self.account(0, old);
+                }
+            }
+        }
+    }
+
+    // ---------- UNDO ----------
+
+    pub fn put_undo(&self, hash: &[u8; 32], undo: &BlockUndo) {
+        let mut key = b"undo:".to_vec();
         key.extend_from_slice(hash);
 
-        self.db
-            .get(key)
-            .unwrap()
-            .map(|v| bincode::deserialize(&v).unwrap())
+        let val = bincode::serialize(undo).unwrap();
+        let old = self.replace(&key, Some(&val));
+        self.account(val.len(), old);
+    }
+
+    pub fn delete_undo(&self, hash: &[u8; 32]) {
+        let mut key = b"undo:".to_vec();
+        key.extend_from_slice(hash);
+
+        let old = self.replace(&key, None);
+        self.account(0, old);
+    }
+
+    pub fn get_undo(&self, hash: &[u8; 32]) -> Result<Option<BlockUndo>, CorruptRecord> {
+        let mut key = b"undo:".to_vec();
+        key.extend_from_slice(hash);
+
+        self.get(&key)
+            .map(|v| bincode::deserialize(&v).map_err(|_| CorruptRecord { kind: "undo", key: hash.to_vec() }))
+            .transpose()
+    }
+
+    // ---------- BLOCK INDEX ----------
+
+    pub fn put_index(&self, entry: &BlockIndexEntry) {
+        let mut key = b"index:".to_vec();
+        key.extend_from_slice(&entry.hash);
+
+        self.insert(&key, &bincode::serialize(entry).unwrap());
+    }
+
+    /// Mọi entry của block index; entry không decode được trả về Err
+    pub fn iter_index(&self) -> impl Iterator<Item = Result<BlockIndexEntry, CorruptRecord>> + '_ {
+        self.scan(b"index:").into_iter().map(|(key, val)| {
+            bincode::deserialize(&val).map_err(|_| CorruptRecord { kind: "index", key: key[6..].to_vec() })
+        })
     }
 
     // ---------- META ----------
 
     pub fn set_tip(&self, hash: &[u8; 32], height: u64) {
-        self.db.insert(b"meta:tip", hash).unwrap();
-        self.db
-            .insert(b"meta:height", &height.to_le_bytes())
-            .unwrap();
+        self.insert(b"meta:tip", hash);
+        self.insert(b"meta:height", &height.to_le_bytes());
     }
 
     pub fn get_tip(&self) -> Option<([u8; 32], u64)> {
-        let tip = self.db.get(b"meta:tip").unwrap()?;
-        let height = self.db.get(b"meta:height").unwrap()?;
+        let tip = self.get(b"meta:tip")?;
+        let height = self.get(b"meta:height")?;
 
         Some((
-            tip.as_ref().try_into().unwrap(),
-            u64::from_le_bytes(height.as_ref().try_into().unwrap()),
+            tip.as_slice().try_into().unwrap(),
+            u64::from_le_bytes(height.as_slice().try_into().unwrap()),
         ))
     }
 
+    /// Height cao nhất đã bị prune (None = chưa từng prune)
+    pub fn pruned_height(&self) -> Option<u64> {
+        let v = self.get(b"meta:pruneheight")?;
+        Some(u64::from_le_bytes(v.as_slice().try_into().unwrap()))
+    }
+
+    pub fn set_pruned_height(&self, height: u64) {
+        self.insert(b"meta:pruneheight", &height.to_le_bytes());
+    }
+
+    /// Snapshot UTXO set đã load (loadtxoutset), còn chờ background validation
+    pub fn get_snapshot(&self) -> Option<SnapshotMetadata> {
+        self.get(b"meta:snapshot")
+            .map(|v| bincode::deserialize(&v).unwrap())
+    }
+
+    pub fn put_snapshot(&self, meta: &SnapshotMetadata) {
+        self.insert(b"meta:snapshot", &bincode::serialize(meta).unwrap());
+    }
+
+    pub fn clear_snapshot(&self) {
+        self.remove(b"meta:snapshot");
+    }
+
+    /// Background validation đã chứng minh snapshot sai, cần reindex
+    pub fn snapshot_invalid(&self) -> bool {
+        self.flag(b"meta:snapshotinvalid")
+    }
+
+    pub fn set_snapshot_invalid(&self) {
+        self.set_flag(b"meta:snapshotinvalid", true);
+    }
+
+    // ---------- BACKGROUND VALIDATION ----------
+
+    /// Height cuối cùng background validation đã validate xong (None = chưa bắt đầu)
+    pub fn get_background_height(&self) -> Option<u64> {
+        let v = self.get(b"meta:bgheight")?;
+        Some(u64::from_le_bytes(v.as_slice().try_into().unwrap()))
+    }
+
+    pub fn set_background_height(&self, height: u64) {
+        self.insert(b"meta:bgheight", &height.to_le_bytes());
+    }
+
+    fn background_utxo_key(txid: &[u8; 32], vout: u32) -> Vec<u8> {
+        let mut key = b"bgutxo:".to_vec();
+        key.extend_from_slice(txid);
+        key.extend_from_slice(&vout.to_le_bytes());
+        key
+    }
+
+    /// UTXO set background validation dựng từ genesis, tách khỏi UTXO set đang dùng
+    pub fn put_background_utxo(&self, utxo: &UTXO) {
+        let key = Self::background_utxo_key(&utxo.txid, utxo.vout);
+        self.insert(&key, &bincode::serialize(utxo).unwrap());
+    }
+
+    pub fn delete_background_utxo(&self, txid: &[u8; 32], vout: u32) {
+        self.remove(&Self::background_utxo_key(txid, vout));
+    }
+
+    pub fn iter_background_utxos(&self) -> Vec<UTXO> {
+        self.scan(b"bgutxo:")
+            .into_iter()
+            .map(|(_, val)| bincode::deserialize(&val).unwrap())
+            .collect()
+    }
+
+    /// Xoá tiến độ background validation (đã xong hoặc snapshot sai)
+    pub fn clear_background(&self) {
+        self.clear_prefix(b"bgutxo:");
+        self.remove(b"meta:bgheight");
+    }
+
+    // ---------- COMPACT FILTER ----------
+
+    pub fn put_filter(&self, hash: &[u8; 32], filter: &[u8]) {
+        let mut key = b"cfilter:".to_vec();
+        key.extend_from_slice(hash);
+        self.insert(&key, filter);
+    }
+
+    pub fn get_filter(&self, hash: &[u8; 32]) -> Option<Vec<u8>> {
+        let mut key = b"cfilter:".to_vec();
+        key.extend_from_slice(hash);
+        self.get(&key)
+    }
+
+    pub fn put_filter_header(&self, hash: &[u8; 32], header: &[u8; 32]) {
+        let mut key = b"cfheader:".to_vec();
+        key.extend_from_slice(hash);
+        self.insert(&key, header);
+    }
+
+    pub fn get_filter_header(&self, hash: &[u8; 32]) -> Option<[u8; 32]> {
+        let mut key = b"cfheader:".to_vec();
+        key.extend_from_slice(hash);
+        self.get(&key).map(|v| v.as_slice().try_into().unwrap())
+    }
+
     // ---------- UTXO ----------
 
-    pub fn put_utxo(&self, utxo: &UTXO) {
+    /// Thống kê + rolling hash của UTXO set sau khi connect block `hash`
+    pub fn put_utxo_stats(&self, hash: &[u8; 32], stats: &UtxoStats) {
+        let mut key = b"utxostats:".to_vec();
+        key.extend_from_slice(hash);
+        self.insert(&key, &bincode::serialize(stats).unwrap());
+    }
+
+    pub fn get_utxo_stats(&self, hash: &[u8; 32]) -> Option<UtxoStats> {
+        let mut key = b"utxostats:".to_vec();
+        key.extend_from_slice(hash);
+
+        self.get(&key).map(|v| bincode::deserialize(&v).unwrap())
+    }
+
+
+    fn utxo_key(txid: &[u8; 32], vout: u32) -> Vec<u8> {
         let mut key = b"utxo:".to_vec();
-        key.extend_from_slice(&utxo.txid);
-        key.extend_from_slice(&utxo.vout.to_le_bytes());
+        key.extend_from_slice(txid);
+        key.extend_from_slice(&vout.to_le_bytes());
+        key
+    }
 
-        let val = bincode::serialize(utxo).unwrap();
-        self.db.insert(key, val).unwrap();
+    pub fn put_utxo(&self, utxo: &UTXO) {
+        let key = Self::utxo_key(&utxo.txid, utxo.vout);
+
+        self.insert(&key, &bincode::serialize(utxo).unwrap());
+    }
+
+    pub fn delete_utxo(&self, txid: &[u8; 32], vout: u32) {
+        self.remove(&Self::utxo_key(txid, vout));
     }
 
     pub fn iter_utxos(&self) -> Vec<UTXO> {
-        let mut list = Vec::new();
+        self.scan(b"utxo:")
+            .into_iter()
+            .map(|(_, val)| bincode::deserialize(&val).unwrap())
+            .collect()
+    }
+}
 
-        for item in self.db.scan_prefix(b"utxo:") {
-            let (_, val) = item.unwrap();
-            let utxo: UTXO = bincode::deserialize(&val).unwrap();
-            list.push(utxo);
+/* =========================
+   TX INDEX / ADDRESS INDEX
+   ========================= */
+
+impl ChainDB {
+    fn flag(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    fn set_flag(&self, key: &[u8], on: bool) {
+        if on {
+            self.insert(key, &[1u8]);
+        } else {
+            self.remove(key);
+        }
+    }
+
+    fn clear_prefix(&self, prefix: &[u8]) {
+        for (key, _) in self.scan(prefix) {
+            self.remove(&key);
+        }
+    }
+
+    // ---------- TXINDEX ----------
+
+    /// txindex đã được dựng và đang theo kịp tip
+    pub fn has_txindex(&self) -> bool {
+        self.flag(b"meta:txindex")
+    }
+
+    pub fn set_txindex(&self, on: bool) {
+        self.set_flag(b"meta:txindex", on);
+    }
+
+    pub fn clear_txindex(&self) {
+        self.clear_prefix(b"txidx:");
+        self.set_txindex(false);
+    }
+
+    pub fn index_block_txs(&self, hash: &[u8; 32], height: u64, block: &Block) {
+        for (i, tx) in block.transactions.iter().enumerate() {
+            let mut key = b"txidx:".to_vec();
+            key.extend_from_slice(&txid(tx));
+
+            let loc = TxLocation {
+                block_hash: *hash,
+                height,
+                position: i as u32,
+            };
+            self.insert(&key, &bincode::serialize(&loc).unwrap());
         }
+    }
+
+    pub fn unindex_block_txs(&self, block: &Block) {
+        for tx in &block.transactions {
+            let mut key = b"txidx:".to_vec();
+            key.extend_from_slice(&txid(tx));
+            self.remove(&key);
+        }
+    }
+
+    pub fn get_tx_location(&self, id: &[u8; 32]) -> Option<TxLocation> {
+        let mut key = b"txidx:".to_vec();
+        key.extend_from_slice(id);
+
+        self.get(&key).map(|v| bincode::deserialize(&v).unwrap())
+    }
+
+    // ---------- ADDRESS INDEX ----------
+
+    pub fn has_addressindex(&self) -> bool {
+        self.flag(b"meta:addressindex")
+    }
+
+    pub fn set_addressindex(&self, on: bool) {
+        self.set_flag(b"meta:addressindex", on);
+    }
+
+    pub fn clear_addressindex(&self) {
+        self.clear_prefix(b"addr:");
+        self.set_addressindex(false);
+    }
+
+    /// addr:<len u32 BE><address> để scan_prefix không lẫn address khác
+    fn address_prefix(address: &[u8]) -> Vec<u8> {
+        let mut key = b"addr:".to_vec();
+        key.extend_from_slice(&(address.len() as u32).to_be_bytes());
+        key.extend_from_slice(address);
+        key
+    }
+
+    fn address_key(address: &[u8], txid: &[u8; 32], vout: u32) -> Vec<u8> {
+        let mut key = Self::address_prefix(address);
+        key.extend_from_slice(txid);
+        key.extend_from_slice(&vout.to_le_bytes());
+        key
+    }
+
+    /// Address của mọi outpoint mà block tiêu (từ undo hoặc output trong chính block)
+    fn spent_addresses(block: &Block, undo: &BlockUndo) -> HashMap<([u8; 32], u32), Vec<u8>> {
+        let mut map = HashMap::new();
+        for u in &undo.spent {
+            map.insert((u.txid, u.vout), u.address.clone());
+        }
+        for tx in &block.transactions {
+            let id = txid(tx);
+            for (vout, out) in tx.outputs.iter().enumerate() {
+                map.insert((id, vout as u32), out.to_address.clone());
+            }
+        }
+        map
+    }
+
+    pub fn index_block_addresses(&self, block: &Block, undo: &BlockUndo, height: u64) {
+        for tx in &block.transactions {
+            let id = txid(tx);
+            for (vout, out) in tx.outputs.iter().enumerate() {
+                let entry = AddressOutput {
+                    txid: id,
+                    vout: vout as u32,
+                    value: out.value,
+                    height,
+                    spent_by: None,
+                };
+                let key = Self::address_key(&out.to_address, &id, vout as u32);
+                self.insert(&key, &bincode::serialize(&entry).unwrap());
+            }
+        }
+
+        let addresses = Self::spent_addresses(block, undo);
+        for tx in block.transactions.iter().skip(1) {
+            let id = txid(tx);
+            for inp in &tx.inputs {
+                let address = match addresses.get(&(inp.prev_txid, inp.vout)) {
+                    Some(a) => a,
+                    None => continue,
+                };
+                let key = Self::address_key(address, &inp.prev_txid, inp.vout);
+                if let Some(v) = self.get(&key) {
+                    let mut entry: AddressOutput = bincode::deserialize(&v).unwrap();
+                    entry.spent_by = Some((id, height));
+                    self.insert(&key, &bincode::serialize(&entry).unwrap());
+                }
+            }
+        }
+    }
+
+    pub fn unindex_block_addresses(&self, block: &Block, undo: &BlockUndo) {
+        let addresses = Self::spent_addresses(block, undo);
+        for tx in block.transactions.iter().skip(1) {
+            for inp in &tx.inputs {
+                let address = match addresses.get(&(inp.prev_txid, inp.vout)) {
+                    Some(a) => a,
+                    None => continue,
+                };
+                let key = Self::address_key(address, &inp.prev_txid, inp.vout);
+                if let Some(v) = self.get(&key) {
+                    let mut entry: AddressOutput = bincode::deserialize(&v).unwrap();
+                    entry.spent_by = None;
+                    self.insert(&key, &bincode::serialize(&entry).unwrap());
+                }
+            }
+        }
+
+        for tx in &block.transactions {
+            let id = txid(tx);
+            for (vout, out) in tx.outputs.iter().enumerate() {
+                self.remove(&Self::address_key(&out.to_address, &id, vout as u32));
+            }
+        }
+    }
+
+    pub fn get_address_outputs(&self, address: &[u8]) -> Vec<AddressOutput> {
+        let mut list: Vec<AddressOutput> = self
+            .scan(&Self::address_prefix(address))
+            .into_iter()
+            .map(|(_, v)| bincode::deserialize(&v).unwrap())
+            .collect();
 
+        list.sort_by_key(|e| e.height);
         list
     }
 }
@@ -1,8 +1,7 @@
 use rand::{RngCore, rngs::OsRng};
-use argon2::{Argon2, PasswordHasher};
+use argon2::Argon2;
 use aes_gcm::{Aes256Gcm, Key, Nonce};
 use aes_gcm::aead::{Aead, KeyInit};
-use sha2::{Sha256, Digest};
 
 pub struct EncryptedBlob {
     pub ciphertext: Vec<u8>,
@@ -31,7 +30,7 @@ pub fn encrypt(password: &str, plaintext: &[u8]) -> EncryptedBlob {
     OsRng.fill_bytes(&mut nonce);
 
     let key_bytes = derive_key(password, &salt);
-    let cipher = Aes256Gcm::new(Key::from_slice(&key_bytes));
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key_bytes));
 
     let ciphertext = cipher
         .encrypt(Nonce::from_slice(&nonce), plaintext)
@@ -44,14 +43,15 @@ pub fn encrypt(password: &str, plaintext: &[u8]) -> EncryptedBlob {
     }
 }
 
-pub fn decrypt(password: &str, blob: &EncryptedBlob) -> Vec<u8> {
+/// None = sai password hoặc dữ liệu bị sửa
+pub fn decrypt(password: &str, blob: &EncryptedBlob) -> Option<Vec<u8>> {
     let key_bytes = derive_key(password, &blob.salt);
-    let cipher = Aes256Gcm::new(Key::from_slice(&key_bytes));
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key_bytes));
 
     cipher
         .decrypt(
             Nonce::from_slice(&blob.nonce),
             blob.ciphertext.as_ref(),
         )
-        .expect("invalid password or corrupted wallet")
+        .ok()
 }
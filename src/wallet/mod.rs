@@ -2,3 +2,6 @@ pub mod seed;
 pub mod derive;
 pub mod address;
 pub mod store;
+pub mod role;
+pub mod crypto;
+pub mod persist;
@@ -1,5 +1,6 @@
 use serde::{Serialize, Deserialize};
 use std::fs;
+use std::io;
 use std::path::Path;
 
 use crate::wallet::crypto::{EncryptedBlob, encrypt, decrypt};
@@ -13,11 +14,12 @@ pub struct WalletFile {
     pub nonce: [u8; 12],
 }
 
+/// Ghi seed đã mã hoá ra file mới (không ghi đè wallet có sẵn), chỉ owner đọc được (unix)
 pub fn save_wallet(
     path: &Path,
     seed: &MasterSeed,
     password: &str,
-) {
+) -> io::Result<()> {
     let blob = encrypt(password, seed.as_bytes());
 
     let file = WalletFile {
@@ -28,15 +30,20 @@ pub fn save_wallet(
     };
 
     let encoded = bincode::serialize(&file).unwrap();
-    fs::write(path, encoded).expect("write wallet failed");
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    io::Write::write_all(&mut options.open(path)?, &encoded)
 }
 
 pub fn load_wallet(
     path: &Path,
     password: &str,
-) -> MasterSeed {
-    let raw = fs::read(path).expect("wallet not found");
-    let file: WalletFile = bincode::deserialize(&raw).unwrap();
+) -> io::Result<MasterSeed> {
+    let raw = fs::read(path)?;
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let file: WalletFile = bincode::deserialize(&raw).map_err(|_| invalid("malformed wallet file"))?;
 
     let blob = EncryptedBlob {
         ciphertext: file.encrypted_seed,
@@ -44,9 +51,32 @@ pub fn load_wallet(
         nonce: file.nonce,
     };
 
-    let seed_bytes = decrypt(password, &blob);
-    let mut seed = [0u8; 32];
-    seed.copy_from_slice(&seed_bytes[..32]);
+    let seed_bytes = decrypt(password, &blob).ok_or_else(|| invalid("wrong passphrase or corrupted wallet"))?;
+    let seed: [u8; 32] = seed_bytes.try_into().map_err(|_| invalid("wallet seed must be 32 bytes"))?;
 
-    MasterSeed::from_bytes(seed)
+    Ok(MasterSeed::from_bytes(seed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wallet_round_trip() {
+        let dir = std::env::temp_dir().join(format!("egg-wallet-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("wallet.dat");
+
+        let seed = MasterSeed::from_bytes([9; 32]);
+        save_wallet(&path, &seed, "pass").unwrap();
+        assert_eq!(load_wallet(&path, "pass").unwrap().as_bytes(), seed.as_bytes());
+
+        let err = load_wallet(&path, "wrong").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // không ghi đè wallet có sẵn
+        assert!(save_wallet(&path, &MasterSeed::generate(), "pass").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -6,6 +6,7 @@ use crate::wallet::derive::{derive_key, DerivedKey};
 use crate::wallet::address::pubkey_to_address;
 use crate::wallet::role::Role;
 
+#[derive(Default)]
 pub struct Wallet {
     seed: Option<MasterSeed>, // None = locked
     next_index: HashMap<Role, u64>,
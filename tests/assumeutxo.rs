@@ -0,0 +1,130 @@
+use egg_node::chain::block::{Block, merkle_root};
+use egg_node::chain::header::BlockHeader;
+use egg_node::chain::params::{ChainParams, Network};
+use egg_node::chain::reward::BLOCK_REWARD;
+use egg_node::chain::sign::sign_tx;
+use egg_node::chain::state::ChainState;
+use egg_node::chain::tx::{Transaction, TxInput, TxOutput};
+use egg_node::chain::txid::txid;
+use egg_node::chain::verify::MAX_VERIFY_LEVEL;
+use egg_node::pow::retarget::next_bits;
+use egg_node::pow::verify::verify_pow;
+use egg_node::storage::sleddb::ChainDB;
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+/// Height của snapshot regtest trong ChainParams
+const SNAPSHOT_HEIGHT: u64 = 50;
+
+/// sled nhả file lock trong thread nền sau khi drop, nên mở lại phải chờ
+fn open(path: &str) -> ChainState {
+    for _ in 0..100 {
+        if let Ok(db) = ChainDB::try_open(path) {
+            return ChainState::load_or_init(ChainParams::new(Network::Regtest), db);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    panic!("db still locked");
+}
+
+/// Block tất định ở `height`: coinbase, cứ 10 block thì thêm 1 tx tiêu
+/// coinbase của block cách đó 5 block, chia làm 2 output
+fn next_block(chain: &ChainState) -> Block {
+    let parent = chain.tip_entry();
+    let height = parent.height + 1;
+
+    let mut txs = vec![Transaction::coinbase(b"assumeutxo".to_vec(), BLOCK_REWARD, &format!("block {}", height))];
+    if height.is_multiple_of(10) {
+        let sk = SecretKey::from_slice(&[3u8; 32]).unwrap();
+        let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &sk);
+        let spent = chain.get_block(&chain.block_hash_at(height - 5).unwrap()).unwrap();
+        let mut tx = Transaction {
+            inputs: vec![TxInput {
+                prev_txid: txid(&spent.transactions[0]),
+                vout: 0,
+                signature: vec![],
+                pubkey: pubkey.serialize().to_vec(),
+            }],
+            outputs: vec![
+                TxOutput { value: BLOCK_REWARD / 2, to_address: b"half-a".to_vec() },
+                TxOutput { value: BLOCK_REWARD / 2, to_address: b"half-b".to_vec() },
+            ],
+            data: vec![],
+        };
+        tx.inputs[0].signature = sign_tx(&tx, &sk);
+        txs.push(tx);
+    }
+
+    let mut header = BlockHeader {
+        version: 1,
+        prev_hash: parent.hash,
+        merkle_root: merkle_root(&txs),
+        timestamp: parent.header.timestamp + 60,
+        bits: next_bits(&chain.params.pow, &chain.index, parent),
+        nonce: 0,
+    };
+    while !verify_pow(&header) {
+        header.nonce += 1;
+    }
+    Block { header, transactions: txs }
+}
+
+#[test]
+fn regtest_snapshot_loads_and_validates_across_restart() {
+    let dir = std::env::temp_dir().join(format!("egg-assumeutxo-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // chain nguồn dựng snapshot khớp với assumeutxo của regtest
+    let mut source = ChainState::load_or_init(ChainParams::new(Network::Regtest), ChainDB::open_temporary());
+    let mut blocks = Vec::new();
+    for _ in 0..SNAPSHOT_HEIGHT {
+        let block = next_block(&source);
+        blocks.push(block.clone());
+        assert!(source.add_block(block));
+    }
+    let file = dir.join("utxo.dat");
+    let meta = source.dump_txoutset(&file).unwrap();
+    let expected = source.params.assumeutxo_for(&meta.base_hash).expect("regtest assumeutxo entry");
+    assert_eq!(
+        (expected.height, expected.coin_count, expected.content_hash),
+        (meta.height, meta.coin_count, meta.content_hash)
+    );
+
+    let path = dir.join("node");
+    let path = path.to_str().unwrap();
+    let mut chain = open(path);
+    chain.load_txoutset(&file).unwrap();
+    assert_eq!(chain.tip, meta.base_hash);
+    assert!(chain.snapshot_pending());
+
+    // validate 1 nửa lịch sử rồi restart
+    for block in &blocks[..25] {
+        assert!(chain.add_block(block.clone()));
+    }
+    assert_eq!(chain.validate_snapshot_step(1000), 25);
+    assert!(chain.snapshot_pending());
+    drop(chain);
+
+    let mut chain = open(path);
+    assert!(chain.snapshot_pending());
+    for block in &blocks[25..] {
+        assert!(chain.add_block(block.clone()));
+    }
+    // tiếp tục từ height 26, không validate lại từ genesis
+    assert_eq!(chain.validate_snapshot_step(1000), SNAPSHOT_HEIGHT - 25);
+    assert!(!chain.snapshot_pending());
+    assert!(!chain.db.snapshot_invalid());
+    assert!(chain.db.get_snapshot().is_none());
+    assert!(chain.verify_chain(0, MAX_VERIFY_LEVEL, |_, _| {}).is_ok());
+
+    // sau restart không còn snapshot chờ validate
+    drop(chain);
+    let mut chain = open(path);
+    assert!(!chain.snapshot_pending());
+    let block = next_block(&chain);
+    assert!(chain.add_block(block));
+    assert_eq!(chain.tip_height(), SNAPSHOT_HEIGHT + 1);
+    drop(chain);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,87 @@
+use egg_node::chain::block::{Block, merkle_root};
+use egg_node::chain::error::ChainError;
+use egg_node::chain::hash::hash_header;
+use egg_node::chain::header::BlockHeader;
+use egg_node::chain::params::{ChainParams, Network};
+use egg_node::chain::state::ChainState;
+use egg_node::chain::tx::Transaction;
+use egg_node::chain::verify::MAX_VERIFY_LEVEL;
+use egg_node::pow::retarget::next_bits;
+use egg_node::pow::verify::verify_pow;
+use egg_node::storage::sleddb::ChainDB;
+
+/// sled nhả file lock trong thread nền sau khi drop, nên mở lại phải chờ
+fn retry<T, E>(open: impl Fn() -> Result<T, E>) -> T {
+    for _ in 0..100 {
+        if let Ok(db) = open() {
+            return db;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    panic!("db still locked");
+}
+
+fn open(path: &str) -> ChainState {
+    ChainState::load_or_init(ChainParams::new(Network::Regtest), retry(|| ChainDB::try_open(path)))
+}
+
+fn mine_on_tip(chain: &mut ChainState, tag: u64) -> [u8; 32] {
+    let parent = chain.tip_entry();
+    let txs = vec![Transaction::coinbase(b"corrupt-test".to_vec(), 0, &format!("block {}", tag))];
+    let mut header = BlockHeader {
+        version: 1,
+        prev_hash: parent.hash,
+        merkle_root: merkle_root(&txs),
+        timestamp: parent.header.timestamp + 1,
+        bits: next_bits(&chain.params.pow, &chain.index, parent),
+        nonce: 0,
+    };
+    while !verify_pow(&header) {
+        header.nonce += 1;
+    }
+    let hash = hash_header(&header);
+    assert!(chain.add_block(Block { header, transactions: txs }));
+    hash
+}
+
+#[test]
+fn corrupt_block_is_reported_not_fatal() {
+    let dir = std::env::temp_dir().join(format!("egg-corrupt-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let path = dir.to_str().unwrap();
+
+    let mut chain = open(path);
+    let hashes: Vec<[u8; 32]> = (1..=4).map(|i| mine_on_tip(&mut chain, i)).collect();
+    let bad = hashes[1];
+    let pos = chain.index.get(&bad).unwrap().data_pos.unwrap();
+    chain.db.flush();
+    drop(chain);
+
+    // ghi đè body của block ở height 2 bằng byte rác
+    {
+        let db = retry(|| sled::open(path));
+        let mut key = b"block:".to_vec();
+        key.extend_from_slice(&pos.to_be_bytes());
+        assert!(db.insert(key, &b"not a block"[..]).unwrap().is_some());
+        db.flush().unwrap();
+    }
+
+    let chain = open(path);
+    assert_eq!(chain.tip_height(), 4);
+    assert!(chain.get_block(&bad).is_none());
+    match chain.verify_chain(0, MAX_VERIFY_LEVEL, |_, _| {}) {
+        Err(ChainError::Corrupt { hash, .. }) => assert_eq!(hash, bad),
+        other => panic!("expected corrupt block 2, got {:?}", other),
+    }
+    drop(chain);
+
+    // reindex bỏ qua block hỏng; block sau nó không nối được nên tip lùi về height 1
+    let db = retry(|| ChainDB::try_open(path));
+    let (chain, skipped) = ChainState::reindex(ChainParams::new(Network::Regtest), db, |_, _| {}).unwrap();
+    assert_eq!(skipped, 1);
+    assert_eq!(chain.tip, hashes[0]);
+    assert!(chain.verify_chain(0, MAX_VERIFY_LEVEL, |_, _| {}).is_ok());
+    drop(chain);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
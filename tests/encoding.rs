@@ -0,0 +1,243 @@
+use egg_node::chain::block::{Block, merkle_root};
+use egg_node::chain::encode::{
+    serialize, deserialize, write_varint, read_varint, DecodeError, Reader,
+};
+use egg_node::chain::genesis_block;
+use egg_node::chain::hash::hash_header;
+use egg_node::chain::header::BlockHeader;
+use egg_node::chain::tx::{Transaction, TxInput, TxOutput};
+use egg_node::chain::txid::txid;
+use egg_node::chain::siphash::{siphash24, siphash_key};
+use egg_node::chain::filter::{build_filter, filter_match_any, outpoint_element};
+use egg_node::p2p::frame::{encode_frame, read_message, FrameDecoder, FrameError, HEADER_SIZE};
+use egg_node::p2p::message::Message;
+
+// Golden vectors cho consensus encoding.
+// Nếu 1 trong các giá trị này đổi => hard fork, KHÔNG được sửa vector cho khớp code.
+
+const GENESIS_HEADER: &str = "\
+01000000\
+0000000000000000000000000000000000000000000000000000000000000000\
+ca31c2a8ba844fde7ab349b2da908d8330986e4e594e997cc64251bf6af528af\
+8085746700000000\
+ffff001f\
+0000000000000000";
+
+const GENESIS_HASH: &str =
+    "67d25f63d5179942248c2e6fd921cea52ccf42f49fdcd4b42e15ae2c6e0edf25";
+
+const GENESIS_COINBASE_TXID: &str =
+    "ca31c2a8ba844fde7ab349b2da908d8330986e4e594e997cc64251bf6af528af";
+
+const SPEND_TX: &str = "\
+01000000\
+01\
+1111111111111111111111111111111111111111111111111111111111111111\
+02000000\
+03aabbcc\
+02ddee\
+02\
+e803000000000000\
+0161\
+ffffffffffffffff\
+00\
+00";
+
+// SipHash-2-4 reference vectors: key = 00..0f, message = 00..(len-1)
+const SIPHASH: &[(usize, u64)] = &[
+    (0, 0x726fdb47dd0e0e31),
+    (1, 0x74f839c593dc67fd),
+    (7, 0xab0200f58b01d137),
+    (8, 0x93f5f5799a932462),
+    (15, 0xa129ca6149be45e5),
+];
+
+const VARINTS: &[(u64, &str)] = &[
+    (0, "00"),
+    (0xfc, "fc"),
+    (0xfd, "fdfd00"),
+    (0xffff, "fdffff"),
+    (0x1_0000, "fe00000100"),
+    (0xffff_ffff, "feffffffff"),
+    (0x1_0000_0000, "ff0000000001000000"),
+];
+
+fn check(name: &str, got: &[u8], expected: &str) {
+    assert_eq!(hex::encode(got), expected, "vector {} mismatch", name);
+}
+
+fn spend_tx() -> Transaction {
+    Transaction {
+        inputs: vec![TxInput {
+            prev_txid: [0x11; 32],
+            vout: 2,
+            signature: vec![0xaa, 0xbb, 0xcc],
+            pubkey: vec![0xdd, 0xee],
+        }],
+        outputs: vec![
+            TxOutput { value: 1000, to_address: b"a".to_vec() },
+            TxOutput { value: u64::MAX, to_address: vec![] },
+        ],
+        data: vec![],
+    }
+}
+
+/// Genesis header + coinbase genesis + spend tx
+fn filter_block() -> Block {
+    let genesis = genesis_block();
+    Block {
+        header: genesis.header.clone(),
+        transactions: vec![genesis.transactions[0].clone(), spend_tx()],
+    }
+}
+
+#[test]
+fn varint() {
+    for (n, expected) in VARINTS {
+        let mut out = Vec::new();
+        write_varint(&mut out, *n);
+        check(&format!("varint {:#x}", n), &out, expected);
+
+        let bytes = hex::decode(expected).unwrap();
+        assert_eq!(read_varint(&mut Reader::new(&bytes)), Ok(*n));
+    }
+}
+
+#[test]
+fn genesis() {
+    let genesis = genesis_block();
+    check("genesis header", &serialize(&genesis.header), GENESIS_HEADER);
+    check("genesis hash", &hash_header(&genesis.header), GENESIS_HASH);
+    check("genesis coinbase txid", &txid(&genesis.transactions[0]), GENESIS_COINBASE_TXID);
+    check("genesis merkle root", &merkle_root(&genesis.transactions), GENESIS_COINBASE_TXID);
+}
+
+#[test]
+fn spend_tx_encoding() {
+    check("spend tx", &serialize(&spend_tx()), SPEND_TX);
+}
+
+#[test]
+fn round_trip() {
+    let genesis = genesis_block();
+    let header: BlockHeader = deserialize(&hex::decode(GENESIS_HEADER).unwrap()).unwrap();
+    assert_eq!(hash_header(&header), hash_header(&genesis.header));
+
+    let decoded: Transaction = deserialize(&hex::decode(SPEND_TX).unwrap()).unwrap();
+    assert_eq!(serialize(&decoded), serialize(&spend_tx()));
+
+    let block: Block = deserialize(&serialize(&genesis)).unwrap();
+    assert_eq!(serialize(&block), serialize(&genesis));
+}
+
+#[test]
+fn reject_malformed() {
+    let non_canonical = hex::decode("fd0500").unwrap();
+    assert_eq!(
+        read_varint(&mut Reader::new(&non_canonical)),
+        Err(DecodeError::NonCanonicalVarInt)
+    );
+
+    let mut trailing = hex::decode(SPEND_TX).unwrap();
+    trailing.push(0);
+    assert_eq!(
+        deserialize::<Transaction>(&trailing).err(),
+        Some(DecodeError::TrailingBytes(1))
+    );
+
+    let mut bad_version = hex::decode(SPEND_TX).unwrap();
+    bad_version[0] = 2;
+    assert_eq!(
+        deserialize::<Transaction>(&bad_version).err(),
+        Some(DecodeError::UnsupportedTxVersion(2))
+    );
+
+    // list length lớn hơn dữ liệu còn lại -> không được cấp phát
+    let oversized = hex::decode("01000000ffffffffffffffffff").unwrap();
+    assert_eq!(
+        deserialize::<Transaction>(&oversized).err(),
+        Some(DecodeError::OversizedLength(u64::MAX))
+    );
+
+    let truncated = hex::decode(&GENESIS_HEADER[..100]).unwrap();
+    assert_eq!(
+        deserialize::<BlockHeader>(&truncated).err(),
+        Some(DecodeError::UnexpectedEof)
+    );
+}
+
+#[test]
+fn siphash() {
+    let key: [u8; 16] = core::array::from_fn(|i| i as u8);
+    let (k0, k1) = siphash_key(&key);
+    for (len, expected) in SIPHASH {
+        let msg: Vec<u8> = (0..*len as u8).collect();
+        assert_eq!(siphash24(k0, k1, &msg), *expected, "siphash len {}", len);
+    }
+}
+
+#[test]
+fn compact_filter() {
+    let block = filter_block();
+    let hash = hash_header(&block.header);
+    let filter = build_filter(&hash, &block);
+
+    let spent = outpoint_element(&[0x11; 32], 2);
+    for element in [&b"genesis"[..], b"a", &spent] {
+        assert_eq!(filter_match_any(&filter, &hash, &[element]), Ok(true));
+    }
+    let misses: Vec<Vec<u8>> = (0..1000u32).map(|i| i.to_le_bytes().to_vec()).collect();
+    let miss_refs: Vec<&[u8]> = misses.iter().map(|m| m.as_slice()).collect();
+    assert_eq!(filter_match_any(&filter, &hash, &miss_refs), Ok(false));
+    assert_eq!(filter_match_any(&[0], &hash, &[b"a"]), Ok(false));
+}
+
+#[test]
+fn p2p_frame() {
+    let magic = [0xe9, 0x67, 0x67, 0x01];
+    let msg = Message::Block { block: filter_block() };
+    let frame = encode_frame(&magic, &msg);
+    assert_eq!(&frame[..4], &magic);
+    assert_eq!(&frame[4..16], b"block\0\0\0\0\0\0\0");
+    assert_eq!(frame.len() - HEADER_SIZE, u32::from_le_bytes(frame[16..20].try_into().unwrap()) as usize);
+
+    // nhận từng byte một: chỉ ra message khi đủ frame
+    let mut dec = FrameDecoder::new(magic);
+    let mut stream = frame.clone();
+    stream.extend_from_slice(&frame);
+    let mut got = 0;
+    for b in &stream {
+        dec.push(&[*b]);
+        while let Some(m) = dec.next_message().unwrap() {
+            assert!(matches!(m, Message::Block { .. }));
+            got += 1;
+        }
+    }
+    assert_eq!((got, dec.buffered()), (2, 0));
+    assert!(read_message(&mut &frame[..], &magic).is_ok());
+
+    // checksum sai: bỏ frame, frame sau vẫn đọc được
+    let mut bad = frame.clone();
+    *bad.last_mut().unwrap() ^= 1;
+    bad.extend_from_slice(&frame);
+    let mut dec = FrameDecoder::new(magic);
+    dec.push(&bad);
+    assert!(matches!(dec.next_message(), Err(FrameError::BadChecksum)));
+    assert!(dec.next_message().unwrap().is_some());
+
+    // command không khớp payload
+    let mut bad = frame.clone();
+    bad[4..9].copy_from_slice(b"tx\0\0\0");
+    assert!(matches!(read_message(&mut &bad[..], &magic), Err(FrameError::CommandMismatch(_))));
+
+    // sai magic / length quá lớn: fatal
+    match read_message(&mut &frame[..], &[0, 0, 0, 0]) {
+        Err(e @ FrameError::BadMagic(_)) => assert!(e.is_fatal()),
+        _ => panic!("bad magic accepted"),
+    }
+    let mut big = frame.clone();
+    big[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(matches!(read_message(&mut &big[..], &magic), Err(FrameError::Oversized(_))));
+    // frame cụt
+    assert!(matches!(read_message(&mut &frame[..frame.len() - 1], &magic), Err(FrameError::Io(_))));
+}
@@ -0,0 +1,76 @@
+use egg_node::p2p::sim::SimNetwork;
+
+const NODES: usize = 10;
+
+/// Các node trong `group` cùng tip `hash` ở `height`
+fn assert_tips(net: &SimNetwork, group: impl IntoIterator<Item = usize>, hash: [u8; 32], height: u64) {
+    for i in group {
+        assert_eq!(net.tip(i), (hash, height), "node {} did not converge", i);
+    }
+}
+
+/// Vòng tròn + dây cung: mỗi node 4 link, partition không cô lập node nào
+fn ring() -> SimNetwork {
+    let mut net = SimNetwork::new(NODES);
+    for i in 0..NODES {
+        net.connect(i, (i + 1) % NODES);
+        net.connect(i, (i + 3) % NODES);
+    }
+    net.run();
+    // handshake chạy qua frame trong `run`
+    for i in 0..NODES {
+        assert!(net.is_connected(i, (i + 1) % NODES) && net.is_connected(i, (i + 3) % NODES));
+    }
+    net
+}
+
+#[test]
+fn partition_and_heal() {
+    let mut net = ring();
+
+    // block lan ra cả mạng
+    let mut tip = [0u8; 32];
+    for _ in 0..5 {
+        tip = net.mine(0);
+    }
+    net.run();
+    assert_tips(&net, 0..NODES, tip, 5);
+
+    // chia mạng, mỗi bên mine chain riêng
+    let left: Vec<usize> = (0..5).collect();
+    let right: Vec<usize> = (5..NODES).collect();
+    net.partition(&[&left, &right]);
+
+    let mut tip_left = tip;
+    for _ in 0..3 {
+        tip_left = net.mine(0);
+    }
+    let mut tip_right = tip;
+    for _ in 0..5 {
+        tip_right = net.mine(7);
+    }
+    net.run();
+    assert_tips(&net, left.iter().copied(), tip_left, 8);
+    assert_tips(&net, right.iter().copied(), tip_right, 10);
+
+    // nối lại: chain dài hơn thắng, bên trái reorg
+    net.heal();
+    net.run();
+    assert!(net.converged());
+    assert_tips(&net, 0..NODES, tip_right, 10);
+
+    // bên trái mine tiếp trên chain mới
+    let tip = net.mine(2);
+    net.run();
+    assert_tips(&net, 0..NODES, tip, 11);
+}
+
+#[test]
+fn self_connection_fails_handshake() {
+    let mut net = SimNetwork::new(2);
+    net.connect(0, 0);
+    net.connect(0, 1);
+    net.run();
+    assert!(!net.is_connected(0, 0));
+    assert!(net.is_connected(0, 1));
+}
@@ -0,0 +1,95 @@
+use egg_node::chain::genesis_block;
+use egg_node::chain::hash::hash_header;
+use egg_node::chain::utxo::UTXO;
+use egg_node::storage::sleddb::ChainDB;
+
+/// sled nhả file lock trong thread nền sau khi drop, nên mở lại phải chờ
+fn reopen(path: &str) -> ChainDB {
+    for _ in 0..100 {
+        if let Ok(db) = ChainDB::try_open(path) {
+            return db;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    panic!("db still locked");
+}
+
+#[test]
+fn uncommitted_batch_is_not_persisted() {
+    let dir = std::env::temp_dir().join(format!("egg-batch-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let path = dir.to_str().unwrap();
+
+    let db = ChainDB::open(path);
+    db.set_tip(&[1; 32], 1);
+
+    db.begin_batch();
+    db.set_tip(&[2; 32], 2);
+    db.set_pruned_height(7);
+    // trong batch đọc thấy giá trị đang gom
+    assert_eq!(db.get_tip(), Some(([2; 32], 2)));
+    assert_eq!(db.pruned_height(), Some(7));
+    db.flush();
+    drop(db);
+
+    // chưa commit: cả 2 ghi cùng mất
+    let db = reopen(path);
+    assert_eq!(db.get_tip(), Some(([1; 32], 1)));
+    assert_eq!(db.pruned_height(), None);
+
+    db.begin_batch();
+    db.set_tip(&[3; 32], 3);
+    db.set_pruned_height(7);
+    db.commit_batch();
+    db.flush();
+    drop(db);
+
+    let db = reopen(path);
+    assert_eq!(db.get_tip(), Some(([3; 32], 3)));
+    assert_eq!(db.pruned_height(), Some(7));
+    drop(db);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Block còn lại, (vout, value) của UTXO, stored_bytes, tip
+type Readback = (Vec<[u8; 32]>, Vec<(u32, u64)>, u64, Option<([u8; 32], u64)>);
+
+/// Cùng chuỗi thao tác, trả về những gì đọc lại được
+fn exercise(db: &ChainDB) -> Readback {
+    let block = genesis_block();
+    let first = db.put_block(&block);
+    let second = db.put_block(&block);
+    assert!(second > first);
+    db.delete_block(first);
+
+    db.begin_batch();
+    for vout in 0..4 {
+        db.put_utxo(&UTXO { txid: [7; 32], vout, value: vout as u64 * 10, address: vec![1], height: 1 });
+    }
+    db.delete_utxo(&[7; 32], 1);
+    db.set_tip(&[5; 32], 9);
+    db.commit_batch();
+
+    let blocks = db
+        .block_positions()
+        .into_iter()
+        .map(|pos| hash_header(&db.get_block(pos).unwrap().unwrap().header))
+        .collect();
+    let utxos = db.iter_utxos().iter().map(|u| (u.vout, u.value)).collect();
+    (blocks, utxos, db.stored_bytes(), db.get_tip())
+}
+
+#[test]
+fn memory_backend_matches_sled() {
+    let dir = std::env::temp_dir().join(format!("egg-backend-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let on_disk = exercise(&ChainDB::open(dir.to_str().unwrap()));
+    let in_memory = exercise(&ChainDB::open_temporary());
+    assert_eq!(on_disk.0, vec![hash_header(&genesis_block().header)]);
+    assert_eq!(on_disk.1, vec![(0, 0), (2, 20), (3, 30)]);
+    assert_eq!(on_disk, in_memory);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}